@@ -1,4 +1,10 @@
-import {get_battery_state_of_charge} from "./env";
+import {get_battery_state_of_charge, get_setting_i64} from "./env";
+
+// Settings are declared in transform.ts and validated by the host
+function getSetting(key: string): i32 {
+    const buffer = String.UTF8.encode(key)
+    return get_setting_i64(changetype<usize>(buffer), buffer.byteLength) as i32
+}
 
 export function add(a: i32, b: i32): ArrayBuffer {
     const arr: ArrayBuffer = new ArrayBuffer(305)
@@ -21,12 +27,14 @@ const PICTURE_LEN: i32 = HEIGHT * WIDTH;
 export function draw(): ArrayBuffer {
 
     const state_of_charge = get_battery_state_of_charge()
+    const litValue = getSetting("lit_value") as u8
+    const dimValue = getSetting("dim_value") as u8
     const arr: ArrayBuffer = new ArrayBuffer(PICTURE_LEN)
     const view = new DataView(arr)
     for (let i = PICTURE_LEN - 1; i >= 0; i--) {
         let current_row = floor(i / WIDTH)
         const isLit = isRowLit(current_row, state_of_charge)
-        view.setUint8(PICTURE_LEN - 1 - i, isLit ? 255 : 10)
+        view.setUint8(PICTURE_LEN - 1 - i, isLit ? litValue : dimValue)
     }
     return arr
 }
@@ -8,6 +8,16 @@ export default class CustomSectionTransform extends Transform {
       name: "battery",
       width: 5,
       height: 10,
+      abi_version: 1,
+      version: "1.0.0",
+      description: "Display battery charge as a vertical bar",
+      permissions: ["battery"],
+      // Battery charge changes slowly, no need to query it every frame
+      refresh_ms: 5000,
+      settings_schema: {
+        lit_value: {type: "integer", default: 255, min: 0, max: 255},
+        dim_value: {type: "integer", default: 10, min: 0, max: 255},
+      },
     };
 
     // Encode to UTF-8. (Binary data would work too—just provide a Uint8Array.)
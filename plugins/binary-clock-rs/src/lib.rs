@@ -0,0 +1,24 @@
+use fw_led_plugin_sdk::{host, plugin, Frame};
+
+const LIT: u8 = 255;
+const DIM: u8 = 10;
+
+// UTC hours, minutes and seconds as binary rows, most significant bit first
+#[plugin(
+    name = "binary-clock-rs",
+    width = 6,
+    height = 3,
+    permissions = ["time"],
+    description = "UTC time as binary hours, minutes and seconds rows"
+)]
+fn draw(frame: &mut Frame<6, 3>) {
+    let seconds_of_day = host::epoch_time() % 86_400;
+    let rows = [seconds_of_day / 3600, seconds_of_day / 60 % 60, seconds_of_day % 60];
+
+    for (row, value) in rows.into_iter().enumerate() {
+        for column in 0..6 {
+            let bit = value >> (5 - column) & 1;
+            frame.set(column, row, if bit == 1 { LIT } else { DIM });
+        }
+    }
+}
@@ -1 +1,2 @@
-export declare function get_global_cpu_usage(): f32
\ No newline at end of file
+export declare function get_global_cpu_usage(): f32
+export declare function get_setting_i64(key_ptr: usize, key_len: i32): i64
@@ -7,6 +7,14 @@ export default class CustomSectionTransform extends Transform {
       name: "cpu",
       width: 2,
       height: 10,
+      abi_version: 1,
+      version: "1.0.0",
+      description: "Display global CPU usage as a vertical bar",
+      permissions: ["cpu"],
+      settings_schema: {
+        lit_value: {type: "integer", default: 255, min: 0, max: 255},
+        dim_value: {type: "integer", default: 10, min: 0, max: 255},
+      },
     };
 
     const payload = new TextEncoder().encode(JSON.stringify(meta));
@@ -3,7 +3,13 @@
 
 // import {logInteger} from "./env";
 
-import {get_memory_usage} from "./env";
+import {get_memory_usage, get_setting_i64} from "./env";
+
+// Settings are declared in transform.ts and validated by the host
+function getSetting(key: string): i32 {
+    const buffer = String.UTF8.encode(key)
+    return get_setting_i64(changetype<usize>(buffer), buffer.byteLength) as i32
+}
 
 
 function isRowLit(row: i32, memory_usage: f32): boolean {
@@ -17,13 +23,15 @@ const WIDTH: i32 = 2;
 export function draw(): ArrayBuffer {
 
     const memory_usage = get_memory_usage()
+    const litValue = getSetting("lit_value") as u8
+    const dimValue = getSetting("dim_value") as u8
     const arr: ArrayBuffer = new ArrayBuffer(PICTURE_LEN)
     const view = new DataView(arr)
     for (let i = PICTURE_LEN - 1; i >= 0; i--) {
         // const val: u8 = Math.floor(Math.random() * 255)
         let current_row = floor(i / WIDTH)
         const isLit = isRowLit(current_row, memory_usage)
-        view.setUint8(PICTURE_LEN - 1 - i, isLit ? 255 : 10)
+        view.setUint8(PICTURE_LEN - 1 - i, isLit ? litValue : dimValue)
     }
     return arr
 }
@@ -8,6 +8,14 @@ export default class CustomSectionTransform extends Transform {
       name: "memory",
       width: 2,
       height: 10,
+      abi_version: 1,
+      version: "1.0.0",
+      description: "Display memory usage as a vertical bar",
+      permissions: ["memory"],
+      settings_schema: {
+        lit_value: {type: "integer", default: 255, min: 0, max: 255},
+        dim_value: {type: "integer", default: 10, min: 0, max: 255},
+      },
     };
 
     // Encode to UTF-8. (Binary data would work too—just provide a Uint8Array.)
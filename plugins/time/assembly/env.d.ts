@@ -1 +1,2 @@
-export declare function get_epoch_time(): i64
\ No newline at end of file
+export declare function get_epoch_time(): i64
+export declare function get_setting_i64(key_ptr: usize, key_len: i32): i64
@@ -3,7 +3,13 @@
 
 // import {logInteger} from "./env";
 
-import {get_epoch_time} from "./env";
+import {get_epoch_time, get_setting_i64} from "./env";
+
+// Settings are declared in transform.ts and validated by the host
+function getSetting(key: string): i32 {
+    const buffer = String.UTF8.encode(key)
+    return get_setting_i64(changetype<usize>(buffer), buffer.byteLength) as i32
+}
 
 const HEIGHT: i32 = 4;
 const WIDTH: i32 = 8;
@@ -14,8 +20,9 @@ function createIndex(row: i32, col: i32): i32 {
     return row * WIDTH + col
 }
 
-const LIT_VAL = 255;
-const DIM_VAL = 10;
+// Read from settings on every draw
+let LIT_VAL: i32 = 255;
+let DIM_VAL: i32 = 10;
 
 
 function formatHours(date: Date): Array<i32> {
@@ -36,9 +43,9 @@ function formatHours(date: Date): Array<i32> {
     const secondDecimalDigit: i32 = floor(hour / 10);
 
     if (secondDecimalDigit == 2) {
-        arr[createIndex(2, 0)] = 255
+        arr[createIndex(2, 0)] = LIT_VAL
     } else if (secondDecimalDigit == 1) {
-        arr[createIndex(3, 0)] = 255
+        arr[createIndex(3, 0)] = LIT_VAL
     }
 
     const FIRST_DECIMAL_INDICES: Array<i32> = [createIndex(0, 1), createIndex(1, 1), createIndex(2, 1), createIndex(3, 1)]
@@ -123,7 +130,9 @@ function formatSeconds(date: Date): Array<i32> {
 }
 
 export function draw(): ArrayBuffer {
-    const epochTime = get_epoch_time()
+    LIT_VAL = getSetting("lit_value")
+    DIM_VAL = getSetting("dim_value")
+    const epochTime = get_epoch_time() + (getSetting("utc_offset_minutes") as i64) * 60
 
     const date = new Date(epochTime * 1000)
     const arr: ArrayBuffer = new ArrayBuffer(PICTURE_LEN)
@@ -8,6 +8,15 @@ export default class CustomSectionTransform extends Transform {
       name: "time",
       width: 8,
       height: 4,
+      abi_version: 1,
+      version: "1.0.0",
+      description: "Display GMT time in a binary clock format",
+      permissions: ["time"],
+      settings_schema: {
+        lit_value: {type: "integer", default: 255, min: 0, max: 255},
+        dim_value: {type: "integer", default: 10, min: 0, max: 255},
+        utc_offset_minutes: {type: "integer", default: 0, min: -720, max: 840},
+      },
     };
 
     // Encode to UTF-8. (Binary data would work too—just provide a Uint8Array.)
@@ -0,0 +1,159 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::punctuated::Punctuated;
+use syn::{bracketed, parse_macro_input, ItemFn, LitInt, LitStr, Token};
+
+// Size of the LED matrix, plugins can't be larger than that
+const MATRIX_WIDTH: u32 = 9;
+const MATRIX_HEIGHT: u32 = 34;
+
+// Host ABI version the SDK imports and exports follow, same as `fw_led_plugin_sdk::ABI_VERSION`
+const ABI_VERSION: u32 = 1;
+
+#[derive(Default)]
+struct PluginArgs {
+    name: Option<LitStr>,
+    width: Option<LitInt>,
+    height: Option<LitInt>,
+    version: Option<LitStr>,
+    author: Option<LitStr>,
+    description: Option<LitStr>,
+    refresh_ms: Option<LitInt>,
+    permissions: Vec<LitStr>,
+}
+
+/// Turns `fn(&mut Frame<WIDTH, HEIGHT>)` into a plugin: emits the `metadata` custom section and a `draw`
+/// export calling the function with a frame that persists between draws.
+///
+/// ```ignore
+/// #[plugin(name = "blink", width = 2, height = 1, permissions = ["time"])]
+/// fn draw(frame: &mut Frame<2, 1>) {
+///     frame.fill(if host::epoch_time() % 2 == 0 { 255 } else { 0 });
+/// }
+/// ```
+///
+/// `name`, `width` and `height` are required. `version` defaults to the crate version, `author`,
+/// `description`, `refresh_ms` and `permissions` are optional.
+#[proc_macro_attribute]
+pub fn plugin(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut args = PluginArgs::default();
+    let parser = syn::meta::parser(|meta| {
+        let ident = meta.path.get_ident().map(|ident| ident.to_string()).unwrap_or_default();
+        match ident.as_str() {
+            "name" => args.name = Some(meta.value()?.parse()?),
+            "width" => args.width = Some(meta.value()?.parse()?),
+            "height" => args.height = Some(meta.value()?.parse()?),
+            "version" => args.version = Some(meta.value()?.parse()?),
+            "author" => args.author = Some(meta.value()?.parse()?),
+            "description" => args.description = Some(meta.value()?.parse()?),
+            "refresh_ms" => args.refresh_ms = Some(meta.value()?.parse()?),
+            "permissions" => {
+                let value = meta.value()?;
+                let content;
+                bracketed!(content in value);
+                args.permissions = Punctuated::<LitStr, Token![,]>::parse_terminated(&content)?
+                    .into_iter()
+                    .collect();
+            }
+            _ => return Err(meta.error("unsupported plugin property")),
+        }
+        Ok(())
+    });
+    parse_macro_input!(attr with parser);
+    let draw = parse_macro_input!(item as ItemFn);
+
+    match expand(args, draw) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(args: PluginArgs, draw: ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let missing = |property: &str| {
+        syn::Error::new(Span::call_site(), format!("missing `{}` plugin property", property))
+    };
+    let name = args.name.ok_or_else(|| missing("name"))?;
+    let width = dimension(args.width.ok_or_else(|| missing("width"))?, MATRIX_WIDTH)?;
+    let height = dimension(args.height.ok_or_else(|| missing("height"))?, MATRIX_HEIGHT)?;
+    if name.value().is_empty() {
+        return Err(syn::Error::new(name.span(), "plugin name can't be empty"));
+    }
+
+    let version = args
+        .version
+        .map(|version| version.value())
+        .or_else(|| std::env::var("CARGO_PKG_VERSION").ok());
+    let mut fields = vec![
+        format!("\"name\":{}", json_string(&name.value())),
+        format!("\"width\":{}", width),
+        format!("\"height\":{}", height),
+        format!("\"abi_version\":{}", ABI_VERSION),
+    ];
+    let optional_strings = [
+        ("version", version),
+        ("author", args.author.map(|author| author.value())),
+        ("description", args.description.map(|description| description.value())),
+    ];
+    for (key, value) in optional_strings {
+        if let Some(value) = value {
+            fields.push(format!("\"{}\":{}", key, json_string(&value)));
+        }
+    }
+    if let Some(refresh_ms) = args.refresh_ms {
+        fields.push(format!("\"refresh_ms\":{}", refresh_ms.base10_parse::<u64>()?));
+    }
+    let permissions = args
+        .permissions
+        .iter()
+        .map(|permission| json_string(&permission.value()))
+        .collect::<Vec<String>>();
+    fields.push(format!("\"permissions\":[{}]", permissions.join(",")));
+
+    let metadata = format!("{{{}}}", fields.join(","));
+    let metadata_bytes = syn::LitByteStr::new(metadata.as_bytes(), Span::call_site());
+    let metadata_len = metadata.len();
+    let draw_name = &draw.sig.ident;
+
+    Ok(quote! {
+        #draw
+
+        #[doc(hidden)]
+        #[unsafe(link_section = "metadata")]
+        #[used]
+        pub static __FW_LED_PLUGIN_METADATA: [u8; #metadata_len] = *#metadata_bytes;
+
+        #[doc(hidden)]
+        #[unsafe(export_name = "draw")]
+        pub extern "C" fn __fw_led_plugin_draw() -> *const u8 {
+            static FRAME: ::fw_led_plugin_sdk::__private::FrameCell<#width, #height> =
+                ::fw_led_plugin_sdk::__private::FrameCell::new();
+            // Plugins are single-threaded and the host doesn't call `draw` re-entrantly
+            let frame = unsafe { FRAME.get_mut() };
+            #draw_name(frame);
+            frame.as_bytes().as_ptr()
+        }
+    })
+}
+
+fn dimension(value: LitInt, max: u32) -> syn::Result<usize> {
+    let parsed = value.base10_parse::<u32>()?;
+    if parsed == 0 || parsed > max {
+        return Err(syn::Error::new(value.span(), format!("should be between 1 and {}", max)));
+    }
+    Ok(parsed as usize)
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for char in value.chars() {
+        match char {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            char if char.is_control() => escaped.push_str(&format!("\\u{:04x}", char as u32)),
+            char => escaped.push(char),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
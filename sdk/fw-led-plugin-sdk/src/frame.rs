@@ -0,0 +1,65 @@
+/// Pixels of a plugin, `WIDTH` columns by `HEIGHT` rows, each 0-255 brightness. Stored row-major,
+/// which is the layout the host reads `draw` results in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame<const WIDTH: usize, const HEIGHT: usize> {
+    pixels: [[u8; WIDTH]; HEIGHT],
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Frame<WIDTH, HEIGHT> {
+    pub const WIDTH: usize = WIDTH;
+    pub const HEIGHT: usize = HEIGHT;
+
+    pub const fn new() -> Self {
+        Self {
+            pixels: [[0; WIDTH]; HEIGHT],
+        }
+    }
+
+    /// Panics when `column` or `row` is out of bounds
+    pub fn set(&mut self, column: usize, row: usize, value: u8) {
+        self.pixels[row][column] = value;
+    }
+
+    /// Panics when `column` or `row` is out of bounds
+    pub fn get(&self, column: usize, row: usize) -> u8 {
+        self.pixels[row][column]
+    }
+
+    pub fn fill(&mut self, value: u8) {
+        self.pixels = [[value; WIDTH]; HEIGHT];
+    }
+
+    pub fn clear(&mut self) {
+        self.fill(0);
+    }
+
+    pub fn row_mut(&mut self, row: usize) -> &mut [u8; WIDTH] {
+        &mut self.pixels[row]
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.pixels.as_flattened()
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Default for Frame<WIDTH, HEIGHT> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod frame_tests {
+    use crate::Frame;
+
+    #[test]
+    fn lays_out_pixels_row_major() {
+        let mut frame = Frame::<3, 2>::new();
+
+        frame.set(1, 0, 10);
+        frame.set(0, 1, 20);
+        frame.row_mut(1)[2] = 30;
+
+        assert_eq!(frame.as_bytes(), [0, 10, 0, 20, 0, 30]);
+    }
+}
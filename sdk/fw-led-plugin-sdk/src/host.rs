@@ -0,0 +1,131 @@
+//! Safe wrappers for the functions the host provides. Stat functions need the matching capability in
+//! `permissions`, only the functions a plugin calls end up imported. Outside WebAssembly they are served by
+//! [`crate::testing`].
+
+#[cfg(target_arch = "wasm32")]
+mod ffi {
+    #[link(wasm_import_module = "env")]
+    extern "C" {
+        pub fn get_global_cpu_usage() -> f32;
+        pub fn get_memory_usage() -> f32;
+        pub fn get_battery_state_of_charge() -> f32;
+        pub fn get_epoch_time() -> u64;
+        pub fn log(level: i32, ptr: *const u8, len: i32);
+        pub fn get_setting_i64(key_ptr: *const u8, key_len: i32) -> i64;
+        pub fn get_setting_f64(key_ptr: *const u8, key_len: i32) -> f64;
+        pub fn get_setting_string(key_ptr: *const u8, key_len: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
+        pub fn get_asset(name_ptr: *const u8, name_len: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
+        pub fn kv_get_i64(key_ptr: *const u8, key_len: i32) -> i64;
+        pub fn kv_get_f64(key_ptr: *const u8, key_len: i32) -> f64;
+        pub fn kv_get_string(key_ptr: *const u8, key_len: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
+        pub fn kv_set_i64(key_ptr: *const u8, key_len: i32, value: i64);
+        pub fn kv_set_f64(key_ptr: *const u8, key_len: i32, value: f64);
+        pub fn kv_set_string(key_ptr: *const u8, key_len: i32, value_ptr: *const u8, value_len: i32);
+    }
+}
+
+// Native builds, e.g. `cargo test`, talk to the mock host instead
+#[cfg(not(target_arch = "wasm32"))]
+use crate::testing::ffi;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+    Trace = 4,
+}
+
+/// Global CPU usage in percent, needs the `cpu` capability
+pub fn global_cpu_usage() -> f32 {
+    unsafe { ffi::get_global_cpu_usage() }
+}
+
+/// Used memory as a 0-1 ratio, needs the `memory` capability
+pub fn memory_usage() -> f32 {
+    unsafe { ffi::get_memory_usage() }
+}
+
+/// Battery charge as a 0-1 ratio, needs the `battery` capability
+pub fn battery_state_of_charge() -> f32 {
+    unsafe { ffi::get_battery_state_of_charge() }
+}
+
+/// Seconds since the Unix epoch, needs the `time` capability
+pub fn epoch_time() -> u64 {
+    unsafe { ffi::get_epoch_time() }
+}
+
+/// Logged by the daemon under the `plugin::<name>` target
+pub fn log(level: Level, message: &str) {
+    unsafe { ffi::log(level as i32, message.as_ptr(), message.len() as i32) }
+}
+
+/// 0 when the setting is missing
+pub fn setting_i64(key: &str) -> i64 {
+    unsafe { ffi::get_setting_i64(key.as_ptr(), key.len() as i32) }
+}
+
+/// 0.0 when the setting is missing
+pub fn setting_f64(key: &str) -> f64 {
+    unsafe { ffi::get_setting_f64(key.as_ptr(), key.len() as i32) }
+}
+
+pub fn setting_string(key: &str) -> Option<String> {
+    read_bytes(|buf_ptr, buf_len| unsafe {
+        ffi::get_setting_string(key.as_ptr(), key.len() as i32, buf_ptr, buf_len)
+    })
+    .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// File from the `assets` directory of the plugin bundle
+pub fn asset(name: &str) -> Option<Vec<u8>> {
+    read_bytes(|buf_ptr, buf_len| unsafe {
+        ffi::get_asset(name.as_ptr(), name.len() as i32, buf_ptr, buf_len)
+    })
+}
+
+/// Reads take the full `<namespace>.<name>` key, 0 when missing
+pub fn kv_get_i64(key: &str) -> i64 {
+    unsafe { ffi::kv_get_i64(key.as_ptr(), key.len() as i32) }
+}
+
+/// 0.0 when missing
+pub fn kv_get_f64(key: &str) -> f64 {
+    unsafe { ffi::kv_get_f64(key.as_ptr(), key.len() as i32) }
+}
+
+pub fn kv_get_string(key: &str) -> Option<String> {
+    read_bytes(|buf_ptr, buf_len| unsafe {
+        ffi::kv_get_string(key.as_ptr(), key.len() as i32, buf_ptr, buf_len)
+    })
+    .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Writes go to the namespace of the plugin instance, `key` is the name within it
+pub fn kv_set_i64(key: &str, value: i64) {
+    unsafe { ffi::kv_set_i64(key.as_ptr(), key.len() as i32, value) }
+}
+
+pub fn kv_set_f64(key: &str, value: f64) {
+    unsafe { ffi::kv_set_f64(key.as_ptr(), key.len() as i32, value) }
+}
+
+pub fn kv_set_string(key: &str, value: &str) {
+    unsafe { ffi::kv_set_string(key.as_ptr(), key.len() as i32, value.as_ptr(), value.len() as i32) }
+}
+
+// Host functions copying into a buffer return the full length, or -1 when missing.
+// The first call asks for the length only.
+fn read_bytes(read: impl Fn(*mut u8, i32) -> i32) -> Option<Vec<u8>> {
+    let len = read(std::ptr::null_mut(), 0);
+    if len < 0 {
+        return None;
+    }
+
+    let mut buffer = vec![0u8; len as usize];
+    let copied = read(buffer.as_mut_ptr(), len);
+    buffer.truncate(copied.clamp(0, len) as usize);
+    Some(buffer)
+}
@@ -0,0 +1,89 @@
+//! Write fw-led-stat-control plugins in Rust.
+//!
+//! ```ignore
+//! use fw_led_plugin_sdk::{host, plugin, Frame};
+//!
+//! #[plugin(name = "seconds", width = 6, height = 1, permissions = ["time"])]
+//! fn draw(frame: &mut Frame<6, 1>) {
+//!     let seconds = host::epoch_time() % 60;
+//!     for column in 0..6 {
+//!         frame.set(column, 0, if seconds >> (5 - column) & 1 == 1 { 255 } else { 0 });
+//!     }
+//! }
+//! ```
+//!
+//! Build with `cargo build --release --target wasm32-unknown-unknown` as a `cdylib`.
+
+// Lets macro-generated `::fw_led_plugin_sdk` paths resolve in this crate's own tests
+extern crate self as fw_led_plugin_sdk;
+
+mod frame;
+pub mod host;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod testing;
+
+pub use fw_led_plugin_sdk_macros::plugin;
+
+pub use crate::frame::Frame;
+
+/// Host ABI version plugins built with this SDK declare
+pub const ABI_VERSION: u32 = 1;
+
+#[doc(hidden)]
+pub mod __private {
+    use std::cell::UnsafeCell;
+
+    use crate::Frame;
+
+    // Storage for the frame of the generated `draw` export
+    pub struct FrameCell<const WIDTH: usize, const HEIGHT: usize>(UnsafeCell<Frame<WIDTH, HEIGHT>>);
+
+    impl<const WIDTH: usize, const HEIGHT: usize> Default for FrameCell<WIDTH, HEIGHT> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    // WebAssembly plugins are single-threaded
+    unsafe impl<const WIDTH: usize, const HEIGHT: usize> Sync for FrameCell<WIDTH, HEIGHT> {}
+
+    impl<const WIDTH: usize, const HEIGHT: usize> FrameCell<WIDTH, HEIGHT> {
+        pub const fn new() -> Self {
+            Self(UnsafeCell::new(Frame::new()))
+        }
+
+        /// # Safety
+        /// No other reference to the frame may be alive
+        #[allow(clippy::mut_from_ref)]
+        pub unsafe fn get_mut(&self) -> &mut Frame<WIDTH, HEIGHT> {
+            &mut *self.0.get()
+        }
+    }
+}
+
+#[cfg(test)]
+mod sdk_tests {
+    use crate::{plugin, Frame};
+
+    #[plugin(name = "test \"plugin\"", width = 3, height = 2, refresh_ms = 500, permissions = ["cpu", "time"])]
+    fn draw(frame: &mut Frame<3, 2>) {
+        let lit = frame.get(0, 0).wrapping_add(1);
+        frame.set(0, 0, lit);
+        frame.set(2, 1, 255);
+    }
+
+    #[test]
+    fn emits_metadata_and_draw_export() {
+        let metadata = std::str::from_utf8(&__FW_LED_PLUGIN_METADATA).unwrap();
+        assert_eq!(
+            metadata,
+            r#"{"name":"test \"plugin\"","width":3,"height":2,"abi_version":1,"version":"0.1.0","refresh_ms":500,"permissions":["cpu","time"]}"#
+        );
+
+        __fw_led_plugin_draw();
+        let pixels = __fw_led_plugin_draw();
+
+        let pixels = unsafe { std::slice::from_raw_parts(pixels, 6) };
+        assert_eq!(pixels, [2, 0, 0, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,192 @@
+//! Stand-in for the host when a plugin is built natively, so it can be unit tested with `cargo test`.
+//! Every thread gets its own [`MockHost`].
+//!
+//! ```ignore
+//! testing::set_host(MockHost { global_cpu_usage: 80.0, ..MockHost::default() });
+//! let mut frame = Frame::<2, 10>::new();
+//! draw(&mut frame);
+//! assert_eq!(testing::render(&frame), "##\n##\n...");
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::Frame;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+/// Values host functions return. Missing settings, keys and assets read the way they do in the daemon.
+#[derive(Debug, Clone, Default)]
+pub struct MockHost {
+    pub global_cpu_usage: f32,
+    pub memory_usage: f32,
+    pub battery_state_of_charge: f32,
+    pub epoch_time: u64,
+    pub settings: HashMap<String, Value>,
+    /// Full `<namespace>.<name>` keys, writes from the plugin go to the `plugin` namespace
+    pub kv: HashMap<String, Value>,
+    pub assets: HashMap<String, Vec<u8>>,
+}
+
+thread_local! {
+    static HOST: RefCell<MockHost> = RefCell::new(MockHost::default());
+}
+
+pub fn set_host(host: MockHost) {
+    HOST.with(|current| *current.borrow_mut() = host);
+}
+
+pub fn with_host<R>(update: impl FnOnce(&mut MockHost) -> R) -> R {
+    HOST.with(|host| update(&mut host.borrow_mut()))
+}
+
+/// Rows of `#` for lit and `.` for dark pixels, the way the daemon prints frames
+pub fn render<const WIDTH: usize, const HEIGHT: usize>(frame: &Frame<WIDTH, HEIGHT>) -> String {
+    (0..HEIGHT)
+        .map(|row| {
+            (0..WIDTH)
+                .map(|column| if frame.get(column, row) > 0 { '#' } else { '.' })
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Same signatures as the WebAssembly imports
+pub(crate) mod ffi {
+    use crate::testing::{with_host, Value};
+
+    unsafe fn read_str<'a>(ptr: *const u8, len: i32) -> &'a str {
+        std::str::from_utf8(std::slice::from_raw_parts(ptr, len.max(0) as usize)).unwrap_or_default()
+    }
+
+    unsafe fn copy_out(bytes: &[u8], buf_ptr: *mut u8, buf_len: i32) -> i32 {
+        let len = bytes.len().min(buf_len.max(0) as usize);
+        if len > 0 {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), buf_ptr, len);
+        }
+        bytes.len() as i32
+    }
+
+    fn number(value: Option<&Value>) -> f64 {
+        match value {
+            Some(Value::Integer(value)) => *value as f64,
+            Some(Value::Float(value)) => *value,
+            _ => 0.0,
+        }
+    }
+
+    unsafe fn string(value: Option<&Value>, buf_ptr: *mut u8, buf_len: i32) -> i32 {
+        match value {
+            Some(Value::String(value)) => copy_out(value.as_bytes(), buf_ptr, buf_len),
+            _ => -1,
+        }
+    }
+
+    pub unsafe fn get_global_cpu_usage() -> f32 {
+        with_host(|host| host.global_cpu_usage)
+    }
+
+    pub unsafe fn get_memory_usage() -> f32 {
+        with_host(|host| host.memory_usage)
+    }
+
+    pub unsafe fn get_battery_state_of_charge() -> f32 {
+        with_host(|host| host.battery_state_of_charge)
+    }
+
+    pub unsafe fn get_epoch_time() -> u64 {
+        with_host(|host| host.epoch_time)
+    }
+
+    pub unsafe fn log(level: i32, ptr: *const u8, len: i32) {
+        eprintln!("[plugin log {}] {}", level, read_str(ptr, len));
+    }
+
+    pub unsafe fn get_setting_i64(key_ptr: *const u8, key_len: i32) -> i64 {
+        with_host(|host| number(host.settings.get(read_str(key_ptr, key_len))) as i64)
+    }
+
+    pub unsafe fn get_setting_f64(key_ptr: *const u8, key_len: i32) -> f64 {
+        with_host(|host| number(host.settings.get(read_str(key_ptr, key_len))))
+    }
+
+    pub unsafe fn get_setting_string(key_ptr: *const u8, key_len: i32, buf_ptr: *mut u8, buf_len: i32) -> i32 {
+        with_host(|host| string(host.settings.get(read_str(key_ptr, key_len)), buf_ptr, buf_len))
+    }
+
+    pub unsafe fn get_asset(name_ptr: *const u8, name_len: i32, buf_ptr: *mut u8, buf_len: i32) -> i32 {
+        with_host(|host| match host.assets.get(read_str(name_ptr, name_len)) {
+            Some(asset) => copy_out(asset, buf_ptr, buf_len),
+            None => -1,
+        })
+    }
+
+    pub unsafe fn kv_get_i64(key_ptr: *const u8, key_len: i32) -> i64 {
+        with_host(|host| number(host.kv.get(read_str(key_ptr, key_len))) as i64)
+    }
+
+    pub unsafe fn kv_get_f64(key_ptr: *const u8, key_len: i32) -> f64 {
+        with_host(|host| number(host.kv.get(read_str(key_ptr, key_len))))
+    }
+
+    pub unsafe fn kv_get_string(key_ptr: *const u8, key_len: i32, buf_ptr: *mut u8, buf_len: i32) -> i32 {
+        with_host(|host| string(host.kv.get(read_str(key_ptr, key_len)), buf_ptr, buf_len))
+    }
+
+    unsafe fn kv_set(key_ptr: *const u8, key_len: i32, value: Value) {
+        let key = format!("plugin.{}", read_str(key_ptr, key_len));
+        with_host(|host| host.kv.insert(key, value));
+    }
+
+    pub unsafe fn kv_set_i64(key_ptr: *const u8, key_len: i32, value: i64) {
+        kv_set(key_ptr, key_len, Value::Integer(value));
+    }
+
+    pub unsafe fn kv_set_f64(key_ptr: *const u8, key_len: i32, value: f64) {
+        kv_set(key_ptr, key_len, Value::Float(value));
+    }
+
+    pub unsafe fn kv_set_string(key_ptr: *const u8, key_len: i32, value_ptr: *const u8, value_len: i32) {
+        kv_set(key_ptr, key_len, Value::String(read_str(value_ptr, value_len).to_string()));
+    }
+}
+
+#[cfg(test)]
+mod testing_tests {
+    use std::collections::HashMap;
+
+    use crate::host;
+    use crate::testing::{render, set_host, with_host, MockHost, Value};
+    use crate::Frame;
+
+    #[test]
+    fn serves_host_functions_from_mock() {
+        set_host(MockHost {
+            global_cpu_usage: 42.0,
+            settings: HashMap::from([("label".to_string(), Value::String("cpu".to_string()))]),
+            ..MockHost::default()
+        });
+
+        host::kv_set_i64("load", 7);
+
+        assert_eq!(host::global_cpu_usage(), 42.0);
+        assert_eq!(host::setting_string("label").as_deref(), Some("cpu"));
+        assert_eq!(host::setting_string("missing"), None);
+        assert_eq!(with_host(|host| host.kv.get("plugin.load").cloned()), Some(Value::Integer(7)));
+    }
+
+    #[test]
+    fn renders_lit_pixels() {
+        let mut frame = Frame::<3, 2>::new();
+        frame.set(0, 0, 1);
+        frame.set(2, 1, 255);
+
+        assert_eq!(render(&frame), "#..\n..#");
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fmt::{Display, Formatter, Write as _};
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use wasmer::FunctionEnvMut;
+
+use crate::plugin_log::read_utf8;
+use crate::settings::SettingsSchema;
+use crate::wasm_module::PluginEnv;
+
+pub const BUNDLE_EXTENSION: &str = "fwled";
+const MANIFEST_FILE: &str = "manifest.toml";
+const ASSETS_DIR: &str = "assets";
+
+// `manifest.toml` at the root of a bundle
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Manifest {
+    pub name: String,
+    // Path of the WASM module inside the bundle
+    #[serde(default = "default_module")]
+    pub module: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    // Takes precedence over the schema from the module metadata
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settings_schema: Option<SettingsSchema>,
+    // Hex encoded SHA-256 of every other file in the bundle, filled in by `bundle build`
+    #[serde(default)]
+    pub checksums: BTreeMap<String, String>,
+}
+
+fn default_module() -> String {
+    "plugin.wasm".to_string()
+}
+
+// Single file plugin: a tar archive with a manifest, the module and optional assets
+pub struct Bundle {
+    pub manifest: Manifest,
+    pub module: Vec<u8>,
+    // Files under `assets/`, keyed by path relative to it
+    pub assets: HashMap<String, Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BundleError {
+    Io(String),
+    MissingManifest,
+    InvalidManifest(String),
+    InvalidPath(String),
+    MissingFile(String),
+    UnlistedFile(String),
+    ChecksumMismatch(String),
+}
+
+impl Display for BundleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BundleError::Io(err) => write!(f, "{}", err),
+            BundleError::MissingManifest => write!(f, "bundle has no {}", MANIFEST_FILE),
+            BundleError::InvalidManifest(err) => write!(f, "invalid {}: {}", MANIFEST_FILE, err),
+            BundleError::InvalidPath(path) => write!(f, "invalid path '{}' in bundle", path),
+            BundleError::MissingFile(path) => write!(f, "'{}' listed in the manifest is missing", path),
+            BundleError::UnlistedFile(path) => write!(f, "'{}' has no checksum in the manifest", path),
+            BundleError::ChecksumMismatch(path) => write!(f, "checksum mismatch for '{}'", path),
+        }
+    }
+}
+
+impl From<std::io::Error> for BundleError {
+    fn from(err: std::io::Error) -> Self {
+        BundleError::Io(err.to_string())
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
+
+// Read a bundle and verify the checksums of all of its files
+pub fn read_bundle(path: &Path) -> Result<Bundle, BundleError> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    let mut files = BTreeMap::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        // Files are only ever kept in memory, reject anything that isn't a plain relative path anyway
+        if !path.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(BundleError::InvalidPath(path.display().to_string()));
+        }
+        let mut data = vec![];
+        entry.read_to_end(&mut data)?;
+        files.insert(path.to_string_lossy().into_owned(), data);
+    }
+
+    Bundle::from_files(files)
+}
+
+impl Bundle {
+    fn from_files(mut files: BTreeMap<String, Vec<u8>>) -> Result<Self, BundleError> {
+        let manifest = files.remove(MANIFEST_FILE).ok_or(BundleError::MissingManifest)?;
+        let manifest = String::from_utf8(manifest)
+            .map_err(|err| BundleError::InvalidManifest(err.to_string()))
+            .and_then(|manifest| {
+                toml::from_str::<Manifest>(&manifest)
+                    .map_err(|err| BundleError::InvalidManifest(err.to_string()))
+            })?;
+
+        for path in manifest.checksums.keys() {
+            if !files.contains_key(path) {
+                return Err(BundleError::MissingFile(path.clone()));
+            }
+        }
+        for (path, data) in &files {
+            let checksum = manifest
+                .checksums
+                .get(path)
+                .ok_or(BundleError::UnlistedFile(path.clone()))?;
+            if !checksum.eq_ignore_ascii_case(&sha256_hex(data)) {
+                return Err(BundleError::ChecksumMismatch(path.clone()));
+            }
+        }
+
+        let module = files
+            .remove(&manifest.module)
+            .ok_or(BundleError::MissingFile(manifest.module.clone()))?;
+        let assets = files
+            .into_iter()
+            .filter_map(|(path, data)| {
+                let asset = path.strip_prefix(ASSETS_DIR)?.strip_prefix('/')?.to_string();
+                Some((asset, data))
+            })
+            .collect();
+
+        Ok(Self {
+            manifest,
+            module,
+            assets,
+        })
+    }
+}
+
+// Build a bundle from a directory with `manifest.toml`, the module and an optional `assets` directory.
+// Checksums in the bundled manifest are computed from the files.
+pub fn build_bundle(source: &Path, output: &Path) -> Result<Manifest, BundleError> {
+    let mut manifest = read_source_manifest(source)?;
+
+    let mut files = BTreeMap::new();
+    let module = fs::read(source.join(&manifest.module))
+        .map_err(|err| BundleError::Io(format!("failed to read {}: {}", manifest.module, err)))?;
+    files.insert(manifest.module.clone(), module);
+    collect_assets(source, &source.join(ASSETS_DIR), &mut files)?;
+
+    manifest.checksums = files
+        .iter()
+        .map(|(path, data)| (path.clone(), sha256_hex(data)))
+        .collect();
+    let manifest_file =
+        toml::to_string(&manifest).map_err(|err| BundleError::InvalidManifest(err.to_string()))?;
+
+    let mut builder = tar::Builder::new(File::create(output)?);
+    append_file(&mut builder, MANIFEST_FILE, manifest_file.as_bytes())?;
+    for (path, data) in &files {
+        append_file(&mut builder, path, data)?;
+    }
+    builder.into_inner()?;
+
+    Ok(manifest)
+}
+
+pub fn read_source_manifest(source: &Path) -> Result<Manifest, BundleError> {
+    let manifest = fs::read_to_string(source.join(MANIFEST_FILE))
+        .map_err(|err| BundleError::Io(format!("failed to read {}: {}", MANIFEST_FILE, err)))?;
+    toml::from_str(&manifest).map_err(|err| BundleError::InvalidManifest(err.to_string()))
+}
+
+fn collect_assets(
+    source: &Path,
+    dir: &Path,
+    files: &mut BTreeMap<String, Vec<u8>>,
+) -> Result<(), BundleError> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_assets(source, &path, files)?;
+        } else {
+            let relative = path
+                .strip_prefix(source)
+                .map_err(|_| BundleError::InvalidPath(path.display().to_string()))?;
+            files.insert(relative.to_string_lossy().into_owned(), fs::read(&path)?);
+        }
+    }
+    Ok(())
+}
+
+// Entries get fixed metadata, so building the same sources twice gives the same bundle
+fn append_file(
+    builder: &mut tar::Builder<File>,
+    path: &str,
+    data: &[u8],
+) -> Result<(), BundleError> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    builder.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+// Host import copying up to `buf_len` bytes of a bundle asset, returns its full size or -1 if missing.
+// Pass `buf_len` 0 to query the size.
+pub fn get_asset(
+    env: FunctionEnvMut<PluginEnv>,
+    name_ptr: i32,
+    name_len: i32,
+    buf_ptr: i32,
+    buf_len: i32,
+) -> i32 {
+    let data = env.data();
+    let Some(memory) = &data.memory else {
+        return -1;
+    };
+    let view = memory.view(&env);
+    let Ok(name) = read_utf8(&view, name_ptr, name_len) else {
+        return -1;
+    };
+    let Some(asset) = data.assets.get(&name) else {
+        return -1;
+    };
+
+    let len = asset.len().min(buf_len.max(0) as usize);
+    match view.write(buf_ptr as u32 as u64, &asset[..len]) {
+        Ok(()) => asset.len() as i32,
+        Err(_) => -1,
+    }
+}
+
+#[cfg(test)]
+mod bundle_tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    use crate::bundle::{build_bundle, read_bundle, sha256_hex, Bundle, BundleError};
+
+    #[test]
+    fn builds_and_reads_bundle() {
+        let source = std::env::temp_dir().join("fw-led-bundle-source");
+        let _ = fs::remove_dir_all(&source);
+        fs::create_dir_all(source.join("assets/fonts")).unwrap();
+        fs::write(source.join("manifest.toml"), "name = 'clock'\nversion = '1.0.0'").unwrap();
+        fs::write(source.join("plugin.wasm"), b"\0asm").unwrap();
+        fs::write(source.join("assets/fonts/digits.bin"), [1, 2, 3]).unwrap();
+        let output = source.join("clock.fwled");
+
+        build_bundle(&source, &output).unwrap();
+        let bundle = read_bundle(&output).unwrap();
+
+        assert_eq!(bundle.manifest.name, "clock");
+        assert_eq!(bundle.module, b"\0asm");
+        assert_eq!(bundle.assets["fonts/digits.bin"], vec![1, 2, 3]);
+        assert_eq!(bundle.manifest.checksums["plugin.wasm"], sha256_hex(b"\0asm"));
+    }
+
+    #[test]
+    fn rejects_tampered_files() {
+        let manifest = format!(
+            "name = 'clock'\n[checksums]\n'plugin.wasm' = '{}'",
+            sha256_hex(b"\0asm")
+        );
+        let files = BTreeMap::from([
+            ("manifest.toml".to_string(), manifest.into_bytes()),
+            ("plugin.wasm".to_string(), b"\0asm-tampered".to_vec()),
+        ]);
+
+        assert_eq!(
+            Bundle::from_files(files).err(),
+            Some(BundleError::ChecksumMismatch("plugin.wasm".to_string()))
+        );
+    }
+}
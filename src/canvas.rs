@@ -1,6 +1,7 @@
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
-use log::error;
 use serde::{Serialize, Serializer};
 use serde::ser::SerializeStruct;
 
@@ -8,35 +9,17 @@ use crate::config::Config;
 use crate::matrix::Matrix;
 use crate::picture::Picture;
 use crate::plugin::Plugin;
+use crate::plugin_host::RemotePicture;
+use crate::plugin_state::PluginStates;
+use crate::stat_provider::system_stats;
+use crate::wasm_module::{compile_plugin, CompiledPlugins, WasmModule};
+
+// Time plugins get to draw a frame, within `PAINT_INTERVAL`
+pub(crate) const FRAME_DEADLINE: Duration = Duration::from_millis(200);
 
 pub struct Canvas {
     pub(crate) plugins: HashMap<String, Plugin>,
 }
-impl From<Config> for Canvas {
-    fn from(value: Config) -> Self {
-        let plugins = value.plugins.into_iter().map(Plugin::from_plugin_config);
-
-        let mut canvas = Self {
-            plugins: HashMap::new(),
-        };
-        for plugin in plugins {
-            let plugin_name = plugin.name.clone();
-            if let Err(err) = canvas.add_plugin(plugin) {
-                match err {
-                    AddPainterError::SpaceTaken => {
-                        error!("No Matrix space left for plugin: {}. Check configuration file for plugin offset settings.", plugin_name);
-                        std::process::exit(1)
-                    }
-                    AddPainterError::DuplicateIdentifier => {
-                        error!("Duplicate identifier for plugin: {}.", plugin_name);
-                        std::process::exit(1)
-                    }
-                }
-            }
-        }
-        canvas
-    }
-}
 
 impl Serialize for Canvas {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -55,12 +38,73 @@ impl Serialize for Canvas {
 #[derive(Debug, Eq, PartialEq)]
 pub(crate) enum AddPainterError {
     SpaceTaken,
+    OutOfBounds,
     DuplicateIdentifier,
 }
 
 impl Canvas {
-    // Call .draw() for all Painters and return the resulting Matrix
+    // Load and place all plugins of a configuration, compiling those missing from `modules`.
+    // Plugins loaded before a failing one are shut down.
+    pub fn load(config: &Config, modules: CompiledPlugins) -> Result<Self, String> {
+        let mut canvas = Self {
+            plugins: HashMap::new(),
+        };
+        if let Err(err) = canvas.add_configured_plugins(config, modules) {
+            canvas.shutdown();
+            return Err(err);
+        }
+        Ok(canvas)
+    }
+
+    fn add_configured_plugins(&mut self, config: &Config, mut modules: CompiledPlugins) -> Result<(), String> {
+        // Instances of the same plugin share a compiled module
+        let plugin_paths = config.plugin_paths();
+
+        for plugin_conf in &config.plugins {
+            let plugin = if config.plugin_host.isolated {
+                RemotePicture::spawn(plugin_conf, &config.plugin_host, &plugin_paths)
+                    .map(|remote| Plugin::from_remote(plugin_conf, remote))
+                    .map_err(|err| format!("Failed to load '{}' module: {}", plugin_conf.id(), err))?
+            } else {
+                let module = match modules.entry(plugin_conf.name.clone()) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(
+                        compile_plugin(&plugin_paths, &plugin_conf.name)
+                            .map_err(|err| format!("Failed to load '{}' module: {}", plugin_conf.name, err))?,
+                    ),
+                };
+                WasmModule::instantiate(module, plugin_conf, system_stats())
+                    .map(|wasm_module| Plugin::new(plugin_conf, wasm_module))
+                    .map_err(|err| format!("Failed to load '{}' module: {}", plugin_conf.id(), err))?
+            };
+
+            let plugin_name = plugin.name.clone();
+            self.add_plugin(plugin).map_err(|err| match err {
+                AddPainterError::SpaceTaken => format!(
+                    "No Matrix space left for plugin: {}. Check configuration file for plugin offset settings.",
+                    plugin_name
+                ),
+                AddPainterError::OutOfBounds => format!(
+                    "Plugin {} doesn't fit in the Matrix at its offset. Check configuration file for plugin offset settings.",
+                    plugin_name
+                ),
+                AddPainterError::DuplicateIdentifier => format!(
+                    "Duplicate identifier for plugin: {}. Give each instance a distinct `id`.",
+                    plugin_name
+                ),
+            })?;
+        }
+        Ok(())
+    }
+
+    // Call .draw() for all Painters and return the resulting Matrix.
+    // Plugins draw concurrently, those missing the deadline are shown with their last frame.
     pub fn paint_matrix(&mut self) -> Matrix {
+        let deadline = Instant::now() + FRAME_DEADLINE;
+        for plugin in self.plugins.values_mut() {
+            plugin.begin_draw(deadline);
+        }
+
         self.plugins
             .iter_mut()
             .map(|(_, plugin)| plugin.draw().shift_matrix(plugin.offset_x, plugin.offset_y))
@@ -71,10 +115,36 @@ impl Canvas {
             .unwrap_or(Matrix::default())
     }
 
+    // Collect state of plugins exporting `save_state`
+    pub fn save_states(&mut self, states: &mut PluginStates) {
+        for (name, plugin) in self.plugins.iter_mut() {
+            if let Some(state) = plugin.save_state() {
+                states.insert(name, state);
+            }
+        }
+    }
+
+    pub fn load_states(&mut self, states: &PluginStates) {
+        for (name, plugin) in self.plugins.iter_mut() {
+            if let Some(state) = states.get(name) {
+                plugin.load_state(state);
+            }
+        }
+    }
+
+    pub fn shutdown(&mut self) {
+        for plugin in self.plugins.values_mut() {
+            plugin.shutdown()
+        }
+    }
+
     pub fn add_plugin(&mut self, plugin: Plugin) -> Result<(), AddPainterError> {
         if self.plugins.get(&plugin.name).is_some() {
             return Err(AddPainterError::DuplicateIdentifier);
         }
+        if !plugin.fits_matrix() {
+            return Err(AddPainterError::OutOfBounds);
+        }
 
         let is_vacant = self.is_space_vacant(&plugin);
 
@@ -86,6 +156,22 @@ impl Canvas {
         Ok(())
     }
 
+    // Swap in a new version of a plugin under the same identifier and return the previous one.
+    // The previous version stays in place if the new one doesn't fit, next to other plugins or in the Matrix.
+    pub fn replace_plugin(&mut self, plugin: Plugin) -> Result<Option<Plugin>, AddPainterError> {
+        let previous = self.plugins.remove(&plugin.name);
+
+        match self.add_plugin(plugin) {
+            Ok(()) => Ok(previous),
+            Err(err) => {
+                if let Some(previous) = previous {
+                    self.plugins.insert(previous.name.clone(), previous);
+                }
+                Err(err)
+            }
+        }
+    }
+
     // Check if Plugin has enough space to paint its picture
     fn is_space_vacant(&self, plugin: &Plugin) -> bool {
         let space_matrix = self.get_space_matrix();
@@ -187,11 +273,14 @@ mod painter_tests {
 #[cfg(test)]
 mod canvas_tests {
     use std::collections::HashMap;
+    use std::fs;
 
     use crate::canvas::{AddPainterError, Canvas};
+    use crate::config::Config;
     use crate::matrix::Matrix;
     use crate::picture::Picture;
     use crate::plugin::Plugin;
+    use crate::wasm_module::CompiledPlugins;
 
     struct PluginMock {}
 
@@ -343,6 +432,55 @@ mod canvas_tests {
         assert_eq!(canvas.is_space_vacant(&painter_2), false)
     }
 
+    #[test]
+    fn replace_plugin_keeps_previous_when_space_taken() {
+        let plugin = |name: &str, offset_x, img_width| Plugin {
+            offset_x,
+            offset_y: 0,
+            img_height: 2,
+            img_width,
+            drawer: Box::new(PluginMock {}),
+            name: name.to_string(),
+        };
+        let mut canvas = Canvas {
+            plugins: HashMap::from([
+                ("left".to_string(), plugin("left", 0, 2)),
+                ("right".to_string(), plugin("right", 4, 2)),
+            ]),
+        };
+
+        let previous = canvas.replace_plugin(plugin("left", 0, 3)).unwrap();
+        let rejected = canvas.replace_plugin(plugin("left", 0, 5));
+
+        assert_eq!(previous.map(|plugin| plugin.img_width), Some(2));
+        assert_eq!(rejected.err(), Some(AddPainterError::SpaceTaken));
+        assert_eq!(canvas.plugins["left"].img_width, 3);
+    }
+
+    #[test]
+    fn replace_plugin_keeps_previous_when_out_of_bounds() {
+        let plugin = |img_width, img_height| Plugin {
+            offset_x: 7,
+            offset_y: 32,
+            img_height,
+            img_width,
+            drawer: Box::new(PluginMock {}),
+            name: "corner".to_string(),
+        };
+        let mut canvas = Canvas {
+            plugins: HashMap::from([("corner".to_string(), plugin(2, 2))]),
+        };
+
+        let wider = canvas.replace_plugin(plugin(3, 2));
+        let taller = canvas.replace_plugin(plugin(2, 3));
+
+        assert_eq!(wider.err(), Some(AddPainterError::OutOfBounds));
+        assert_eq!(taller.err(), Some(AddPainterError::OutOfBounds));
+        assert_eq!(canvas.plugins["corner"].img_height, 2);
+        assert_eq!(canvas.plugins["corner"].img_width, 2);
+        canvas.paint_matrix();
+    }
+
     #[test]
     fn paint_matrix_with_2_painters() {
         struct Painter1 {}
@@ -604,4 +742,35 @@ mod canvas_tests {
         assert_eq!(second_painter_add_result, AddPainterError::SpaceTaken);
         assert_eq!(canvas.plugins.len(), 1);
     }
+
+    #[test]
+    fn load_places_configured_plugins_or_reports_why_not() {
+        let dir = std::env::temp_dir().join("fw-led-canvas-load");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("square.wasm"),
+            r#"(module
+                (memory (export "memory") 1)
+                (@custom "metadata" "{\"name\":\"square\",\"width\":2,\"height\":2}")
+                (func (export "draw") (result i32) (i32.const 0)))"#,
+        )
+        .unwrap();
+        let config = |second_pos_x: usize| -> Config {
+            toml::from_str(&format!(
+                "plugin_paths = ['{}']\n\
+                 [[plugins]]\nname = 'square'\npos_x = 0\npos_y = 0\n\
+                 [[plugins]]\nname = 'square'\nid = 'second'\npos_x = {}\npos_y = 0",
+                dir.display(),
+                second_pos_x
+            ))
+            .unwrap()
+        };
+
+        let mut canvas = Canvas::load(&config(2), CompiledPlugins::new()).unwrap();
+        let overlapping = Canvas::load(&config(1), CompiledPlugins::new());
+
+        assert_eq!(canvas.plugins.len(), 2);
+        assert!(overlapping.is_err_and(|err| err.starts_with("No Matrix space left for plugin: second")));
+        canvas.shutdown();
+    }
 }
@@ -0,0 +1,261 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+
+use clap::{Parser, Subcommand};
+use log::LevelFilter;
+
+use crate::bundle::{build_bundle, read_bundle, read_source_manifest, BUNDLE_EXTENSION};
+use crate::canvas::Canvas;
+use crate::config::Config;
+use crate::config_diagnostics::{check_config, Severity};
+use crate::controller::PAINT_INTERVAL;
+use crate::led_controller::{LEDController, LED_PORT_PATH};
+use crate::matrix::Matrix;
+use crate::plugin_inspect::describe_permissions;
+use crate::plugin_paths::PluginPaths;
+use crate::plugin_state::{state_dir, PluginStates};
+use crate::scaffold;
+use crate::scaffold::Language;
+use crate::wasm_module::{compile, read_metadata};
+
+// Runs the daemon when started without a subcommand
+#[derive(Parser)]
+#[command(version, about = "System stats for Framework laptop LED hardware")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Configuration file, instead of $FW_LED_CONFIG and the default locations
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Serial device of the LED matrix
+    #[arg(short, long, global = true, default_value = LED_PORT_PATH)]
+    pub device: PathBuf,
+    /// Overrides the default level of RUST_LOG: off, error, warn, info, debug or trace
+    #[arg(long, global = true)]
+    pub log_level: Option<LevelFilter>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the daemon, painting configured plugins on the LED matrix
+    Run,
+    /// Paint a single frame with the configured plugins and print it
+    RenderOnce {
+        /// Send the frame to the LED matrix instead of printing it
+        #[arg(long)]
+        send: bool,
+    },
+    /// Show the frames the daemon would paint in the terminal, until interrupted
+    Preview,
+    /// Load the configuration and its plugins, without touching the LED matrix
+    ValidateConfig,
+    /// Build and inspect .fwled plugin bundles
+    #[command(subcommand)]
+    Bundle(BundleCommand),
+    /// Check a .wasm module or .fwled bundle against the plugin ABI and draw a frame with mocked stats
+    InspectPlugin { plugin: PathBuf },
+    /// Create a plugin project, ready to build and test
+    NewPlugin {
+        name: String,
+        /// Columns, up to 9
+        #[arg(long)]
+        width: usize,
+        /// Rows, up to 34
+        #[arg(long)]
+        height: usize,
+        #[arg(long, value_enum, default_value_t = Language::AssemblyScript)]
+        language: Language,
+        /// Defaults to `./<name>`
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// SDK crate Rust projects depend on, the `sdk/fw-led-plugin-sdk` directory of a checkout of this repository
+        #[arg(long)]
+        sdk_path: Option<PathBuf>,
+    },
+    /// List plugins in the search path with the file each name resolves to
+    ListPlugins,
+    /// Serve a single plugin to the daemon over stdin/stdout, started by the daemon itself
+    #[command(hide = true)]
+    PluginHost,
+}
+
+#[derive(Subcommand)]
+pub enum BundleCommand {
+    /// Build a bundle from a directory with manifest.toml, the module and an optional assets directory
+    Build {
+        source: PathBuf,
+        /// Defaults to `<name>.fwled` in the current directory
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Verify a bundle and print its contents
+    Inspect { bundle: PathBuf },
+}
+
+pub fn run_bundle_command(command: BundleCommand) -> Result<(), String> {
+    match command {
+        BundleCommand::Build { source, output } => {
+            let output = match output {
+                Some(output) => output,
+                None => {
+                    let manifest = read_source_manifest(&source).map_err(|err| err.to_string())?;
+                    PathBuf::from(format!("{}.{}", manifest.name, BUNDLE_EXTENSION))
+                }
+            };
+            build_bundle(&source, &output).map_err(|err| err.to_string())?;
+            println!("Built {}", output.display());
+            Ok(())
+        }
+        BundleCommand::Inspect { bundle } => inspect_bundle(&bundle),
+    }
+}
+
+pub fn render_once(config_path: Option<&Path>, device: &Path, send: bool) -> Result<(), String> {
+    // Fail before loading plugins when the frame can't be sent anyway
+    let led_controller = if send { Some(LEDController::open(device)?) } else { None };
+
+    let mut canvas = load_canvas(config_path)?;
+    let matrix = canvas.paint_matrix();
+    canvas.shutdown();
+
+    match led_controller {
+        Some(mut led_controller) => led_controller.draw_matrix(matrix),
+        None => println!("{}", render_matrix(&matrix)),
+    }
+    Ok(())
+}
+
+pub fn preview(config_path: Option<&Path>) -> Result<(), String> {
+    let mut canvas = load_canvas(config_path)?;
+
+    loop {
+        let matrix = canvas.paint_matrix();
+        // Redraw in place: move the cursor home and clear the screen
+        let mut stdout = std::io::stdout().lock();
+        let printed = writeln!(stdout, "\x1b[H\x1b[2J{}", render_matrix(&matrix)).and_then(|_| stdout.flush());
+        // Stop once nothing reads the output anymore
+        if printed.is_err() {
+            canvas.shutdown();
+            return Ok(());
+        }
+
+        sleep(PAINT_INTERVAL);
+    }
+}
+
+// Report every problem of the configuration, then load its plugins as the daemon would
+pub fn validate_config(config_path: Option<&Path>) -> Result<(), String> {
+    let (diagnostics, modules) = check_config(config_path);
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(format!("{} error(s) found", errors));
+    }
+
+    let config = Config::load(config_path)?;
+    let mut canvas = Canvas::load(&config, modules)?;
+    canvas.shutdown();
+
+    for source in &config.sources {
+        println!("Read {}", source.display());
+    }
+    println!("Configuration is valid, {} plugin(s) placed", config.plugins.len());
+    Ok(())
+}
+
+// Canvas of the configured plugins with their persisted state, which is only read
+fn load_canvas(config_path: Option<&Path>) -> Result<Canvas, String> {
+    let (config, modules) = Config::load_checked(config_path)?;
+    let mut canvas = Canvas::load(&config, modules)?;
+    canvas.load_states(&PluginStates::load(state_dir()));
+    Ok(canvas)
+}
+
+fn render_matrix(matrix: &Matrix) -> String {
+    matrix.to_string().replace("\r\n", "\n")
+}
+
+pub fn new_plugin(
+    name: &str,
+    width: usize,
+    height: usize,
+    language: Language,
+    output: Option<PathBuf>,
+    sdk_path: Option<&Path>,
+) -> Result<(), String> {
+    let dir = scaffold::new_plugin(name, width, height, language, output, sdk_path)?;
+
+    println!("Created {}", dir.display());
+    match language {
+        Language::AssemblyScript => println!("Build and test with: npm install && npm run asbuild && npm test"),
+        Language::Rust => println!("Test with: cargo test, build with: cargo build --release --target wasm32-unknown-unknown"),
+    }
+    Ok(())
+}
+
+pub fn list_plugins(plugin_paths: &PluginPaths) {
+    println!("Search path:");
+    for dir in plugin_paths.dirs() {
+        println!("  {}", dir.display());
+    }
+
+    let plugins = plugin_paths.list();
+    if plugins.is_empty() {
+        println!("No plugins found");
+        return;
+    }
+    println!("Plugins:");
+    for (name, files) in plugins {
+        let Some((resolved, shadowed)) = files.split_first() else {
+            continue;
+        };
+        println!("  {} -> {}", name, resolved.display());
+        for file in shadowed {
+            println!("    shadows {}", file.display());
+        }
+    }
+}
+
+fn inspect_bundle(path: &Path) -> Result<(), String> {
+    let bundle = read_bundle(path).map_err(|err| err.to_string())?;
+    let manifest = &bundle.manifest;
+
+    println!("name:        {}", manifest.name);
+    println!("version:     {}", manifest.version.as_deref().unwrap_or("unknown"));
+    if let Some(description) = &manifest.description {
+        println!("description: {}", description);
+    }
+    println!("module:      {} ({} bytes)", manifest.module, bundle.module.len());
+
+    let metadata = compile(&bundle.module)
+        .and_then(|compiled| read_metadata(&compiled.module))
+        .map_err(|err| format!("invalid module: {}", err))?;
+    println!("size:        {}x{}", metadata.width, metadata.height);
+    println!("abi version: {}", metadata.abi_version);
+    println!("permissions: {}", describe_permissions(&metadata));
+
+    let settings_schema = manifest.settings_schema.as_ref().or(metadata.settings_schema.as_ref());
+    if let Some(settings_schema) = settings_schema {
+        println!("settings:");
+        for (key, setting) in settings_schema {
+            println!("  {} ({})", key, setting.kind);
+        }
+    }
+
+    let mut assets = bundle.assets.iter().collect::<Vec<_>>();
+    assets.sort();
+    if !assets.is_empty() {
+        println!("assets:");
+        for (name, data) in assets {
+            println!("  {} ({} bytes)", name, data.len());
+        }
+    }
+    println!("checksums:   ok");
+    Ok(())
+}
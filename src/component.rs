@@ -0,0 +1,497 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use wasmer::{
+    imports, Function, FunctionEnv, FunctionEnvMut, Imports, Instance, Memory, MemoryView, RuntimeError, Store,
+    TypedFunction, Type,
+};
+use wasmparser::{Parser, Payload, TypeRef};
+
+use crate::kv_bus;
+use crate::kv_bus::KvValue;
+use crate::permissions::{required_capability, Capability};
+use crate::plugin_log;
+use crate::plugin_log::read_utf8;
+use crate::plugin_state::MAX_STATE_SIZE;
+use crate::settings::lookup_setting;
+use crate::wasm_module::{
+    call_failed, get_battery_state_of_charge, get_epoch_time, get_global_cpu_usage, get_memory_usage,
+    get_optional_function, PluginEnv, WASMError,
+};
+
+// Components implementing the `plugin` world of wit/plugin.wit. wasmer doesn't implement the component model,
+// so the host runs the core module a component embeds and talks to it through the canonical ABI, under the
+// import and export names `wit-bindgen` and `wit-component` give the world functions.
+
+// Binary preamble of components: magic number, version 0x0d and layer 1
+pub(crate) const COMPONENT_PREAMBLE: [u8; 8] = [0x00, 0x61, 0x73, 0x6d, 0x0d, 0x00, 0x01, 0x00];
+
+pub(crate) const STATS_INTERFACE: &str = "fw-led:plugin/stats@1.0.0";
+const SETTINGS_INTERFACE: &str = "fw-led:plugin/settings@1.0.0";
+const LOGGING_INTERFACE: &str = "fw-led:plugin/logging@1.0.0";
+const KV_INTERFACE: &str = "fw-led:plugin/kv@1.0.0";
+
+// Exports the host calls: name, params, results and whether plugins have to provide it
+pub(crate) const EXPECTED_EXPORTS: [(&str, &[Type], &[Type], bool); 10] = [
+    ("draw", &[], &[Type::I32], true),
+    ("cabi_post_draw", &[Type::I32], &[], false),
+    ("init", &[], &[], false),
+    ("tick", &[Type::I32], &[], false),
+    ("shutdown", &[], &[], false),
+    ("save-state", &[], &[Type::I32], false),
+    ("cabi_post_save-state", &[Type::I32], &[], false),
+    ("load-state", &[Type::I32, Type::I32], &[], false),
+    ("cabi_realloc", &[Type::I32, Type::I32, Type::I32, Type::I32], &[Type::I32], false),
+    ("_initialize", &[], &[], false),
+];
+
+// `option<setting-value>` and `option<kv-value>` results: option tag, then variant tag and payload, 8-byte aligned
+const OPTION_VARIANT_SIZE: usize = 24;
+
+pub(crate) fn is_component(value: &[u8]) -> bool {
+    value.starts_with(&COMPONENT_PREAMBLE)
+}
+
+// Role of a core module inside a component
+#[derive(PartialEq)]
+enum EmbeddedModule {
+    Plugin,
+    // `wit-component` routes lowered imports through a table exported by a shim and filled by a fixup module,
+    // the host links the plugin module to its imports directly instead
+    Shim,
+    Fixup,
+}
+
+// The core module implementing the plugin. Components adapting WASI or composed of several plugin modules
+// need a component model runtime and are rejected.
+pub(crate) fn core_module(value: &[u8]) -> Result<&[u8], WASMError> {
+    let invalid = |err: wasmparser::BinaryReaderError| WASMError::InvalidComponent(err.to_string());
+    let mut modules: Vec<(Range<usize>, EmbeddedModule)> = vec![];
+    let mut nested = false;
+
+    for payload in Parser::new(0).parse_all(value) {
+        match payload.map_err(invalid)? {
+            Payload::ModuleSection { unchecked_range, .. } => {
+                modules.push((unchecked_range, EmbeddedModule::Plugin));
+                nested = true;
+            }
+            Payload::ComponentSection { .. } => {
+                return Err(WASMError::InvalidComponent("nested components aren't supported".to_string()))
+            }
+            Payload::ImportSection(imports) if nested => {
+                for import in imports {
+                    let import = import.map_err(invalid)?;
+                    if import.module.is_empty() && matches!(import.ty, TypeRef::Table(_)) {
+                        modules.last_mut().expect("nested payloads follow their module").1 = EmbeddedModule::Fixup;
+                    }
+                }
+            }
+            Payload::ExportSection(exports) if nested => {
+                for export in exports {
+                    if export.map_err(invalid)?.name == "$imports" {
+                        modules.last_mut().expect("nested payloads follow their module").1 = EmbeddedModule::Shim;
+                    }
+                }
+            }
+            Payload::End(_) => nested = false,
+            _ => {}
+        }
+    }
+
+    let plugins = modules
+        .into_iter()
+        .filter(|(_, module)| *module == EmbeddedModule::Plugin)
+        .map(|(range, _)| range)
+        .collect::<Vec<Range<usize>>>();
+    match plugins.as_slice() {
+        [range] => value
+            .get(range.clone())
+            .ok_or(WASMError::InvalidComponent("core module extends past the end of the file".to_string())),
+        [] => Err(WASMError::InvalidComponent("no core module found".to_string())),
+        _ => Err(WASMError::InvalidComponent(format!(
+            "found {} core modules, components built with a WASI adapter or composed of several modules aren't supported",
+            plugins.len()
+        ))),
+    }
+}
+
+// Imports of the `plugin` world, stats are only linked when the plugin was granted the matching capability
+pub(crate) fn create_imports(
+    store: &mut Store,
+    env: &FunctionEnv<PluginEnv>,
+    granted: &HashSet<Capability>,
+) -> Imports {
+    let mut imports = imports! {
+        SETTINGS_INTERFACE => {
+            "get-setting" => Function::new_typed_with_env(store, env, get_setting),
+        },
+        LOGGING_INTERFACE => {
+            // `level` enum cases are numbered like the levels of the core `log` import
+            "log" => Function::new_typed_with_env(store, env, plugin_log::log_utf8),
+        },
+        KV_INTERFACE => {
+            "get" => Function::new_typed_with_env(store, env, kv_get),
+            "set" => Function::new_typed_with_env(store, env, kv_set),
+        }
+    };
+
+    let stat_functions = [
+        ("battery-state-of-charge", Function::new_typed_with_env(store, env, get_battery_state_of_charge)),
+        ("global-cpu-usage", Function::new_typed_with_env(store, env, get_global_cpu_usage)),
+        ("memory-usage", Function::new_typed_with_env(store, env, get_memory_usage)),
+        ("epoch-time", Function::new_typed_with_env(store, env, get_epoch_time)),
+    ];
+    for (name, function) in stat_functions {
+        if required_capability(STATS_INTERFACE, name).is_some_and(|capability| granted.contains(&capability)) {
+            imports.define(STATS_INTERFACE, name, function);
+        }
+    }
+    imports
+}
+
+// Lowered values of the `setting-value` and `kv-value` cases
+enum CaseValue {
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+}
+
+// get-setting: func(key: string) -> option<setting-value>
+fn get_setting(mut env: FunctionEnvMut<PluginEnv>, key_ptr: i32, key_len: i32, ret_ptr: i32) -> Result<(), RuntimeError> {
+    let value = match lookup_setting(&env, key_ptr, key_len) {
+        Some(toml::Value::Integer(value)) => Some((0, CaseValue::Integer(value))),
+        Some(toml::Value::Float(value)) => Some((1, CaseValue::Float(value))),
+        Some(toml::Value::Boolean(value)) => Some((2, CaseValue::Boolean(value))),
+        Some(toml::Value::String(value)) => Some((3, CaseValue::String(value))),
+        _ => None,
+    };
+    write_option_variant(&mut env, ret_ptr, value)
+}
+
+// get: func(key: string) -> option<kv-value>
+fn kv_get(mut env: FunctionEnvMut<PluginEnv>, key_ptr: i32, key_len: i32, ret_ptr: i32) -> Result<(), RuntimeError> {
+    let value = match kv_bus::lookup(&env, key_ptr, key_len) {
+        Some(KvValue::Integer(value)) => Some((0, CaseValue::Integer(value))),
+        Some(KvValue::Float(value)) => Some((1, CaseValue::Float(value))),
+        Some(KvValue::String(value)) => Some((2, CaseValue::String(value))),
+        None => None,
+    };
+    write_option_variant(&mut env, ret_ptr, value)
+}
+
+// set: func(key: string, value: kv-value), the variant is flattened into its case and a joined (i64, i32) payload
+fn kv_set(
+    env: FunctionEnvMut<PluginEnv>,
+    key_ptr: i32,
+    key_len: i32,
+    case: i32,
+    payload: i64,
+    string_len: i32,
+) -> Result<(), RuntimeError> {
+    let value = match case {
+        0 => KvValue::Integer(payload),
+        1 => KvValue::Float(f64::from_bits(payload as u64)),
+        2 => {
+            let view = memory_view(&env)?;
+            KvValue::String(read_utf8(&view, payload as i32, string_len).map_err(|err| RuntimeError::new(err.to_string()))?)
+        }
+        _ => return Err(RuntimeError::new(format!("invalid kv-value case {}", case))),
+    };
+    kv_bus::store(&env, key_ptr, key_len, value);
+    Ok(())
+}
+
+fn memory_view<'a>(env: &'a FunctionEnvMut<PluginEnv>) -> Result<MemoryView<'a>, RuntimeError> {
+    let memory = env.data().memory.as_ref().ok_or_else(|| RuntimeError::new("plugin has no 'memory' export"))?;
+    Ok(memory.view(env))
+}
+
+fn write_option_variant(
+    env: &mut FunctionEnvMut<PluginEnv>,
+    ret_ptr: i32,
+    value: Option<(u8, CaseValue)>,
+) -> Result<(), RuntimeError> {
+    let mut area = [0u8; OPTION_VARIANT_SIZE];
+    if let Some((case, payload)) = value {
+        area[0] = 1;
+        area[8] = case;
+        match payload {
+            CaseValue::Integer(value) => area[16..].copy_from_slice(&value.to_le_bytes()),
+            CaseValue::Float(value) => area[16..].copy_from_slice(&value.to_le_bytes()),
+            CaseValue::Boolean(value) => area[16] = value as u8,
+            CaseValue::String(value) => {
+                let ptr = copy_to_guest(env, value.as_bytes())?;
+                area[16..20].copy_from_slice(&ptr.to_le_bytes());
+                area[20..].copy_from_slice(&(value.len() as u32).to_le_bytes());
+            }
+        }
+    }
+    memory_view(env)?
+        .write(ret_ptr as u32 as u64, &area)
+        .map_err(|err| RuntimeError::new(err.to_string()))
+}
+
+// Strings returned to the plugin are owned by it, in memory from its `cabi_realloc`
+fn copy_to_guest(env: &mut FunctionEnvMut<PluginEnv>, bytes: &[u8]) -> Result<u32, RuntimeError> {
+    let realloc = env
+        .data()
+        .realloc
+        .clone()
+        .ok_or_else(|| RuntimeError::new("plugin has no 'cabi_realloc' export"))?;
+    let ptr = realloc.call(env, 0, 0, 1, bytes.len() as i32)? as u32;
+    memory_view(env)?
+        .write(ptr as u64, bytes)
+        .map_err(|err| RuntimeError::new(err.to_string()))?;
+    Ok(ptr)
+}
+
+// World exports, besides `tick` and `shutdown` which have the same signature as the core module ones
+pub(crate) struct ComponentExports {
+    draw: TypedFunction<(), i32>,
+    post_draw: Option<TypedFunction<i32, ()>>,
+    init: Option<TypedFunction<(), ()>>,
+    save_state: Option<TypedFunction<(), i32>>,
+    post_save_state: Option<TypedFunction<i32, ()>>,
+    load_state: Option<TypedFunction<(i32, i32), ()>>,
+    pub(crate) realloc: Option<TypedFunction<(i32, i32, i32, i32), i32>>,
+}
+
+impl ComponentExports {
+    pub(crate) fn new(instance: &Instance, store: &Store) -> Result<Self, WASMError> {
+        let draw = instance
+            .exports
+            .get_typed_function(store, "draw")
+            .map_err(|err| WASMError::InvalidExport {
+                name: "draw".to_string(),
+                err: err.to_string(),
+            })?;
+
+        Ok(Self {
+            draw,
+            post_draw: get_optional_function(instance, store, "cabi_post_draw")?,
+            init: get_optional_function(instance, store, "init")?,
+            save_state: get_optional_function(instance, store, "save-state")?,
+            post_save_state: get_optional_function(instance, store, "cabi_post_save-state")?,
+            load_state: get_optional_function(instance, store, "load-state")?,
+            realloc: get_optional_function(instance, store, "cabi_realloc")?,
+        })
+    }
+
+    pub(crate) fn init(&self, store: &mut Store) -> Result<(), WASMError> {
+        match &self.init {
+            Some(init) => init.call(store).map_err(|err| call_failed("init", err)),
+            None => Ok(()),
+        }
+    }
+
+    // draw: func() -> list<u8>, which has to hold exactly `len` values
+    pub(crate) fn draw(&self, store: &mut Store, memory: &Memory, len: usize) -> Result<Vec<u8>, WASMError> {
+        let ret_ptr = self.draw.call(store).map_err(|err| call_failed("draw", err))?;
+        let picture = read_list(store, memory, ret_ptr, |picture_len| match picture_len == len {
+            true => Ok(()),
+            false => Err(format!("returned {} values, expected {}", picture_len, len)),
+        })
+        .map_err(|err| invalid_result("draw", err));
+        post_return(store, self.post_draw.as_ref(), "cabi_post_draw", ret_ptr)?;
+        picture
+    }
+
+    // save-state: func() -> list<u8>, `None` when the plugin doesn't export it
+    pub(crate) fn save_state(&self, store: &mut Store, memory: &Memory) -> Result<Option<Vec<u8>>, WASMError> {
+        let Some(save_state) = &self.save_state else {
+            return Ok(None);
+        };
+        let ret_ptr = save_state.call(store).map_err(|err| call_failed("save-state", err))?;
+        let state = read_list(store, memory, ret_ptr, |state_len| match state_len <= MAX_STATE_SIZE {
+            true => Ok(()),
+            false => Err(format!("returned state of {} bytes, the limit is {} bytes", state_len, MAX_STATE_SIZE)),
+        })
+        .map_err(|err| invalid_result("save-state", err));
+        post_return(store, self.post_save_state.as_ref(), "cabi_post_save-state", ret_ptr)?;
+        state.map(Some)
+    }
+
+    // load-state: func(state: list<u8>), the list is handed over to the plugin
+    pub(crate) fn load_state(&self, store: &mut Store, memory: &Memory, state: &[u8]) -> Result<(), WASMError> {
+        let (Some(load_state), Some(realloc)) = (&self.load_state, &self.realloc) else {
+            return Ok(());
+        };
+        let ptr = realloc
+            .call(store, 0, 0, 1, state.len() as i32)
+            .map_err(|err| call_failed("cabi_realloc", err))?;
+        memory
+            .view(store)
+            .write(ptr as u32 as u64, state)
+            .map_err(|err| WASMError::MemoryAccessFailed(err.to_string()))?;
+        load_state
+            .call(store, ptr, state.len() as i32)
+            .map_err(|err| call_failed("load-state", err))
+    }
+}
+
+// `list<u8>` results are returned as a (ptr, len) pair in a return area. The length is checked before copying.
+fn read_list<F>(store: &Store, memory: &Memory, ret_ptr: i32, check_len: F) -> Result<Vec<u8>, String>
+where
+    F: FnOnce(usize) -> Result<(), String>,
+{
+    let view = memory.view(store);
+    let mut area = [0u8; 8];
+    view.read(ret_ptr as u32 as u64, &mut area).map_err(|err| err.to_string())?;
+    let ptr = u32::from_le_bytes([area[0], area[1], area[2], area[3]]) as u64;
+    let len = u32::from_le_bytes([area[4], area[5], area[6], area[7]]);
+    check_len(len as usize)?;
+    view.copy_range_to_vec(ptr..ptr + len as u64).map_err(|err| err.to_string())
+}
+
+fn invalid_result(function: &str, err: String) -> WASMError {
+    WASMError::InvalidExport {
+        name: function.to_string(),
+        err,
+    }
+}
+
+// Lets the plugin free the returned list
+fn post_return(
+    store: &mut Store,
+    post_return: Option<&TypedFunction<i32, ()>>,
+    name: &str,
+    ret_ptr: i32,
+) -> Result<(), WASMError> {
+    match post_return {
+        Some(post_return) => post_return.call(store, ret_ptr).map_err(|err| call_failed(name, err)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod component_tests {
+    use std::sync::Arc;
+
+    use wasmer::wat2wasm;
+
+    use crate::component::{core_module, COMPONENT_PREAMBLE};
+    use crate::config::PluginConf;
+    use crate::kv_bus::{KvValue, KV_BUS};
+    use crate::picture::Picture;
+    use crate::stat_provider::{system_stats, HostStats, ScriptedStats};
+    use crate::wasm_module::{compile, WASMError, WasmModule};
+
+    // Lights its pixels with the `lit` setting, the CPU usage and the first byte of the `label` setting,
+    // and counts frames on the kv bus
+    const GAUGE_PLUGIN: &str = r#"(module
+        (import "fw-led:plugin/stats@1.0.0" "global-cpu-usage" (func $cpu (result f32)))
+        (import "fw-led:plugin/settings@1.0.0" "get-setting" (func $get_setting (param i32 i32 i32)))
+        (import "fw-led:plugin/kv@1.0.0" "set" (func $kv_set (param i32 i32 i32 i64 i32)))
+        (memory (export "memory") 1)
+        (global $heap (mut i32) (i32.const 4096))
+        (@custom "metadata" "{\"name\":\"gauge\",\"width\":3,\"height\":1,\"permissions\":[\"cpu\"]}")
+        (data (i32.const 0) "lit")
+        (data (i32.const 8) "label")
+        (data (i32.const 16) "frames")
+        (func (export "cabi_realloc") (param i32 i32 i32 i32) (result i32)
+            (global.get $heap)
+            (global.set $heap (i32.add (global.get $heap) (local.get 3))))
+        (func (export "init")
+            (call $get_setting (i32.const 0) (i32.const 3) (i32.const 64))
+            (call $get_setting (i32.const 8) (i32.const 5) (i32.const 96)))
+        (func (export "draw") (result i32)
+            (i32.store8 (i32.const 256) (i32.load8_u (i32.const 80)))
+            (i32.store8 (i32.const 257) (i32.trunc_f32_u (call $cpu)))
+            (i32.store8 (i32.const 258) (i32.load8_u (i32.load (i32.const 112))))
+            (call $kv_set (i32.const 16) (i32.const 6) (i32.const 0) (i64.const 1) (i32.const 0))
+            (i32.store (i32.const 128) (i32.const 256))
+            (i32.store (i32.const 132) (i32.const 3))
+            (i32.const 128)))"#;
+
+    // Keeps a single byte counter at address 0, incremented on every draw
+    const COUNTER_PLUGIN: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $heap (mut i32) (i32.const 4096))
+        (@custom "metadata" "{\"name\":\"counter\",\"width\":1,\"height\":1}")
+        (func (export "cabi_realloc") (param i32 i32 i32 i32) (result i32)
+            (global.get $heap)
+            (global.set $heap (i32.add (global.get $heap) (local.get 3))))
+        (func (export "save-state") (result i32)
+            (i32.store (i32.const 128) (i32.const 0))
+            (i32.store (i32.const 132) (i32.const 1))
+            (i32.const 128))
+        (func (export "load-state") (param $ptr i32) (param $len i32)
+            (i32.store8 (i32.const 0) (i32.load8_u (local.get $ptr))))
+        (func (export "draw") (result i32)
+            (i32.store8 (i32.const 0) (i32.add (i32.load8_u (i32.const 0)) (i32.const 1)))
+            (i32.store (i32.const 128) (i32.const 0))
+            (i32.store (i32.const 132) (i32.const 1))
+            (i32.const 128)))"#;
+
+    // Modules `wit-component` adds to route imports which need the plugin memory
+    const SHIM_MODULE: &str = r#"(module (table (export "$imports") 1 1 funcref))"#;
+    const FIXUP_MODULE: &str = r#"(module (import "" "$imports" (table 1 1 funcref)))"#;
+
+    // Core modules in a component, without the type, instance and canonical sections a real one has
+    fn component(modules: &[&str]) -> Vec<u8> {
+        let mut component = COMPONENT_PREAMBLE.to_vec();
+        for module in modules {
+            let module = wat2wasm(module.as_bytes()).unwrap();
+            // Core module section, with its LEB128 encoded size
+            component.push(1);
+            let mut size = module.len();
+            while size >= 0x80 {
+                component.push((size & 0x7f) as u8 | 0x80);
+                size >>= 7;
+            }
+            component.push(size as u8);
+            component.extend_from_slice(&module);
+        }
+        component
+    }
+
+    fn plugin_conf(name: &str) -> PluginConf {
+        toml::from_str(&format!(
+            "name = '{}'\npos_x = 0\npos_y = 0\npermissions = ['cpu']\n[settings]\nlit = 200\nlabel = 'A'",
+            name
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn draws_component_through_canonical_abi() {
+        let compiled = compile(&component(&[SHIM_MODULE, GAUGE_PLUGIN, FIXUP_MODULE])).unwrap();
+        let stats = ScriptedStats::new(HostStats {
+            global_cpu_usage: 42.0,
+            memory_usage: 0.0,
+            battery_state_of_charge: 0.0,
+            epoch_time: 0,
+        });
+
+        let mut module = WasmModule::instantiate(&compiled, &plugin_conf("gauge"), Arc::new(stats)).unwrap();
+        let frame = module.draw();
+
+        assert!(compiled.component);
+        assert_eq!((frame.get_el(0, 0), frame.get_el(0, 1), frame.get_el(0, 2)), (200, 42, b'A'));
+        assert_eq!(KV_BUS.lock().unwrap().get("gauge.frames"), Some(&KvValue::Integer(1)));
+    }
+
+    #[test]
+    fn restores_component_state() {
+        let compiled = compile(&component(&[COUNTER_PLUGIN])).unwrap();
+        let mut previous = WasmModule::instantiate(&compiled, &plugin_conf("counter"), system_stats()).unwrap();
+        previous.draw();
+        previous.draw();
+        let state = previous.save_state().unwrap();
+
+        let mut reloaded = WasmModule::instantiate(&compiled, &plugin_conf("counter"), system_stats()).unwrap();
+        reloaded.load_state(&state);
+
+        assert_eq!(state, vec![2]);
+        assert_eq!(reloaded.draw().get_el(0, 0), 3);
+    }
+
+    #[test]
+    fn rejects_component_with_several_modules() {
+        let adapted = component(&[GAUGE_PLUGIN, COUNTER_PLUGIN]);
+
+        assert!(matches!(core_module(&adapted), Err(WASMError::InvalidComponent(_))));
+        assert!(matches!(core_module(&COMPONENT_PREAMBLE), Err(WASMError::InvalidComponent(_))));
+    }
+}
@@ -1,49 +1,295 @@
 use std::env::current_exe;
 use std::fs;
 use std::io::ErrorKind;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use log::error;
+use log::{error, warn};
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Debug)]
-pub(crate) struct Config {
+use crate::config_diagnostics::{check_config, Diagnostic, Severity};
+use crate::permissions::Capability;
+use crate::plugin_paths::PluginPaths;
+use crate::wasm_module::CompiledPlugins;
+
+const CONFIG_SUBDIR: &str = "fw-led-stat-control";
+// Overrides the default locations, the `--config` flag takes precedence over it
+const CONFIG_ENV_VAR: &str = "FW_LED_CONFIG";
+// Directory next to the config file with `*.toml` drop-ins, merged in lexical order
+pub(crate) const DROP_IN_DIR: &str = "conf.d";
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Config {
     pub(crate) plugins: Vec<PluginConf>,
+    #[serde(default)]
+    pub(crate) plugin_host: PluginHostConf,
+    // Searched for plugins before the user and system directories
+    #[serde(default)]
+    pub(crate) plugin_paths: Vec<PathBuf>,
+    // Reload when the config file or a drop-in changes, read at startup only
+    #[serde(default)]
+    pub auto_reload: bool,
+    #[serde(default)]
+    pub control_socket: ControlSocketConf,
+    // Main file followed by the drop-ins merged into it
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+}
+
+// Runs plugins in separate helper processes, one per plugin instance, instead of the daemon
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct PluginHostConf {
+    #[serde(default)]
+    pub(crate) isolated: bool,
+    // User and group the helper processes switch to, requires the daemon to run as root. `nobody` when unset.
+    pub(crate) uid: Option<u32>,
+    pub(crate) gid: Option<u32>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-pub(crate) struct PluginConf {
+// Access to the control socket, read at startup only
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ControlSocketConf {
+    // Group allowed to use the socket besides root
+    pub gid: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PluginConf {
     pub(crate) name: String,
+    // Distinguishes instances of the same plugin, defaults to `name`
+    pub(crate) id: Option<String>,
     pub(crate) pos_x: usize,
     pub(crate) pos_y: usize,
+    pub(crate) wasi: Option<WasiConf>,
+    // Capabilities the user approves for the plugin
+    #[serde(default)]
+    pub(crate) permissions: Vec<Capability>,
+    // Passed to the plugin `init` export as JSON
+    #[serde(default)]
+    pub(crate) settings: toml::Table,
+}
+
+// Presence of the table enables WASI preview1 imports for the plugin
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct WasiConf {
+    // Host directory exposed read-only to the plugin
+    pub(crate) preopen: Option<PathBuf>,
+    #[serde(default = "default_guest_path")]
+    pub(crate) guest_path: String,
+}
+
+impl PluginConf {
+    pub(crate) fn id(&self) -> &str {
+        self.id.as_deref().unwrap_or(&self.name)
+    }
+}
+
+fn default_guest_path() -> String {
+    "/".to_string()
 }
 
 impl Config {
-    pub fn init() -> Self {
-        let config_file = get_config_file();
-        toml::from_str(&config_file).unwrap()
+    pub fn plugin_paths(&self) -> PluginPaths {
+        PluginPaths::new(&self.plugin_paths)
+    }
+
+    // Plugin search path for tools that should work without a valid config
+    pub fn plugin_paths_or_default(config_path: Option<&Path>) -> PluginPaths {
+        match Config::load(config_path) {
+            Ok(config) => config.plugin_paths(),
+            Err(_) => PluginPaths::new(&[]),
+        }
     }
+
+    // Read the configuration from `config_path`, or the first file found in the default locations,
+    // with the drop-ins of its `conf.d` directory merged into it
+    pub fn load(config_path: Option<&Path>) -> Result<Self, String> {
+        let path = config_file(config_path)?;
+        let mut table = read_table(&path)?;
+        let mut sources = vec![path.clone()];
+        for drop_in in drop_ins(&path)? {
+            merge_config(&mut table, read_table(&drop_in)?);
+            sources.push(drop_in);
+        }
+
+        let mut config = toml::Value::Table(table).try_into::<Config>().map_err(|err| {
+            let sources = sources.iter().map(|source| source.display().to_string()).collect::<Vec<String>>();
+            format!("Invalid configuration in {}: {}", sources.join(", "), err)
+        })?;
+        config.sources = sources;
+        Ok(config)
+    }
+
+    // Load the configuration once the validation pass found no errors, failing with all of them otherwise.
+    // Returns the plugin modules compiled by the validation pass, for `Canvas::load`.
+    pub(crate) fn load_checked(config_path: Option<&Path>) -> Result<(Self, CompiledPlugins), String> {
+        let (diagnostics, modules) = check_config(config_path);
+        let (errors, warnings): (Vec<Diagnostic>, Vec<Diagnostic>) =
+            diagnostics.into_iter().partition(|diagnostic| diagnostic.severity == Severity::Error);
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+        if !errors.is_empty() {
+            let errors = errors.iter().map(Diagnostic::to_string).collect::<Vec<String>>();
+            return Err(errors.join("\n"));
+        }
+        Ok((Self::load(config_path)?, modules))
+    }
+
+    pub(crate) fn init(config_path: Option<&Path>) -> (Self, CompiledPlugins) {
+        Self::load_checked(config_path).unwrap_or_else(|err| {
+            error!("{}", err);
+            std::process::exit(1)
+        })
+    }
+}
+
+// `--config`, then `$FW_LED_CONFIG`, then the first existing of the user, system and `<exe dir>` files
+pub(crate) fn config_file(config_path: Option<&Path>) -> Result<PathBuf, String> {
+    if let Some(path) = config_path {
+        return Ok(path.to_path_buf());
+    }
+    if let Some(path) = std::env::var_os(CONFIG_ENV_VAR).filter(|path| !path.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+
+    let candidates = default_config_files();
+    candidates.iter().find(|path| path.is_file()).cloned().ok_or_else(|| {
+        let candidates = candidates.iter().map(|path| path.display().to_string()).collect::<Vec<String>>();
+        format!("Configuration file not found, looked in {}", candidates.join(", "))
+    })
 }
 
-fn get_config_file() -> String {
-    let config_path = current_exe()
-        .map(|path| path.as_path().parent().map(PathBuf::from))
-        .ok()
+fn default_config_files() -> Vec<PathBuf> {
+    let mut files = vec![];
+
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(config_home) = config_home {
+        files.push(config_home.join(CONFIG_SUBDIR).join("config.toml"));
+    }
+    files.push(Path::new("/etc").join(CONFIG_SUBDIR).join("config.toml"));
+    if let Some(exe_dir) = current_exe().ok().and_then(|path| path.parent().map(Path::to_path_buf)) {
+        files.push(exe_dir.join("config.toml"));
+    }
+    files
+}
+
+fn read_table(path: &Path) -> Result<toml::Table, String> {
+    let contents = fs::read_to_string(path).map_err(|err| match err.kind() {
+        ErrorKind::NotFound => format!("Configuration file {} not found", path.display()),
+        _ => format!("Failed to read configuration file {}: {}", path.display(), err),
+    })?;
+    toml::from_str(&contents).map_err(|err| format!("Invalid configuration file {}: {}", path.display(), err))
+}
+
+// `*.toml` files in the drop-in directory next to the config file, sorted by name
+pub(crate) fn drop_ins(config_file: &Path) -> Result<Vec<PathBuf>, String> {
+    let dir = config_file.parent().unwrap_or(Path::new(".")).join(DROP_IN_DIR);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(format!("Failed to read {}: {}", dir.display(), err)),
+    };
+
+    let mut drop_ins = entries
         .flatten()
-        .map(|path| path.join("./config.toml"))
-        .ok_or(std::io::Error::from(ErrorKind::Other))
-        .and_then(fs::canonicalize)
-        .and_then(fs::read_to_string);
-
-    config_path.unwrap_or_else(|err| {
-        match err.kind() {
-            ErrorKind::NotFound => {
-                error!("Configuration file not found in the library directory");
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|extension| extension == "toml"))
+        .collect::<Vec<PathBuf>>();
+    drop_ins.sort();
+    Ok(drop_ins)
+}
+
+// Drop-ins add directories to `plugin_paths` and instances to `[[plugins]]`, where an entry with the instance id
+// of an earlier one updates that entry instead. Tables are merged key by key, other values replaced.
+fn merge_config(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Array(base)), toml::Value::Array(overlay)) if key == "plugins" => {
+                merge_plugins(base, overlay)
             }
-            _ => {
-                error!("Unknown error occurred {}", err);
+            (Some(toml::Value::Array(base)), toml::Value::Array(overlay)) if key == "plugin_paths" => {
+                base.extend(overlay)
+            }
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge_tables(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
             }
         }
-        std::process::exit(1)
-    })
+    }
+}
+
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge_tables(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn merge_plugins(base: &mut Vec<toml::Value>, overlay: Vec<toml::Value>) {
+    // Same fallback to the plugin name as `PluginConf::id`
+    fn instance_id(plugin: &toml::Value) -> Option<&str> {
+        plugin.get("id").or_else(|| plugin.get("name")).and_then(toml::Value::as_str)
+    }
+
+    for plugin in overlay {
+        let existing = instance_id(&plugin)
+            .and_then(|id| base.iter().position(|existing| instance_id(existing) == Some(id)));
+        match (existing.map(|index| &mut base[index]), plugin) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(plugin)) => merge_tables(existing, plugin),
+            (_, plugin) => base.push(plugin),
+        }
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::config::Config;
+
+    #[test]
+    fn merges_drop_ins_in_lexical_order() {
+        let dir = std::env::temp_dir().join("fw-led-config");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        fs::write(
+            dir.join("config.toml"),
+            "plugin_paths = ['/opt/plugins']\n\
+             [[plugins]]\nname = 'cpu'\npos_x = 2\npos_y = 24\npermissions = ['cpu']",
+        )
+        .unwrap();
+        // Moves the `cpu` instance, then adds a second one
+        fs::write(dir.join("conf.d/20-cpu.toml"), "[[plugins]]\nname = 'cpu'\npos_y = 10").unwrap();
+        fs::write(
+            dir.join("conf.d/10-clock.toml"),
+            "plugin_paths = ['/srv/plugins']\n\
+             [plugin_host]\nisolated = true\n\
+             [[plugins]]\nname = 'cpu'\nid = 'cpu-2'\npos_x = 5\npos_y = 0",
+        )
+        .unwrap();
+        fs::write(dir.join("conf.d/30-ignored.txt"), "not toml").unwrap();
+
+        let config = Config::load(Some(&dir.join("config.toml"))).unwrap();
+
+        let placement = config
+            .plugins
+            .iter()
+            .map(|plugin| (plugin.id(), plugin.pos_x, plugin.pos_y, plugin.permissions.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(placement, vec![("cpu", 2, 10, 1), ("cpu-2", 5, 0, 0)]);
+        assert_eq!(config.plugin_paths, vec![PathBuf::from("/opt/plugins"), PathBuf::from("/srv/plugins")]);
+        assert!(config.plugin_host.isolated);
+        assert_eq!(
+            config.sources,
+            vec![dir.join("config.toml"), dir.join("conf.d/10-clock.toml"), dir.join("conf.d/20-cpu.toml")]
+        );
+    }
 }
@@ -0,0 +1,629 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use toml::de::{DeTable, DeValue, ValueDeserializer};
+use toml::Spanned;
+
+use crate::config::{config_file, drop_ins, ControlSocketConf, PluginHostConf, WasiConf};
+use crate::control_socket::CONTROL_NAMESPACE;
+use crate::matrix::{MATRIX_HEIGHT, MATRIX_WIDTH};
+use crate::permissions::{grant_permissions, Capability};
+use crate::plugin_paths::PluginPaths;
+use crate::wasm_module::{compile_file, read_metadata, CompiledPlugins};
+
+const CONFIG_KEYS: [&str; 5] = ["plugins", "plugin_host", "plugin_paths", "auto_reload", "control_socket"];
+const PLUGIN_HOST_KEYS: [&str; 3] = ["isolated", "uid", "gid"];
+const CONTROL_SOCKET_KEYS: [&str; 1] = ["gid"];
+const PLUGIN_KEYS: [&str; 7] = ["name", "id", "pos_x", "pos_y", "wasi", "permissions", "settings"];
+const WASI_KEYS: [&str; 2] = ["preopen", "guest_path"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    // Ignored by the daemon, e.g. unknown keys
+    Warning,
+    // Keeps the daemon from loading the configuration
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: PathBuf,
+    // Both start at 1
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // Missing when the problem isn't tied to a place in a file, e.g. an unreadable file
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.line, self.column)
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{}: ", location)?;
+        }
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+// Byte range in one of the checked files
+#[derive(Clone, Debug)]
+struct Origin {
+    source: usize,
+    span: Range<usize>,
+}
+
+// `[[plugins]]` entry with drop-ins merged into it, along with where its values were set
+struct PluginEntry {
+    origin: Origin,
+    id: Option<String>,
+    name: Option<(String, Origin)>,
+    pos_x: Option<(usize, Origin)>,
+    pos_y: Option<(usize, Origin)>,
+    permissions: Option<(Vec<Capability>, Origin)>,
+    // Keys set in any of the merged entries, including those with invalid values
+    keys: HashSet<String>,
+}
+
+impl PluginEntry {
+    // Same fallback to the plugin name as `PluginConf::id`
+    fn instance_id(&self) -> Option<&str> {
+        self.id.as_deref().or(self.name.as_ref().map(|(name, _)| name.as_str()))
+    }
+
+    fn merge(&mut self, overlay: PluginEntry) {
+        self.id = overlay.id.or(self.id.take());
+        self.name = overlay.name.or(self.name.take());
+        self.pos_x = overlay.pos_x.or(self.pos_x.take());
+        self.pos_y = overlay.pos_y.or(self.pos_y.take());
+        self.permissions = overlay.permissions.or(self.permissions.take());
+        self.keys.extend(overlay.keys);
+    }
+}
+
+// Width, height and requested capabilities of a plugin module
+type PluginSize = (usize, usize, Option<Vec<Capability>>);
+
+// Plugin placed on the matrix, for overlap checks
+struct Placement<'a> {
+    id: &'a str,
+    origin: &'a Origin,
+    columns: Range<usize>,
+    rows: Range<usize>,
+}
+
+struct Checker {
+    files: Vec<(PathBuf, String)>,
+    diagnostics: Vec<(Severity, Option<Origin>, String)>,
+    plugins: Vec<PluginEntry>,
+    plugin_paths: Vec<PathBuf>,
+    // Modules compiled for the size checks, handed on to load the canvas
+    modules: CompiledPlugins,
+    // Set when a file couldn't be read or parsed, its plugins may be incomplete
+    incomplete: bool,
+}
+
+// Check the configuration files `Config::load` would read and report every problem found, in order of appearance.
+// Plugins are looked up in the search path and compiled to check their size, but aren't instantiated. The compiled
+// modules are returned along with the diagnostics so loading the configuration doesn't compile them again.
+pub fn check_config(config_path: Option<&Path>) -> (Vec<Diagnostic>, CompiledPlugins) {
+    let path = match config_file(config_path) {
+        Ok(path) => path,
+        Err(err) => return (vec![Diagnostic::error(None, err)], CompiledPlugins::new()),
+    };
+    let mut checker = Checker {
+        files: vec![],
+        diagnostics: vec![],
+        plugins: vec![],
+        plugin_paths: vec![],
+        modules: CompiledPlugins::new(),
+        incomplete: false,
+    };
+
+    let drop_ins = drop_ins(&path).unwrap_or_else(|err| {
+        checker.error(None, err);
+        vec![]
+    });
+    for (index, file) in [path].into_iter().chain(drop_ins).enumerate() {
+        match fs::read_to_string(&file) {
+            Ok(contents) => {
+                checker.files.push((file, contents));
+                checker.check_file(checker.files.len() - 1, index > 0);
+            }
+            Err(err) => {
+                checker.error(None, format!("failed to read {}: {}", file.display(), err));
+                checker.incomplete = true;
+            }
+        }
+    }
+    if !checker.incomplete {
+        checker.check_layout();
+    }
+    let modules = std::mem::take(&mut checker.modules);
+    (checker.into_diagnostics(), modules)
+}
+
+impl Diagnostic {
+    fn error(location: Option<Location>, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            location,
+            message,
+        }
+    }
+}
+
+impl Checker {
+    fn error(&mut self, origin: Option<Origin>, message: String) {
+        self.diagnostics.push((Severity::Error, origin, message));
+    }
+
+    fn warning(&mut self, origin: Option<Origin>, message: String) {
+        self.diagnostics.push((Severity::Warning, origin, message));
+    }
+
+    fn location(&self, origin: &Origin) -> Location {
+        let (file, contents) = &self.files[origin.source];
+        let before = &contents[..origin.span.start.min(contents.len())];
+        let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+        Location {
+            file: file.clone(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    fn into_diagnostics(mut self) -> Vec<Diagnostic> {
+        self.diagnostics
+            .sort_by_key(|(_, origin, _)| origin.as_ref().map(|origin| (origin.source, origin.span.start)));
+        self.diagnostics
+            .iter()
+            .map(|(severity, origin, message)| Diagnostic {
+                severity: *severity,
+                location: origin.as_ref().map(|origin| self.location(origin)),
+                message: message.clone(),
+            })
+            .collect()
+    }
+
+    fn check_file(&mut self, source: usize, is_drop_in: bool) {
+        let contents = self.files[source].1.clone();
+        let (table, errors) = DeTable::parse_recoverable(&contents);
+        if !errors.is_empty() {
+            self.incomplete = true;
+            for err in errors {
+                let origin = err.span().map(|span| Origin { source, span });
+                self.error(origin, err.message().to_string());
+            }
+            return;
+        }
+
+        for (key, value) in table.get_ref() {
+            let origin = Origin {
+                source,
+                span: key.span(),
+            };
+            match key.get_ref().as_ref() {
+                "plugins" => match value.get_ref() {
+                    DeValue::Array(entries) => {
+                        for entry in entries.iter() {
+                            self.check_plugin_entry(source, entry, is_drop_in);
+                        }
+                    }
+                    _ => self.error(Some(origin), "`plugins` should be an array of `[[plugins]]` tables".to_string()),
+                },
+                "plugin_host" => {
+                    self.check_keys(source, value, &PLUGIN_HOST_KEYS, "plugin_host");
+                    self.check_value::<PluginHostConf>(source, "plugin_host", value);
+                }
+                "plugin_paths" => {
+                    if let Some(paths) = self.check_value::<Vec<PathBuf>>(source, "plugin_paths", value) {
+                        self.plugin_paths.extend(paths);
+                    }
+                }
+                "auto_reload" => {
+                    self.check_value::<bool>(source, "auto_reload", value);
+                }
+                "control_socket" => {
+                    self.check_keys(source, value, &CONTROL_SOCKET_KEYS, "control_socket");
+                    self.check_value::<ControlSocketConf>(source, "control_socket", value);
+                }
+                key => self.unknown_key(origin, key, &CONFIG_KEYS, "configuration"),
+            }
+        }
+    }
+
+    fn check_plugin_entry(&mut self, source: usize, entry: &Spanned<DeValue>, is_drop_in: bool) {
+        let origin = Origin {
+            source,
+            span: entry.span(),
+        };
+        let Some(table) = entry.get_ref().as_table() else {
+            self.error(Some(origin), "`plugins` entries should be tables".to_string());
+            return;
+        };
+
+        let mut plugin = PluginEntry {
+            origin,
+            id: None,
+            name: None,
+            pos_x: None,
+            pos_y: None,
+            permissions: None,
+            keys: table.keys().map(|key| key.get_ref().to_string()).collect(),
+        };
+        for (key, value) in table {
+            let key_origin = Origin {
+                source,
+                span: key.span(),
+            };
+            let value_origin = Origin {
+                source,
+                span: value.span(),
+            };
+            match key.get_ref().as_ref() {
+                "name" => {
+                    plugin.name = self.check_value::<String>(source, "name", value).map(|name| (name, value_origin))
+                }
+                "id" => plugin.id = self.check_value::<String>(source, "id", value),
+                "pos_x" => plugin.pos_x = self.check_position(source, "pos_x", value, MATRIX_WIDTH, "columns"),
+                "pos_y" => plugin.pos_y = self.check_position(source, "pos_y", value, MATRIX_HEIGHT, "rows"),
+                "permissions" => {
+                    plugin.permissions = self
+                        .check_value::<Vec<Capability>>(source, "permissions", value)
+                        .map(|permissions| (permissions, value_origin))
+                }
+                "wasi" => {
+                    self.check_keys(source, value, &WASI_KEYS, "wasi");
+                    self.check_value::<WasiConf>(source, "wasi", value);
+                }
+                "settings" => {
+                    self.check_value::<toml::Table>(source, "settings", value);
+                }
+                key => self.unknown_key(key_origin, key, &PLUGIN_KEYS, "plugin entry"),
+            }
+        }
+
+        // Drop-in entries update the earlier entry with the same instance id, as in `Config::load`
+        let existing = match (is_drop_in, plugin.instance_id()) {
+            (true, Some(id)) => self.plugins.iter().position(|existing| existing.instance_id() == Some(id)),
+            _ => None,
+        };
+        match existing {
+            Some(index) => self.plugins[index].merge(plugin),
+            None => self.plugins.push(plugin),
+        }
+    }
+
+    // Warn about keys of a table that aren't in `known`
+    fn check_keys(&mut self, source: usize, value: &Spanned<DeValue>, known: &[&str], table_name: &str) {
+        let Some(table) = value.get_ref().as_table() else {
+            return;
+        };
+        for key in table.keys() {
+            if !known.contains(&key.get_ref().as_ref()) {
+                let origin = Origin {
+                    source,
+                    span: key.span(),
+                };
+                self.unknown_key(origin, key.get_ref(), known, table_name);
+            }
+        }
+    }
+
+    fn unknown_key(&mut self, origin: Origin, key: &str, known: &[&str], table_name: &str) {
+        let message = format!("unknown key `{}` in {}{}", key, table_name, did_you_mean(key, known.iter().copied()));
+        self.warning(Some(origin), message);
+    }
+
+    // Deserialize a single value the way `Config` would, reporting the error at the offending value
+    fn check_value<T: DeserializeOwned>(&mut self, source: usize, key: &str, value: &Spanned<DeValue>) -> Option<T> {
+        match T::deserialize(ValueDeserializer::from(value.clone())) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                let span = err.span().unwrap_or(value.span());
+                self.error(Some(Origin { source, span }), format!("invalid `{}`: {}", key, err.message()));
+                None
+            }
+        }
+    }
+
+    fn check_position(
+        &mut self,
+        source: usize,
+        key: &str,
+        value: &Spanned<DeValue>,
+        size: usize,
+        axis: &str,
+    ) -> Option<(usize, Origin)> {
+        let position = self.check_value::<usize>(source, key, value)?;
+        let origin = Origin {
+            source,
+            span: value.span(),
+        };
+        if position >= size {
+            let message = format!(
+                "`{} = {}` is outside of the matrix, its {} go from 0 to {}",
+                key,
+                position,
+                axis,
+                size - 1
+            );
+            self.error(Some(origin), message);
+            return None;
+        }
+        Some((position, origin))
+    }
+
+    fn check_layout(&mut self) {
+        let plugin_paths = PluginPaths::new(&self.plugin_paths);
+        let available = plugin_paths.list();
+        // Plugin size by name, `None` when it failed to load
+        let mut sizes: HashMap<String, Option<PluginSize>> = HashMap::new();
+        let mut errors = vec![];
+        let mut ids: HashMap<&str, &Origin> = HashMap::new();
+        let mut placements: Vec<Placement> = vec![];
+
+        for plugin in &self.plugins {
+            // Invalid values were reported already
+            for field in ["name", "pos_x", "pos_y"] {
+                if !plugin.keys.contains(field) {
+                    errors.push((plugin.origin.clone(), format!("plugin entry is missing `{}`", field)));
+                }
+            }
+            let (Some(id), Some((name, name_origin))) = (plugin.instance_id(), &plugin.name) else {
+                continue;
+            };
+            if let Some(first) = ids.insert(id, &plugin.origin) {
+                let message = format!(
+                    "duplicate instance id `{}`, first used at {}. Give each instance a distinct `id`",
+                    id,
+                    self.location(first)
+                );
+                errors.push((plugin.origin.clone(), message));
+                continue;
+            }
+            if id == CONTROL_NAMESPACE {
+                let message = format!(
+                    "instance id `{}` is reserved for values written through the control socket, set another `id`",
+                    id
+                );
+                errors.push((plugin.origin.clone(), message));
+            }
+
+            let size = sizes.entry(name.clone()).or_insert_with(|| {
+                let path = plugin_paths.resolve(name)?;
+                let metadata = compile_file(&path).and_then(|compiled| {
+                    let metadata = read_metadata(&compiled.module)?;
+                    self.modules.insert(name.clone(), compiled);
+                    Ok(metadata)
+                });
+                match metadata {
+                    Ok(metadata) => Some((metadata.width, metadata.height, metadata.permissions)),
+                    Err(err) => {
+                        let message = format!("failed to load `{}` from {}: {}", name, path.display(), err);
+                        errors.push((name_origin.clone(), message));
+                        None
+                    }
+                }
+            });
+            if plugin_paths.resolve(name).is_none() {
+                let message = format!(
+                    "plugin `{}` not found in the search path{}",
+                    name,
+                    did_you_mean(name, available.keys().map(String::as_str))
+                );
+                errors.push((name_origin.clone(), message));
+            }
+            let (Some((width, height, requested)), Some((pos_x, pos_x_origin)), Some((pos_y, pos_y_origin))) =
+                (size.clone(), &plugin.pos_x, &plugin.pos_y)
+            else {
+                continue;
+            };
+
+            let approved = plugin.permissions.as_ref().map(|(permissions, _)| permissions.as_slice()).unwrap_or(&[]);
+            let permissions_invalid = plugin.keys.contains("permissions") && plugin.permissions.is_none();
+            if let (false, Err(unapproved)) = (permissions_invalid, grant_permissions(requested.as_deref(), approved)) {
+                let unapproved = unapproved.iter().map(Capability::to_string).collect::<Vec<String>>();
+                let origin = plugin.permissions.as_ref().map(|(_, origin)| origin).unwrap_or(&plugin.origin);
+                let message = format!(
+                    "`{}` requires permissions not granted in `permissions`: {}",
+                    id,
+                    unapproved.join(", ")
+                );
+                errors.push((origin.clone(), message));
+            }
+
+            let columns = *pos_x..pos_x + width;
+            let rows = *pos_y..pos_y + height;
+            let mut fits = true;
+            if columns.end > MATRIX_WIDTH {
+                let message = format!(
+                    "`{}` is {} column(s) wide, at `pos_x = {}` it would take {} but the last column is {}",
+                    id,
+                    width,
+                    pos_x,
+                    cells("column", &columns),
+                    MATRIX_WIDTH - 1
+                );
+                errors.push((pos_x_origin.clone(), message));
+                fits = false;
+            }
+            if rows.end > MATRIX_HEIGHT {
+                let message = format!(
+                    "`{}` is {} row(s) high, at `pos_y = {}` it would take {} but the last row is {}",
+                    id,
+                    height,
+                    pos_y,
+                    cells("row", &rows),
+                    MATRIX_HEIGHT - 1
+                );
+                errors.push((pos_y_origin.clone(), message));
+                fits = false;
+            }
+            if !fits {
+                continue;
+            }
+
+            for placed in &placements {
+                let overlap_columns = columns.start.max(placed.columns.start)..columns.end.min(placed.columns.end);
+                let overlap_rows = rows.start.max(placed.rows.start)..rows.end.min(placed.rows.end);
+                if overlap_columns.is_empty() || overlap_rows.is_empty() {
+                    continue;
+                }
+                let message = format!(
+                    "`{}` overlaps `{}` (at {}) in {}, {}",
+                    id,
+                    placed.id,
+                    self.location(placed.origin),
+                    cells("column", &overlap_columns),
+                    cells("row", &overlap_rows)
+                );
+                errors.push((plugin.origin.clone(), message));
+            }
+            placements.push(Placement {
+                id,
+                origin: &plugin.origin,
+                columns,
+                rows,
+            });
+        }
+
+        for (origin, message) in errors {
+            self.diagnostics.push((Severity::Error, Some(origin), message));
+        }
+    }
+}
+
+// "column 3" or "columns 3-5"
+fn cells(axis: &str, range: &Range<usize>) -> String {
+    if range.len() == 1 {
+        format!("{} {}", axis, range.start)
+    } else {
+        format!("{}s {}-{}", axis, range.start, range.end - 1)
+    }
+}
+
+// ", did you mean `x`?" for the closest candidate, when it's close enough to be a typo
+fn did_you_mean<'a>(word: &str, candidates: impl Iterator<Item = &'a str>) -> String {
+    let closest = candidates
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= 2 && *distance < candidate.len())
+        .min_by_key(|(distance, _)| *distance);
+    match closest {
+        Some((_, candidate)) => format!(", did you mean `{}`?", candidate),
+        None => String::new(),
+    }
+}
+
+// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod config_diagnostics_tests {
+    use std::fs;
+
+    use crate::config_diagnostics::{check_config, did_you_mean, Severity};
+
+    #[test]
+    fn reports_every_problem_with_its_location() {
+        let dir = std::env::temp_dir().join("fw-led-config-diagnostics");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("plugins")).unwrap();
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        fs::write(
+            dir.join("plugins/square.wasm"),
+            r#"(module
+                (memory (export "memory") 1)
+                (@custom "metadata" "{\"name\":\"square\",\"width\":3,\"height\":3}")
+                (func (export "draw") (result i32) (i32.const 0)))"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("config.toml"),
+            format!(
+                "plugin_paths = ['{}']\n\
+                 [[plugins]]\nname = 'square'\npos_x = 0\npos_y = 0\n\
+                 [[plugins]]\nname = 'square'\nid = 'moved'\npos_x = 0\npos_y = 20\n\
+                 [[plugins]]\nname = 'sqare'\nid = 'typo'\npox_x = 1\npos_y = 40\n\
+                 [[plugins]]\nname = 'square'\nid = 'edge'\npos_x = 7\npos_y = 10",
+                dir.join("plugins").display()
+            ),
+        )
+        .unwrap();
+        // Moves `moved` onto the first instance
+        fs::write(dir.join("conf.d/10-move.toml"), "[[plugins]]\nid = 'moved'\npos_x = 1\npos_y = 1").unwrap();
+
+        let diagnostics = check_config(Some(&dir.join("config.toml")))
+            .0
+            .iter()
+            .map(|diagnostic| diagnostic.to_string().replace(&format!("{}/", dir.display()), ""))
+            .collect::<Vec<String>>();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                "config.toml:6:1: error: `moved` overlaps `square` (at config.toml:2:1) in columns 1-2, rows 1-2",
+                "config.toml:11:1: error: plugin entry is missing `pos_x`",
+                "config.toml:12:8: error: plugin `sqare` not found in the search path, did you mean `square`?",
+                "config.toml:14:1: warning: unknown key `pox_x` in plugin entry, did you mean `pos_x`?",
+                "config.toml:15:9: error: `pos_y = 40` is outside of the matrix, its rows go from 0 to 33",
+                "config.toml:19:9: error: `edge` is 3 column(s) wide, at `pos_x = 7` it would take columns 7-9 but the last column is 8",
+            ]
+        );
+
+        fs::write(dir.join("conf.d/20-broken.toml"), "[[plugins]]\npos_x = = 1").unwrap();
+        let (diagnostics, _) = check_config(Some(&dir.join("config.toml")));
+        let syntax_error = diagnostics
+            .iter()
+            .find(|diagnostic| {
+                let location = diagnostic.location.as_ref();
+                location.is_some_and(|location| location.file.ends_with("conf.d/20-broken.toml"))
+            })
+            .unwrap();
+
+        assert_eq!(syntax_error.severity, Severity::Error);
+        assert_eq!(syntax_error.location.as_ref().map(|location| (location.line, location.column)), Some((2, 9)));
+        // Layout checks are skipped while a file can't be parsed
+        assert!(!diagnostics.iter().any(|diagnostic| diagnostic.message.contains("overlaps")));
+    }
+
+    #[test]
+    fn suggests_only_close_names() {
+        let keys = ["name", "pos_x", "pos_y", "permissions"];
+
+        assert_eq!(did_you_mean("permisions", keys.into_iter()), ", did you mean `permissions`?");
+        assert_eq!(did_you_mean("nmae", keys.into_iter()), ", did you mean `name`?");
+        assert_eq!(did_you_mean("colour", keys.into_iter()), "");
+    }
+}
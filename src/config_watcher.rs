@@ -0,0 +1,203 @@
+use std::ffi::{OsStr, OsString};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::thread::sleep;
+use std::time::Duration;
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use log::{error, info, warn};
+
+use crate::config::DROP_IN_DIR;
+use crate::controller::ControllerMessage;
+
+// Editors save in several steps, e.g. a backup file first. Reload once no change came in for that long.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+struct ConfigWatch {
+    inotify: Inotify,
+    config_dir: WatchDescriptor,
+    config_name: OsString,
+    drop_in_dir: PathBuf,
+    // Missing while the drop-in directory doesn't exist
+    drop_ins: Option<WatchDescriptor>,
+}
+
+// Watch the config file and its drop-in directory, and ask the controller to reload the configuration once edits
+// settle. The reload is validated like a SIGHUP one, a half-edited file keeps the running layout.
+pub fn spawn(tx: Sender<ControllerMessage>, config_file: &Path) {
+    let mut watch = match ConfigWatch::new(config_file) {
+        Ok(watch) => watch,
+        Err(err) => {
+            warn!("{}, configuration auto-reload is disabled", err);
+            return;
+        }
+    };
+    info!("Watching {} for configuration changes", config_file.display());
+
+    std::thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+        loop {
+            match watch.read_changes(&mut buffer, true) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) => {
+                    error!("Failed to read configuration events, configuration auto-reload stopped: {}", err);
+                    return;
+                }
+            }
+            loop {
+                sleep(DEBOUNCE);
+                match watch.read_changes(&mut buffer, false) {
+                    Ok(true) => continue,
+                    Ok(false) => break,
+                    Err(err) => {
+                        error!("Failed to read configuration events, configuration auto-reload stopped: {}", err);
+                        return;
+                    }
+                }
+            }
+
+            info!("Configuration changed, reloading");
+            if tx.send(ControllerMessage::ReloadConfig).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+// CREATE is needed to notice the drop-in directory showing up, files created in place also end with CLOSE_WRITE
+fn watch_mask() -> WatchMask {
+    WatchMask::CREATE | WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::MOVED_FROM | WatchMask::DELETE
+}
+
+impl ConfigWatch {
+    fn new(config_file: &Path) -> Result<Self, String> {
+        let config_name = config_file
+            .file_name()
+            .ok_or(format!("{} isn't a file path", config_file.display()))?;
+        let config_dir = match config_file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let inotify = Inotify::init().map_err(|err| format!("Failed to initialize inotify: {}", err))?;
+        // Editors writing a temporary file and renaming it over the config replace its inode,
+        // so the directories are watched rather than the files
+        let config_dir_watch = inotify
+            .watches()
+            .add(&config_dir, watch_mask())
+            .map_err(|err| format!("Failed to watch {}: {}", config_dir.display(), err))?;
+
+        let mut watch = Self {
+            inotify,
+            config_dir: config_dir_watch,
+            config_name: config_name.to_os_string(),
+            drop_in_dir: config_dir.join(DROP_IN_DIR),
+            drop_ins: None,
+        };
+        watch.watch_drop_ins();
+        Ok(watch)
+    }
+
+    // Watch the drop-in directory as it is now. A directory deleted or moved away since loses its watch,
+    // one created or moved in place gets a new one.
+    fn watch_drop_ins(&mut self) {
+        if let Some(previous) = self.drop_ins.take() {
+            // Already gone when the directory was deleted
+            let _ = self.inotify.watches().remove(previous);
+        }
+        if !self.drop_in_dir.is_dir() {
+            return;
+        }
+        match self.inotify.watches().add(&self.drop_in_dir, watch_mask()) {
+            Ok(watch) => self.drop_ins = Some(watch),
+            Err(err) => warn!("Failed to watch {} for configuration changes: {}", self.drop_in_dir.display(), err),
+        }
+    }
+
+    // Whether the events read concern the configuration. Doesn't wait for events unless `blocking` is set.
+    fn read_changes(&mut self, buffer: &mut [u8], blocking: bool) -> std::io::Result<bool> {
+        let events = if blocking {
+            self.inotify.read_events_blocking(buffer)
+        } else {
+            self.inotify.read_events(buffer)
+        };
+        let events = match events {
+            Ok(events) => events,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(err) => return Err(err),
+        };
+
+        let mut changed = false;
+        let mut drop_in_dir_changed = false;
+        for event in events {
+            let in_drop_in_dir = Some(&event.wd) == self.drop_ins.as_ref();
+            // The drop-in directory was deleted, its watch descriptor may be reused
+            if in_drop_in_dir && event.mask.contains(EventMask::IGNORED) {
+                self.drop_ins = None;
+                drop_in_dir_changed = true;
+                continue;
+            }
+            let Some(name) = event.name else {
+                continue;
+            };
+            if event.wd == self.config_dir && name == DROP_IN_DIR {
+                drop_in_dir_changed = true;
+            }
+            changed |= (event.wd == self.config_dir || in_drop_in_dir)
+                && is_config_file(name, in_drop_in_dir, &self.config_name);
+        }
+        // Created, deleted or moved after startup
+        if drop_in_dir_changed {
+            self.watch_drop_ins();
+        }
+        Ok(changed || drop_in_dir_changed)
+    }
+}
+
+// The config file itself in its directory, any `*.toml` drop-in in the drop-in directory
+fn is_config_file(name: &OsStr, in_drop_in_dir: bool, config_name: &OsStr) -> bool {
+    if in_drop_in_dir {
+        Path::new(name).extension().is_some_and(|extension| extension == "toml")
+    } else {
+        name == config_name
+    }
+}
+
+#[cfg(test)]
+mod config_watcher_tests {
+    use std::ffi::OsStr;
+    use std::fs;
+
+    use crate::config_watcher::{is_config_file, ConfigWatch};
+
+    #[test]
+    fn matches_config_file_and_drop_ins() {
+        let config_name = OsStr::new("config.toml");
+
+        assert!(is_config_file(OsStr::new("config.toml"), false, config_name));
+        assert!(is_config_file(OsStr::new("10-layout.toml"), true, config_name));
+        assert!(!is_config_file(OsStr::new("config.toml~"), false, config_name));
+        assert!(!is_config_file(OsStr::new("other.toml"), false, config_name));
+        assert!(!is_config_file(OsStr::new(".10-layout.toml.swp"), true, config_name));
+    }
+
+    #[test]
+    fn watches_recreated_drop_in_dir() {
+        let dir = std::env::temp_dir().join("fw-led-config-watch");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        fs::write(dir.join("config.toml"), "").unwrap();
+        let mut watch = ConfigWatch::new(&dir.join("config.toml")).unwrap();
+        let mut buffer = [0u8; 4096];
+
+        fs::remove_dir(dir.join("conf.d")).unwrap();
+        assert!(watch.read_changes(&mut buffer, false).unwrap());
+        assert!(watch.drop_ins.is_none());
+
+        fs::create_dir(dir.join("conf.d")).unwrap();
+        assert!(watch.read_changes(&mut buffer, false).unwrap());
+        fs::write(dir.join("conf.d/10-layout.toml"), "").unwrap();
+        assert!(watch.read_changes(&mut buffer, false).unwrap());
+    }
+}
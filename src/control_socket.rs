@@ -0,0 +1,193 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{chown, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+use crate::kv_bus::{KvValue, KV_BUS, MAX_KEY_LENGTH, MAX_STRING_LENGTH};
+use crate::plugin_worker::frame_stats_json;
+
+const DEFAULT_RUNTIME_DIR: &str = "/run/fw-led-stat-control";
+const SOCKET_NAME: &str = "control.sock";
+// Clients served at once, others are turned away
+const MAX_CLIENTS: usize = 8;
+// Namespace clients write to, so they can't overwrite the values of plugin instances
+pub(crate) const CONTROL_NAMESPACE: &str = "ctl";
+// Longest command the bus could accept: `set`, a key and a quoted string value
+const MAX_LINE_LENGTH: usize = MAX_KEY_LENGTH + MAX_STRING_LENGTH + 16;
+
+// systemd sets $RUNTIME_DIRECTORY for services with `RuntimeDirectory=`
+fn socket_path() -> PathBuf {
+    std::env::var_os("RUNTIME_DIRECTORY")
+        .map(PathBuf::from)
+        .unwrap_or(PathBuf::from(DEFAULT_RUNTIME_DIR))
+        .join(SOCKET_NAME)
+}
+
+// Serve the line based control protocol on a unix socket, so local scripts can write to the kv bus:
+//   set <key> <value>, get <key>, unset <key>, stats
+// Any key can be read, `set` and `unset` only take keys in `CONTROL_NAMESPACE`.
+// Only root and members of `gid` may connect.
+pub fn spawn(gid: Option<u32>) {
+    let path = socket_path();
+    // Socket left behind by a previous run
+    let _ = fs::remove_file(&path);
+
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            warn!("Failed to bind control socket {}, external kv writes are disabled: {}", path.display(), err);
+            return;
+        }
+    };
+    // Clients can overwrite any value plugins read, so the socket isn't open to every local user
+    if let Some(gid) = gid {
+        if let Err(err) = chown(&path, None, Some(gid)) {
+            warn!("Failed to set control socket group to {}: {}", gid, err);
+        }
+    }
+    if let Err(err) = fs::set_permissions(&path, fs::Permissions::from_mode(0o660)) {
+        warn!("Failed to set control socket permissions: {}", err);
+    }
+    info!("Listening for control commands on {}", path.display());
+
+    let clients = Arc::new(AtomicUsize::new(0));
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            if clients.fetch_add(1, Ordering::SeqCst) >= MAX_CLIENTS {
+                clients.fetch_sub(1, Ordering::SeqCst);
+                let _ = writeln!(stream, "error: too many clients");
+                continue;
+            }
+            let slot = ClientSlot(Arc::clone(&clients));
+            std::thread::spawn(move || {
+                let _slot = slot;
+                handle_client(stream)
+            });
+        }
+    });
+}
+
+// Frees the client's place once its thread ends, even by panicking
+struct ClientSlot(Arc<AtomicUsize>);
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn handle_client(stream: UnixStream) {
+    // Don't keep a thread around for clients which went silent
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+
+    let mut reader = BufReader::new(stream);
+    loop {
+        let line = match read_line(&mut reader) {
+            Ok(Some(line)) => line,
+            Ok(None) => return,
+            Err(err) => {
+                let _ = writeln!(writer, "error: {}", err);
+                return;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_command(&line);
+        debug!("Control command '{}': {}", line, response);
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+// Next line without its newline, or `None` once the client is done. Lines over the limit aren't buffered.
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>, String> {
+    let mut line = String::new();
+    // One byte over the limit is read to tell a line too long from one ending right at it
+    match reader.take(MAX_LINE_LENGTH as u64 + 1).read_line(&mut line) {
+        Ok(0) => Ok(None),
+        Ok(_) if line.ends_with('\n') => Ok(Some(line.trim_end_matches(['\r', '\n']).to_string())),
+        Ok(_) if line.len() > MAX_LINE_LENGTH => Err(format!("commands are limited to {} bytes", MAX_LINE_LENGTH)),
+        Ok(_) => Ok(Some(line)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+pub(crate) fn handle_command(line: &str) -> String {
+    let mut parts = line.trim().splitn(3, char::is_whitespace);
+    let command = parts.next().unwrap_or_default();
+    let key = parts.next().unwrap_or_default();
+    let value = parts.next();
+
+    let writable = key.strip_prefix(CONTROL_NAMESPACE).is_some_and(|name| name.starts_with('.'));
+    let mut kv_bus = KV_BUS.lock().unwrap();
+    match (command, value) {
+        ("set" | "unset", _) if !writable => {
+            format!("error: only keys in the '{0}' namespace can be written, e.g. '{0}.status'", CONTROL_NAMESPACE)
+        }
+        ("get", None) if !key.is_empty() => match kv_bus.get(key) {
+            Some(value) => value.to_string(),
+            None => format!("error: unknown key '{}'", key),
+        },
+        ("set", Some(value)) => match kv_bus.set(key, KvValue::parse(value)) {
+            Ok(()) => "ok".to_string(),
+            Err(err) => format!("error: {}", err),
+        },
+        ("unset", None) if !key.is_empty() => {
+            kv_bus.remove(key);
+            "ok".to_string()
+        }
+        ("stats", None) if key.is_empty() => frame_stats_json(),
+        _ => "error: expected 'get <key>', 'set <key> <value>', 'unset <key>' or 'stats'".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod control_socket_tests {
+    use std::io::Cursor;
+
+    use crate::control_socket::{handle_command, read_line, MAX_LINE_LENGTH};
+    use crate::kv_bus::{KvValue, KV_BUS};
+
+    #[test]
+    fn handles_kv_commands() {
+        assert_eq!(handle_command("set ctl.status \"passed\""), "ok");
+        assert_eq!(handle_command("set ctl.progress 75"), "ok");
+
+        assert_eq!(handle_command("get ctl.status"), "\"passed\"");
+        assert_eq!(handle_command("get ctl.progress"), "75");
+        assert_eq!(handle_command("unset ctl.progress"), "ok");
+        assert!(handle_command("get ctl.progress").starts_with("error"));
+        assert!(handle_command("set ctl.status").starts_with("error"));
+    }
+
+    #[test]
+    fn writes_only_to_control_namespace() {
+        KV_BUS.lock().unwrap().set("cpu.load", KvValue::Integer(40)).unwrap();
+
+        assert!(handle_command("set cpu.load 99").starts_with("error: only keys in the 'ctl' namespace"));
+        assert!(handle_command("set ctlx.load 99").starts_with("error"));
+        assert!(handle_command("unset cpu.load").starts_with("error"));
+        assert_eq!(handle_command("get cpu.load"), "40");
+    }
+
+    #[test]
+    fn limits_line_length() {
+        let longest = "x".repeat(MAX_LINE_LENGTH);
+        let mut reader = Cursor::new(format!("get a.b\n{}\n{}x\nget c.d\n", longest, longest));
+
+        assert_eq!(read_line(&mut reader), Ok(Some("get a.b".to_string())));
+        assert_eq!(read_line(&mut reader), Ok(Some(longest)));
+        assert!(read_line(&mut reader).is_err());
+    }
+}
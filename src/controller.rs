@@ -1,35 +1,180 @@
-use crate::canvas::Canvas;
-use crate::config::Config;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+use crate::canvas::{AddPainterError, Canvas};
+use crate::config::{Config, PluginConf};
 use crate::led_controller::LEDController;
+use crate::picture::Picture;
+use crate::plugin::Plugin;
+use crate::plugin_host::RemotePicture;
+use crate::plugin_state::{state_dir, PluginStates};
+use crate::stat_provider::system_stats;
+use crate::wasm_module::{compile_plugin, WasmModule};
+
+// Interval the daemon repaints the matrix at
+pub const PAINT_INTERVAL: Duration = Duration::from_millis(250);
+// Plugin states are collected and written this often while running, so a crash loses at most that much
+const PERSIST_INTERVAL: Duration = Duration::from_secs(60);
 
 pub struct Controller {
     canvas: Canvas,
+    // Configuration the canvas was built from, used to reload single plugins
+    config: Config,
+    // Set with `--config`, otherwise the default location is read
+    config_path: Option<PathBuf>,
+    states: PluginStates,
+    persisted_at: Instant,
     led_controller: LEDController,
 }
 
 impl Controller {
-    pub fn init() -> Self {
-        let config = Config::init();
+    pub fn init(config_path: Option<PathBuf>, device: &Path) -> Self {
+        let (config, modules) = Config::init(config_path.as_deref());
+        let states = PluginStates::load(state_dir());
+
+        let mut canvas = Canvas::load(&config, modules).unwrap_or_else(|err| {
+            error!("{}", err);
+            std::process::exit(1)
+        });
+        canvas.load_states(&states);
 
         Self {
-            canvas: config.into(),
-            led_controller: LEDController::init(),
+            canvas,
+            config,
+            config_path,
+            states,
+            persisted_at: Instant::now(),
+            led_controller: LEDController::init(device),
         }
     }
 
-    pub fn reload_config(&mut self) {
-        let config = Config::init();
+    // Load the new configuration and all of its plugins next to the running canvas, and only swap them
+    // once everything loaded. On error the running canvas and configuration are kept.
+    pub fn reload_config(&mut self) -> Result<(), String> {
+        let (config, modules) = Config::load_checked(self.config_path.as_deref())?;
+        let mut canvas = Canvas::load(&config, modules)?;
+
+        // The config file is looked up again on every reload, while auto-reload keeps watching the startup one
+        if let (Some(previous), Some(current)) = (self.config.sources.first(), config.sources.first()) {
+            if previous != current {
+                match self.config.auto_reload {
+                    true => warn!(
+                        "Configuration is now read from {} instead of {}, auto-reload keeps watching the latter \
+                         until the daemon restarts",
+                        current.display(),
+                        previous.display()
+                    ),
+                    false => {
+                        info!("Configuration is now read from {} instead of {}", current.display(), previous.display())
+                    }
+                }
+            }
+        }
+
+        self.canvas.save_states(&mut self.states);
+        self.canvas.shutdown();
+        canvas.load_states(&self.states);
+        self.states.persist();
+        self.canvas = canvas;
+        self.config = config;
+        Ok(())
+    }
+
+    // Recompile a plugin after its .wasm file changed and swap in all of its instances.
+    // Previous versions keep running if the new one fails to load.
+    pub fn reload_plugin(&mut self, name: &str) {
+        let plugin_confs = self
+            .config
+            .plugins
+            .iter()
+            .filter(|plugin| plugin.name == name)
+            .collect::<Vec<&PluginConf>>();
+        if plugin_confs.is_empty() {
+            return;
+        }
+
+        // Plugin hosts compile the module themselves
+        let plugin_paths = self.config.plugin_paths();
+        let module = if self.config.plugin_host.isolated {
+            None
+        } else {
+            match compile_plugin(&plugin_paths, name) {
+                Ok(module) => Some(module),
+                Err(err) => {
+                    error!("Failed to reload '{}' module, keeping the previous version: {}", name, err);
+                    return;
+                }
+            }
+        };
+
+        for plugin_conf in plugin_confs {
+            let id = plugin_conf.id();
+            let plugin = match &module {
+                Some(module) => WasmModule::instantiate(module, plugin_conf, system_stats())
+                    .map(|wasm_module| Plugin::new(plugin_conf, wasm_module))
+                    .map_err(|err| err.to_string()),
+                None => RemotePicture::spawn(plugin_conf, &self.config.plugin_host, &plugin_paths)
+                    .map(|remote| Plugin::from_remote(plugin_conf, remote)),
+            };
+            let mut plugin = match plugin {
+                Ok(plugin) => plugin,
+                Err(err) => {
+                    error!("Failed to reload '{}' module, keeping the previous version: {}", id, err);
+                    continue;
+                }
+            };
 
-        self.canvas = config.into()
+            if let Some(state) = self.canvas.plugins.get_mut(id).and_then(Plugin::save_state) {
+                self.states.insert(id, state);
+            }
+            if let Some(state) = self.states.get(id) {
+                plugin.load_state(state);
+            }
+
+            match self.canvas.replace_plugin(plugin) {
+                Ok(previous) => {
+                    if let Some(mut previous) = previous {
+                        previous.shutdown();
+                    }
+                    info!("Reloaded '{}' module", id);
+                }
+                Err(AddPainterError::SpaceTaken) => {
+                    error!("No Matrix space left for reloaded plugin: {}, keeping the previous version.", id);
+                }
+                Err(AddPainterError::OutOfBounds) => {
+                    error!("Reloaded plugin {} doesn't fit in the Matrix at its offset, keeping the previous version.", id);
+                }
+                Err(AddPainterError::DuplicateIdentifier) => {
+                    error!("Duplicate identifier for plugin: {}.", id);
+                }
+            }
+        }
+        self.states.persist();
+    }
+
+    pub fn shutdown(&mut self) {
+        self.canvas.save_states(&mut self.states);
+        self.states.persist();
+        self.canvas.shutdown()
     }
 
     pub fn schedule_paint(&mut self) {
         let matrix = self.canvas.paint_matrix();
-        self.led_controller.draw_matrix(matrix)
+        self.led_controller.draw_matrix(matrix);
+
+        if self.persisted_at.elapsed() >= PERSIST_INTERVAL {
+            self.canvas.save_states(&mut self.states);
+            self.states.persist();
+            self.persisted_at = Instant::now();
+        }
     }
 }
 
 pub enum ControllerMessage {
     ReloadConfig,
+    // A plugin .wasm file changed on disk
+    ReloadPlugin(String),
     Terminate,
 }
@@ -0,0 +1,191 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+pub use crate::config::PluginConf;
+use crate::controller::PAINT_INTERVAL;
+pub use crate::matrix::Matrix;
+pub use crate::stat_provider::{HostStats, RecordedStats};
+use crate::stat_provider::ScriptedStats;
+pub use crate::wasm_module::WASMError;
+use crate::wasm_module::{compile, compile_file, CompiledPlugin, WasmModule};
+
+// Set to rewrite snapshot files with the frames a test produced
+const UPDATE_SNAPSHOTS_VAR: &str = "UPDATE_SNAPSHOTS";
+
+// Runs a plugin without the daemon, LED hardware or system APIs. Stat imports return scripted values
+// and time only moves forward by `PAINT_INTERVAL` per drawn frame. Plugin crates can use it from their tests,
+// with this crate as a dev-dependency.
+pub struct PluginHarness {
+    module: WasmModule,
+    stats: Arc<ScriptedStats>,
+    now: Instant,
+}
+
+impl PluginHarness {
+    // Load a `.wasm` module or a `.fwled` bundle
+    pub fn load(path: &Path, plugin_conf: &PluginConf) -> Result<Self, WASMError> {
+        Self::from_compiled(&compile_file(path)?, plugin_conf)
+    }
+
+    pub fn from_bytes(bytes: &[u8], plugin_conf: &PluginConf) -> Result<Self, WASMError> {
+        Self::from_compiled(&compile(bytes)?, plugin_conf)
+    }
+
+    fn from_compiled(compiled: &CompiledPlugin, plugin_conf: &PluginConf) -> Result<Self, WASMError> {
+        let stats = Arc::new(ScriptedStats::default());
+        let module = WasmModule::instantiate(compiled, plugin_conf, stats.clone())?;
+
+        Ok(Self {
+            module,
+            stats,
+            now: Instant::now(),
+        })
+    }
+
+    // Draw one frame per entry, with stat imports returning that entry's values. Stops at the first failed frame.
+    pub fn run(&mut self, script: &[HostStats]) -> Result<Vec<Matrix>, WASMError> {
+        script.iter().map(|stats| self.draw(*stats)).collect()
+    }
+
+    // Draw one frame per sample of a recording, e.g. one saved with `RecordedStats::save`
+    pub fn replay(&mut self, recording: &RecordedStats) -> Result<Vec<Matrix>, WASMError> {
+        self.run(recording.samples())
+    }
+
+    pub fn draw(&mut self, stats: HostStats) -> Result<Matrix, WASMError> {
+        self.stats.set(stats);
+        self.now += PAINT_INTERVAL;
+        self.module.try_draw_at(self.now)
+    }
+}
+
+// Frames as printed by `Matrix`, separated by empty lines
+pub fn render_frames(frames: &[Matrix]) -> String {
+    let frames = frames
+        .iter()
+        .map(|frame| frame.to_string().replace("\r\n", "\n"))
+        .collect::<Vec<String>>();
+    format!("{}\n", frames.join("\n\n"))
+}
+
+// Compare frames against a stored snapshot, or store them when `UPDATE_SNAPSHOTS` is set
+pub fn compare_snapshot(frames: &[Matrix], path: &Path) -> Result<(), String> {
+    let actual = render_frames(frames);
+
+    if std::env::var_os(UPDATE_SNAPSHOTS_VAR).is_some() {
+        return fs::write(path, actual)
+            .map_err(|err| format!("failed to write snapshot {}: {}", path.display(), err));
+    }
+
+    let expected = fs::read_to_string(path).map_err(|err| {
+        format!(
+            "failed to read snapshot {}: {}, run with {}=1 to create it",
+            path.display(),
+            err,
+            UPDATE_SNAPSHOTS_VAR
+        )
+    })?;
+    if expected == actual {
+        return Ok(());
+    }
+
+    let expected = expected.split("\n\n").collect::<Vec<&str>>();
+    let actual = actual.split("\n\n").collect::<Vec<&str>>();
+    let mismatch = (0..expected.len().max(actual.len()))
+        .find(|&index| expected.get(index).map(|frame| frame.trim_end()) != actual.get(index).map(|frame| frame.trim_end()))
+        .unwrap_or(0);
+    Err(format!(
+        "frame {} differs from snapshot {}\nexpected:\n{}\nactual:\n{}",
+        mismatch,
+        path.display(),
+        expected.get(mismatch).unwrap_or(&"<none>"),
+        actual.get(mismatch).unwrap_or(&"<none>")
+    ))
+}
+
+pub fn assert_snapshot(frames: &[Matrix], path: &Path) {
+    if let Err(err) = compare_snapshot(frames, path) {
+        panic!("{}", err);
+    }
+}
+
+#[cfg(test)]
+mod harness_tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::config::PluginConf;
+    use crate::harness::{assert_snapshot, compare_snapshot, render_frames, PluginHarness};
+    use crate::matrix::Matrix;
+    use crate::stat_provider::{HostStats, RecordedStats};
+
+    // Lights the first pixel above 50% CPU usage, the second one on odd seconds and
+    // the third one once `tick` has seen 500ms pass
+    const GAUGE_PLUGIN: &str = r#"(module
+        (import "env" "get_global_cpu_usage" (func $cpu (result f32)))
+        (import "env" "get_epoch_time" (func $time (result i64)))
+        (memory (export "memory") 1)
+        (global $elapsed (mut i32) (i32.const 0))
+        (@custom "metadata" "{\"name\":\"gauge\",\"width\":3,\"height\":1,\"permissions\":[\"cpu\",\"time\"]}")
+        (func (export "tick") (param $dt_ms i32)
+            (global.set $elapsed (i32.add (global.get $elapsed) (local.get $dt_ms))))
+        (func (export "draw") (result i32)
+            (i32.store8 (i32.const 0) (i32.mul (f32.gt (call $cpu) (f32.const 50)) (i32.const 255)))
+            (i32.store8 (i32.const 1) (i32.mul (i32.wrap_i64 (i64.rem_u (call $time) (i64.const 2))) (i32.const 255)))
+            (i32.store8 (i32.const 2) (i32.mul (i32.ge_u (global.get $elapsed) (i32.const 500)) (i32.const 255)))
+            (i32.const 0)))"#;
+
+    fn plugin_conf() -> PluginConf {
+        toml::from_str("name = 'gauge'\npos_x = 0\npos_y = 0\npermissions = ['cpu', 'time']").unwrap()
+    }
+
+    fn stats(global_cpu_usage: f32, epoch_time: u64) -> HostStats {
+        HostStats {
+            global_cpu_usage,
+            epoch_time,
+            ..HostStats::default()
+        }
+    }
+
+    #[test]
+    fn draws_frames_with_scripted_stats() {
+        let mut harness = PluginHarness::from_bytes(GAUGE_PLUGIN.as_bytes(), &plugin_conf()).unwrap();
+
+        let frames = harness.run(&[stats(80.0, 10), stats(20.0, 11), stats(20.0, 12)]).unwrap();
+
+        let pixels = frames
+            .iter()
+            .map(|frame| [frame.get_el(0, 0), frame.get_el(0, 1), frame.get_el(0, 2)])
+            .collect::<Vec<[u8; 3]>>();
+        assert_eq!(pixels, vec![[255, 0, 0], [0, 255, 0], [0, 0, 255]]);
+        assert_snapshot(&frames, &Path::new(env!("CARGO_MANIFEST_DIR")).join("src/snapshots/gauge.txt"));
+    }
+
+    #[test]
+    fn replays_recorded_stats() {
+        let path = std::env::temp_dir().join("fw-led-harness-recording.jsonl");
+        RecordedStats::save(&[stats(80.0, 10), stats(20.0, 11), stats(20.0, 12)], &path).unwrap();
+        let recording = RecordedStats::load(&path).unwrap();
+        let mut harness = PluginHarness::from_bytes(GAUGE_PLUGIN.as_bytes(), &plugin_conf()).unwrap();
+
+        let frames = harness.replay(&recording).unwrap();
+
+        assert_snapshot(&frames, &Path::new(env!("CARGO_MANIFEST_DIR")).join("src/snapshots/gauge.txt"));
+    }
+
+    #[test]
+    fn reports_first_frame_differing_from_snapshot() {
+        let dir = std::env::temp_dir().join("fw-led-harness");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("gauge.wasm"), GAUGE_PLUGIN).unwrap();
+        fs::write(dir.join("snapshot.txt"), render_frames(&[Matrix::default(), Matrix::default()])).unwrap();
+        let mut harness = PluginHarness::load(&dir.join("gauge.wasm"), &plugin_conf()).unwrap();
+        let frames = harness.run(&[stats(0.0, 10), stats(0.0, 11)]).unwrap();
+
+        let err = compare_snapshot(&frames, &dir.join("snapshot.txt")).unwrap_err();
+
+        assert!(err.starts_with("frame 1 differs"), "{}", err);
+    }
+}
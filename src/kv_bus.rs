@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::sync::{LazyLock, Mutex};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use wasmer::FunctionEnvMut;
+
+use crate::plugin_log::read_utf8;
+use crate::wasm_module::PluginEnv;
+
+// Limits keeping external writers from growing the bus without bounds
+const MAX_KEYS: usize = 1024;
+pub(crate) const MAX_KEY_LENGTH: usize = 128;
+pub(crate) const MAX_STRING_LENGTH: usize = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum KvValue {
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum KvError {
+    InvalidKey(String),
+    TooManyKeys,
+    ValueTooLong,
+}
+
+// Values shared between plugins and external writers, keyed by `<namespace>.<name>`.
+// Plugins write to the namespace matching their name, and can read any key.
+#[derive(Default)]
+pub struct KvBus {
+    values: HashMap<String, KvValue>,
+    // Incremented on every change, so out-of-process plugin hosts are only sent updated values
+    version: u64,
+    // Writes made inside an out-of-process plugin host, forwarded to the daemon with each frame
+    journal: Option<Vec<(String, KvValue)>>,
+}
+
+pub static KV_BUS: LazyLock<Mutex<KvBus>> = LazyLock::new(|| Mutex::new(KvBus::default()));
+
+impl Display for KvValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            KvValue::Integer(value) => write!(f, "{}", value),
+            KvValue::Float(value) => write!(f, "{}", value),
+            KvValue::String(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+impl Display for KvError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::InvalidKey(key) => write!(
+                f,
+                "invalid key '{}', expected '<namespace>.<name>' of up to {} characters",
+                key, MAX_KEY_LENGTH
+            ),
+            KvError::TooManyKeys => write!(f, "key limit of {} reached", MAX_KEYS),
+            KvError::ValueTooLong => write!(f, "values are limited to {} bytes", MAX_STRING_LENGTH),
+        }
+    }
+}
+
+impl KvValue {
+    // Integers and floats are stored as numbers, anything else as a string.
+    // Surrounding double quotes are stripped, so `"42"` stays a string.
+    pub fn parse(value: &str) -> Self {
+        let value = value.trim();
+        if let Some(quoted) = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+            return KvValue::String(quoted.to_string());
+        }
+        if let Ok(value) = value.parse::<i64>() {
+            return KvValue::Integer(value);
+        }
+        match value.parse::<f64>() {
+            Ok(value) => KvValue::Float(value),
+            Err(_) => KvValue::String(value.to_string()),
+        }
+    }
+}
+
+impl KvBus {
+    pub fn get(&self, key: &str) -> Option<&KvValue> {
+        self.values.get(key)
+    }
+
+    pub fn set(&mut self, key: &str, value: KvValue) -> Result<(), KvError> {
+        let has_namespace = key
+            .split_once('.')
+            .is_some_and(|(namespace, name)| !namespace.is_empty() && !name.is_empty());
+        if !has_namespace || key.len() > MAX_KEY_LENGTH || key.contains(char::is_whitespace) {
+            return Err(KvError::InvalidKey(key.to_string()));
+        }
+        if matches!(&value, KvValue::String(value) if value.len() > MAX_STRING_LENGTH) {
+            return Err(KvError::ValueTooLong);
+        }
+        if !self.values.contains_key(key) && self.values.len() >= MAX_KEYS {
+            return Err(KvError::TooManyKeys);
+        }
+
+        if let Some(journal) = &mut self.journal {
+            journal.push((key.to_string(), value.clone()));
+        }
+        self.values.insert(key.to_string(), value);
+        self.version += 1;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<KvValue> {
+        let removed = self.values.remove(key);
+        if removed.is_some() {
+            self.version += 1;
+        }
+        removed
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, KvValue> {
+        self.values.clone()
+    }
+
+    // Take over values from the daemon, in an out-of-process plugin host
+    pub fn replace(&mut self, values: HashMap<String, KvValue>) {
+        self.values = values;
+        self.version += 1;
+    }
+
+    pub fn enable_journal(&mut self) {
+        self.journal = Some(vec![]);
+    }
+
+    pub fn take_journal(&mut self) -> Vec<(String, KvValue)> {
+        self.journal.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+}
+
+// Host imports reading fully qualified keys, e.g. `build.status`. Keys are passed as UTF-8 (ptr, len) pairs.
+pub fn kv_get_i64(env: FunctionEnvMut<PluginEnv>, key_ptr: i32, key_len: i32) -> i64 {
+    match lookup(&env, key_ptr, key_len) {
+        Some(KvValue::Integer(value)) => value,
+        Some(KvValue::Float(value)) => value as i64,
+        _ => 0,
+    }
+}
+
+pub fn kv_get_f64(env: FunctionEnvMut<PluginEnv>, key_ptr: i32, key_len: i32) -> f64 {
+    match lookup(&env, key_ptr, key_len) {
+        Some(KvValue::Float(value)) => value,
+        Some(KvValue::Integer(value)) => value as f64,
+        _ => 0.0,
+    }
+}
+
+// Copies up to `buf_len` bytes of a string value and returns its full length, or -1 if missing
+pub fn kv_get_string(
+    env: FunctionEnvMut<PluginEnv>,
+    key_ptr: i32,
+    key_len: i32,
+    buf_ptr: i32,
+    buf_len: i32,
+) -> i32 {
+    let Some(KvValue::String(value)) = lookup(&env, key_ptr, key_len) else {
+        return -1;
+    };
+    let Some(memory) = &env.data().memory else {
+        return -1;
+    };
+
+    let bytes = value.as_bytes();
+    let len = bytes.len().min(buf_len.max(0) as usize);
+    match memory.view(&env).write(buf_ptr as u32 as u64, &bytes[..len]) {
+        Ok(()) => bytes.len() as i32,
+        Err(_) => -1,
+    }
+}
+
+// Host imports writing to the plugin namespace, `count` set by plugin `cpu` ends up as `cpu.count`
+pub fn kv_set_i64(env: FunctionEnvMut<PluginEnv>, key_ptr: i32, key_len: i32, value: i64) {
+    store(&env, key_ptr, key_len, KvValue::Integer(value))
+}
+
+pub fn kv_set_f64(env: FunctionEnvMut<PluginEnv>, key_ptr: i32, key_len: i32, value: f64) {
+    store(&env, key_ptr, key_len, KvValue::Float(value))
+}
+
+pub fn kv_set_string(
+    env: FunctionEnvMut<PluginEnv>,
+    key_ptr: i32,
+    key_len: i32,
+    value_ptr: i32,
+    value_len: i32,
+) {
+    let Some(memory) = &env.data().memory else {
+        return;
+    };
+    match read_utf8(&memory.view(&env), value_ptr, value_len) {
+        Ok(value) => store(&env, key_ptr, key_len, KvValue::String(value)),
+        Err(err) => warn!(target: "WASM", "'{}' module passed invalid kv value: {}", env.data().name, err),
+    }
+}
+
+fn read_key(env: &FunctionEnvMut<PluginEnv>, key_ptr: i32, key_len: i32) -> Option<String> {
+    let memory = env.data().memory.as_ref()?;
+    read_utf8(&memory.view(env), key_ptr, key_len).ok()
+}
+
+pub(crate) fn lookup(env: &FunctionEnvMut<PluginEnv>, key_ptr: i32, key_len: i32) -> Option<KvValue> {
+    let key = read_key(env, key_ptr, key_len)?;
+    KV_BUS.lock().unwrap().get(&key).cloned()
+}
+
+pub(crate) fn store(env: &FunctionEnvMut<PluginEnv>, key_ptr: i32, key_len: i32, value: KvValue) {
+    let Some(key) = read_key(env, key_ptr, key_len) else {
+        return;
+    };
+    let name = &env.data().name;
+    if let Err(err) = KV_BUS.lock().unwrap().set(&format!("{}.{}", name, key), value) {
+        warn!(target: "WASM", "'{}' module failed to set kv value: {}", name, err);
+    }
+}
+
+#[cfg(test)]
+mod kv_bus_tests {
+    use crate::kv_bus::{KvBus, KvError, KvValue};
+
+    #[test]
+    fn parses_typed_values() {
+        assert_eq!(KvValue::parse("42"), KvValue::Integer(42));
+        assert_eq!(KvValue::parse(" 0.5 "), KvValue::Float(0.5));
+        assert_eq!(KvValue::parse("\"42\""), KvValue::String("42".to_string()));
+        assert_eq!(KvValue::parse("failed"), KvValue::String("failed".to_string()));
+    }
+
+    #[test]
+    fn requires_namespaced_keys() {
+        let mut bus = KvBus::default();
+
+        assert_eq!(bus.set("build.status", KvValue::parse("ok")), Ok(()));
+        assert_eq!(
+            bus.set("status", KvValue::Integer(1)),
+            Err(KvError::InvalidKey("status".to_string()))
+        );
+        assert_eq!(bus.get("build.status"), Some(&KvValue::String("ok".to_string())));
+    }
+
+    #[test]
+    fn bumps_version_only_on_changes() {
+        let mut bus = KvBus::default();
+        bus.set("build.status", KvValue::Integer(1)).unwrap();
+        let version = bus.version();
+
+        bus.remove("build.missing");
+        assert_eq!(bus.version(), version);
+        bus.remove("build.status");
+        assert_eq!(bus.version(), version + 1);
+    }
+}
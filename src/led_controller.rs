@@ -1,4 +1,5 @@
 use std::io::Write;
+use std::path::Path;
 use std::time::Duration;
 
 use log::error;
@@ -10,19 +11,23 @@ pub struct LEDController {
     port: Box<dyn SerialPort>,
 }
 
-const LED_PORT_PATH: &'static str = "/dev/ttyACM0";
+pub const LED_PORT_PATH: &str = "/dev/ttyACM0";
 
 impl LEDController {
-    pub fn init() -> Self {
-        let port = serialport::new(LED_PORT_PATH, 115_200)
+    pub fn init(device: &Path) -> Self {
+        Self::open(device).unwrap_or_else(|err| {
+            error!("{}", err);
+            std::process::exit(1)
+        })
+    }
+
+    pub fn open(device: &Path) -> Result<Self, String> {
+        let port = serialport::new(device.to_string_lossy(), 115_200)
             .timeout(Duration::from_secs(3))
             .open()
-            .unwrap_or_else(|err| {
-                error!("Failed to open LED Matrix Serial Port {}", err);
-                std::process::exit(1)
-            });
+            .map_err(|err| format!("Failed to open LED Matrix Serial Port {}: {}", device.display(), err))?;
 
-        Self { port }
+        Ok(Self { port })
     }
 
     pub fn draw_matrix(&mut self, matrix: Matrix) {
@@ -0,0 +1,29 @@
+mod bundle;
+mod canvas;
+pub mod cli;
+mod component;
+pub mod config;
+mod config_diagnostics;
+pub mod config_watcher;
+pub mod control_socket;
+pub mod controller;
+pub mod harness;
+mod kv_bus;
+mod led_controller;
+pub mod matrix;
+mod permissions;
+mod picture;
+mod plugin;
+pub mod plugin_inspect;
+pub mod plugin_host;
+mod plugin_log;
+mod plugin_paths;
+mod plugin_state;
+pub mod plugin_watcher;
+mod plugin_worker;
+mod scaffold;
+mod settings;
+pub mod stat_provider;
+mod system_stat_monitor;
+mod wasi;
+pub mod wasm_module;
@@ -1,9 +1,10 @@
 use std::io::Error;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
 use std::thread::sleep;
-use std::time::Duration;
 
+use clap::Parser;
 use log::{error, info};
 use sd_notify::NotifyState;
 use signal_hook::consts::{SIGHUP, TERM_SIGNALS};
@@ -11,40 +12,86 @@ use signal_hook::flag;
 use signal_hook::iterator::exfiltrator::WithOrigin;
 use signal_hook::iterator::SignalsInfo;
 
-use crate::controller::{Controller, ControllerMessage};
-
-mod canvas;
-mod config;
-mod controller;
-mod led_controller;
-mod matrix;
-mod picture;
-mod plugin;
-mod system_stat_monitor;
-mod wasm_module;
+use fw_led_stat_control::cli::{Cli, Command};
+use fw_led_stat_control::config::Config;
+use fw_led_stat_control::controller::{Controller, ControllerMessage, PAINT_INTERVAL};
+use fw_led_stat_control::{cli, config_watcher, control_socket, plugin_host, plugin_inspect, plugin_watcher};
 
 fn main() -> Result<(), Error> {
-    env_logger::init();
+    let cli = Cli::parse();
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(level) = cli.log_level {
+        logger.filter_level(level);
+    }
+    logger.init();
+
+    let config_path = cli.config.as_deref();
+    let result = match cli.command.unwrap_or(Command::Run) {
+        Command::Run => return run(cli.config.clone(), &cli.device),
+        Command::PluginHost => return plugin_host::run(),
+        Command::RenderOnce { send } => cli::render_once(config_path, &cli.device, send),
+        Command::Preview => cli::preview(config_path),
+        Command::ValidateConfig => cli::validate_config(config_path),
+        Command::Bundle(command) => cli::run_bundle_command(command),
+        Command::InspectPlugin { plugin } => plugin_inspect::inspect_plugin(&plugin),
+        Command::NewPlugin { name, width, height, language, output, sdk_path } => {
+            cli::new_plugin(&name, width, height, language, output, sdk_path.as_deref())
+        }
+        Command::ListPlugins => {
+            cli::list_plugins(&Config::plugin_paths_or_default(config_path));
+            Ok(())
+        }
+    };
+    result.map_err(|err| {
+        error!("{}", err);
+        Error::other(err)
+    })
+}
 
+// Run the daemon until a termination signal
+fn run(config_path: Option<PathBuf>, device: &Path) -> Result<(), Error> {
     let (tx, rx) = std::sync::mpsc::channel::<ControllerMessage>();
+    plugin_watcher::spawn(tx.clone(), &Config::plugin_paths_or_default(config_path.as_deref()));
+    // An invalid configuration stops the controller at startup, the watcher is only needed with a valid one
+    let config = Config::load(config_path.as_deref()).ok();
+    if let Some(config) = &config {
+        if let (true, Some(config_file)) = (config.auto_reload, config.sources.first()) {
+            config_watcher::spawn(tx.clone(), config_file);
+        }
+    }
+    control_socket::spawn(config.and_then(|config| config.control_socket.gid));
 
     // Worker loop that handles LED controls
+    let device = device.to_path_buf();
     let handle = std::thread::spawn(move || {
-        let mut controller = Controller::init();
+        let mut controller = Controller::init(config_path, &device);
         loop {
             if let Ok(message) = rx.try_recv() {
                 match message {
                     ControllerMessage::ReloadConfig => {
-                        controller.reload_config();
-                        sd_notify::notify(true, &[NotifyState::Ready]).unwrap();
+                        let status = match controller.reload_config() {
+                            Ok(()) => {
+                                info!("Configuration reloaded");
+                                "Configuration reloaded".to_string()
+                            }
+                            Err(err) => {
+                                error!("Failed to reload configuration, keeping the running one: {}", err);
+                                format!("Reload failed, keeping the running configuration: {}", single_line(&err))
+                            }
+                        };
+                        sd_notify::notify(false, &[NotifyState::Ready, NotifyState::Status(&status)]).unwrap();
+                    }
+                    ControllerMessage::ReloadPlugin(name) => controller.reload_plugin(&name),
+                    ControllerMessage::Terminate => {
+                        controller.shutdown();
+                        break;
                     }
-                    ControllerMessage::Terminate => break,
                 }
             }
 
             controller.schedule_paint();
 
-            sleep(Duration::from_millis(250))
+            sleep(PAINT_INTERVAL)
         }
     });
 
@@ -62,15 +109,16 @@ fn main() -> Result<(), Error> {
     sigs.extend(TERM_SIGNALS);
     let mut signals = SignalsInfo::<WithOrigin>::new(&sigs)?;
 
-    sd_notify::notify(true, &[NotifyState::Ready]).unwrap();
+    // NOTIFY_SOCKET is kept in the environment, reloads notify systemd again
+    sd_notify::notify(false, &[NotifyState::Ready]).unwrap();
 
     loop {
         for origin in signals.pending() {
             match origin.signal {
                 SIGHUP => {
                     info!("Reloading configuration file");
-                    sd_notify::notify(true, &[NotifyState::Reloading]).unwrap();
-                    sd_notify::notify(true, &[NotifyState::monotonic_usec_now().unwrap()]).unwrap();
+                    sd_notify::notify(false, &[NotifyState::Reloading]).unwrap();
+                    sd_notify::notify(false, &[NotifyState::monotonic_usec_now().unwrap()]).unwrap();
 
                     tx.send(ControllerMessage::ReloadConfig).unwrap();
                 }
@@ -92,3 +140,13 @@ fn main() -> Result<(), Error> {
         }
     }
 }
+
+// sd_notify STATUS is a single line, configuration errors are reported one per line
+fn single_line(message: &str) -> String {
+    message
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>()
+        .join("; ")
+}
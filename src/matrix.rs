@@ -51,6 +51,10 @@ impl Matrix {
         Matrix { data: output }
     }
 
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn get_el(&self, row: usize, col: usize) -> u8 {
         self.data[row * MATRIX_WIDTH + col]
     }
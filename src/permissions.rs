@@ -0,0 +1,110 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::component::STATS_INTERFACE;
+
+// Capabilities plugins declare in metadata and users approve per plugin in config.toml
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Capability {
+    Battery,
+    Cpu,
+    Memory,
+    Time,
+    // WASI preopened directory
+    Fs,
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Capability::Battery => "battery",
+            Capability::Cpu => "cpu",
+            Capability::Memory => "memory",
+            Capability::Time => "time",
+            Capability::Fs => "fs",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Stat capabilities every plugin had before they were gated. Modules declaring no `permissions` in their metadata
+// were built before that and are granted these without approval, so they keep loading with existing configs.
+pub const LEGACY_CAPABILITIES: [Capability; 4] =
+    [Capability::Battery, Capability::Cpu, Capability::Memory, Capability::Time];
+
+// Capability a host import is gated behind. Imports without one are available to every plugin.
+pub fn required_capability(module: &str, name: &str) -> Option<Capability> {
+    match (module, name) {
+        ("env", "get_battery_state_of_charge") => Some(Capability::Battery),
+        ("env", "get_global_cpu_usage") => Some(Capability::Cpu),
+        ("env", "get_memory_usage") => Some(Capability::Memory),
+        ("env", "get_epoch_time") => Some(Capability::Time),
+        (STATS_INTERFACE, "battery-state-of-charge") => Some(Capability::Battery),
+        (STATS_INTERFACE, "global-cpu-usage") => Some(Capability::Cpu),
+        (STATS_INTERFACE, "memory-usage") => Some(Capability::Memory),
+        (STATS_INTERFACE, "epoch-time") => Some(Capability::Time),
+        _ => None,
+    }
+}
+
+// Grant requested capabilities, as long as the user approved all of them.
+// Returns the unapproved ones otherwise. `None` is a module without `permissions`, see `LEGACY_CAPABILITIES`.
+pub fn grant_permissions(
+    requested: Option<&[Capability]>,
+    approved: &[Capability],
+) -> Result<HashSet<Capability>, Vec<Capability>> {
+    let Some(requested) = requested else {
+        return Ok(HashSet::from(LEGACY_CAPABILITIES));
+    };
+    let mut unapproved = requested
+        .iter()
+        .filter(|capability| !approved.contains(capability))
+        .copied()
+        .collect::<Vec<Capability>>();
+
+    if unapproved.is_empty() {
+        Ok(requested.iter().copied().collect())
+    } else {
+        unapproved.sort();
+        unapproved.dedup();
+        Err(unapproved)
+    }
+}
+
+#[cfg(test)]
+mod permissions_tests {
+    use std::collections::HashSet;
+
+    use crate::permissions::{grant_permissions, Capability, LEGACY_CAPABILITIES};
+
+    #[test]
+    fn grants_only_requested_capabilities() {
+        let granted = grant_permissions(
+            Some(&[Capability::Time]),
+            &[Capability::Time, Capability::Battery],
+        );
+
+        assert_eq!(granted, Ok(HashSet::from([Capability::Time])));
+    }
+
+    #[test]
+    fn rejects_unapproved_capabilities() {
+        let granted = grant_permissions(
+            Some(&[Capability::Fs, Capability::Time, Capability::Battery]),
+            &[Capability::Time],
+        );
+
+        assert_eq!(granted, Err(vec![Capability::Battery, Capability::Fs]));
+    }
+
+    #[test]
+    fn grants_stats_to_modules_without_permissions() {
+        let granted = grant_permissions(None, &[]);
+
+        assert_eq!(granted, Ok(HashSet::from(LEGACY_CAPABILITIES)));
+    }
+}
@@ -1,5 +1,21 @@
+use std::time::Instant;
+
 use crate::matrix::Matrix;
 
 pub trait Picture {
+    // Start drawing the next frame, which `draw` has to return by the deadline.
+    // Pictures drawing synchronously do all the work in `draw`.
+    fn begin_draw(&mut self, _deadline: Instant) {}
+
     fn draw(&mut self) -> Matrix;
+
+    // Release resources before the picture is dropped, e.g. on configuration reload
+    fn shutdown(&mut self) {}
+
+    // Snapshot of internal state to carry over to the next instance of the picture
+    fn save_state(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn load_state(&mut self, _state: &[u8]) {}
 }
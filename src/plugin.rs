@@ -1,12 +1,17 @@
+use std::time::Instant;
+
 use serde::Serialize;
 
 use crate::config::PluginConf;
-use crate::matrix::{EMPTY_MATRIX, Matrix, MATRIX_WIDTH};
+use crate::matrix::{EMPTY_MATRIX, Matrix, MATRIX_HEIGHT, MATRIX_WIDTH};
 use crate::picture::Picture;
+use crate::plugin_host::RemotePicture;
+use crate::plugin_worker::PluginWorker;
 use crate::wasm_module::WasmModule;
 
 #[derive(Serialize)]
 pub struct Plugin {
+    // Instance identifier, the `id` from config or the plugin name
     pub(crate) name: String,
     #[serde(skip)]
     pub(crate) img_height: usize,
@@ -21,24 +26,56 @@ pub struct Plugin {
 }
 
 impl Picture for Plugin {
+    fn begin_draw(&mut self, deadline: Instant) {
+        self.drawer.begin_draw(deadline)
+    }
+
     fn draw(&mut self) -> Matrix {
         self.drawer.draw()
     }
+
+    fn shutdown(&mut self) {
+        self.drawer.shutdown()
+    }
+
+    fn save_state(&mut self) -> Option<Vec<u8>> {
+        self.drawer.save_state()
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        self.drawer.load_state(state)
+    }
 }
 
 impl Plugin {
-    pub(crate) fn from_plugin_config(plugin_conf: PluginConf) -> Self {
-        let wasm_module = WasmModule::from(plugin_conf.name.as_str());
+    pub(crate) fn new(plugin_conf: &PluginConf, wasm_module: WasmModule) -> Self {
         Self {
-            name: plugin_conf.name,
+            name: plugin_conf.id().to_string(),
             img_height: wasm_module.metadata.height,
             img_width: wasm_module.metadata.width,
             offset_y: plugin_conf.pos_y,
             offset_x: plugin_conf.pos_x,
-            drawer: Box::from(wasm_module),
+            drawer: Box::new(PluginWorker::spawn(plugin_conf.id(), wasm_module)),
         }
     }
 
+    pub(crate) fn from_remote(plugin_conf: &PluginConf, remote: RemotePicture) -> Self {
+        Self {
+            name: plugin_conf.id().to_string(),
+            img_height: remote.height,
+            img_width: remote.width,
+            offset_y: plugin_conf.pos_y,
+            offset_x: plugin_conf.pos_x,
+            drawer: Box::new(PluginWorker::spawn(plugin_conf.id(), remote)),
+        }
+    }
+
+    // Whether the picture stays inside the matrix at its offset
+    pub(crate) fn fits_matrix(&self) -> bool {
+        self.offset_x.checked_add(self.img_width).is_some_and(|end| end <= MATRIX_WIDTH)
+            && self.offset_y.checked_add(self.img_height).is_some_and(|end| end <= MATRIX_HEIGHT)
+    }
+
     // Returns space taken by Picture as Matrix. non 0 values indicate space taken.
     pub(crate) fn get_space_as_matrix(&self) -> Matrix {
         let mut output = Vec::from(EMPTY_MATRIX);
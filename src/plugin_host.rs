@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::env::current_exe;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{PluginConf, PluginHostConf};
+use crate::kv_bus::{KvValue, KV_BUS};
+use crate::matrix::Matrix;
+use crate::picture::Picture;
+use crate::plugin_paths::PluginPaths;
+use crate::stat_provider::system_stats;
+use crate::wasm_module::{compile_file, WasmModule};
+
+// Subcommand the daemon starts its own executable with to run a plugin host
+const PLUGIN_HOST_ARG: &str = "plugin-host";
+
+// Time the plugin host gets to answer a request before it's considered hung and killed
+pub(crate) const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+// Longest response line read from a plugin host, well above a frame with a full key/value journal or a saved state
+const MAX_RESPONSE_SIZE: usize = 4 * 1024 * 1024;
+// `nobody`, plugin hosts run as it unless another user is configured
+const NOBODY_ID: u32 = 65534;
+// Minimum interval between restarts of a plugin host that died
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+// Requests sent by the daemon, one JSON object per line
+#[derive(Serialize, Deserialize, Debug)]
+enum HostRequest {
+    Load { plugin: PluginConf, path: PathBuf },
+    Draw {
+        // Key/value bus contents, sent only when they changed since the previous frame
+        kv: Option<HashMap<String, KvValue>>,
+    },
+    SaveState,
+    LoadState(Vec<u8>),
+    Shutdown,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum HostResponse {
+    Loaded { width: usize, height: usize },
+    Frame {
+        matrix: Vec<u8>,
+        // Values the plugin wrote to the key/value bus while drawing the frame
+        kv_writes: Vec<(String, KvValue)>,
+    },
+    State(Option<Vec<u8>>),
+    Done,
+    Error(String),
+}
+
+// Entry point of the plugin host process. Serves requests from the daemon on stdin/stdout.
+pub fn run() -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    let mut wasm_module: Option<WasmModule> = None;
+    KV_BUS.lock().unwrap().enable_journal();
+
+    for line in io::stdin().lock().lines() {
+        let request: HostRequest = serde_json::from_str(&line?)?;
+
+        let response = match (request, &mut wasm_module) {
+            (HostRequest::Load { plugin: plugin_conf, path }, _) => {
+                match compile_file(&path)
+                    .and_then(|compiled| WasmModule::instantiate(&compiled, &plugin_conf, system_stats()))
+                {
+                    Ok(loaded) => {
+                        let response = HostResponse::Loaded {
+                            width: loaded.metadata.width,
+                            height: loaded.metadata.height,
+                        };
+                        wasm_module = Some(loaded);
+                        response
+                    }
+                    Err(err) => HostResponse::Error(err.to_string()),
+                }
+            }
+            (HostRequest::Draw { kv }, Some(wasm_module)) => {
+                if let Some(kv) = kv {
+                    KV_BUS.lock().unwrap().replace(kv);
+                }
+                let matrix = wasm_module.draw().as_bytes().to_vec();
+                HostResponse::Frame {
+                    matrix,
+                    kv_writes: KV_BUS.lock().unwrap().take_journal(),
+                }
+            }
+            (HostRequest::SaveState, Some(wasm_module)) => {
+                HostResponse::State(wasm_module.save_state())
+            }
+            (HostRequest::LoadState(state), Some(wasm_module)) => {
+                wasm_module.load_state(&state);
+                HostResponse::Done
+            }
+            (HostRequest::Shutdown, wasm_module) => {
+                if let Some(wasm_module) = wasm_module {
+                    wasm_module.shutdown();
+                }
+                write_message(&mut stdout, &HostResponse::Done)?;
+                return Ok(());
+            }
+            (_, None) => HostResponse::Error("no plugin loaded".to_string()),
+        };
+
+        write_message(&mut stdout, &response)?;
+    }
+    Ok(())
+}
+
+fn write_message<T: Serialize>(writer: &mut impl Write, message: &T) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    writer.flush()
+}
+
+// Plugin host process, talking to the daemon over a unix socket pair passed as its stdin/stdout
+struct HostProcess {
+    child: Child,
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl HostProcess {
+    fn spawn(host_conf: &PluginHostConf) -> io::Result<Self> {
+        // Plugin hosts run untrusted code, they never keep root privileges
+        let as_root = running_as_root()?;
+        let uid = host_conf.uid.or(as_root.then_some(NOBODY_ID));
+        let gid = host_conf.gid.or(as_root.then_some(NOBODY_ID));
+        if uid == Some(0) || gid == Some(0) {
+            return Err(io::Error::new(ErrorKind::PermissionDenied, "plugin hosts can't run as root"));
+        }
+
+        let (socket, child_socket) = UnixStream::pair()?;
+
+        let mut command = Command::new(current_exe()?);
+        command
+            .arg(PLUGIN_HOST_ARG)
+            .stdin(Stdio::from(std::os::fd::OwnedFd::from(child_socket.try_clone()?)))
+            .stdout(Stdio::from(std::os::fd::OwnedFd::from(child_socket)));
+        // Group has to be switched first, while the process still has the privileges to do so
+        if let Some(gid) = gid {
+            command.gid(gid);
+        }
+        if let Some(uid) = uid {
+            command.uid(uid);
+        }
+
+        Ok(Self {
+            child: command.spawn()?,
+            reader: BufReader::new(socket.try_clone()?),
+            writer: socket,
+        })
+    }
+
+    fn call(&mut self, request: &HostRequest) -> io::Result<HostResponse> {
+        write_message(&mut self.writer, request)?;
+        let line = read_response(&mut self.reader, Instant::now() + RESPONSE_TIMEOUT)?;
+        serde_json::from_slice(&line).map_err(io::Error::from)
+    }
+}
+
+// Read a response line from the untrusted plugin host, which has until `deadline` to send all of it
+fn read_response(reader: &mut BufReader<UnixStream>, deadline: Instant) -> io::Result<Vec<u8>> {
+    let mut line = vec![];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::from(ErrorKind::TimedOut));
+        }
+        reader.get_ref().set_read_timeout(Some(remaining))?;
+
+        // Unix sockets report an expired read timeout as `WouldBlock`
+        let available = reader.fill_buf().map_err(|err| match err.kind() {
+            ErrorKind::WouldBlock => io::Error::from(ErrorKind::TimedOut),
+            _ => err,
+        })?;
+        if available.is_empty() {
+            return Err(io::Error::from(ErrorKind::UnexpectedEof));
+        }
+        let (used, complete) = match available.iter().position(|byte| *byte == b'\n') {
+            Some(position) => (position + 1, true),
+            None => (available.len(), false),
+        };
+        if line.len() + used > MAX_RESPONSE_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidData, "plugin host response too long"));
+        }
+        line.extend_from_slice(&available[..used]);
+        reader.consume(used);
+        if complete {
+            return Ok(line);
+        }
+    }
+}
+
+// Effective user of the daemon, as the owner of its /proc entry
+fn running_as_root() -> io::Result<bool> {
+    Ok(fs::metadata("/proc/self")?.uid() == 0)
+}
+
+impl Drop for HostProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Plugin drawn by a plugin host process. The process is restarted if it dies or hangs.
+pub struct RemotePicture {
+    plugin_conf: PluginConf,
+    // Resolved by the daemon, the plugin host may not see the same search path
+    path: PathBuf,
+    host_conf: PluginHostConf,
+    process: Option<HostProcess>,
+    started_at: Instant,
+    // Version of the key/value bus last sent to the process
+    kv_version: Option<u64>,
+    last_frame: Matrix,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+}
+
+impl RemotePicture {
+    pub(crate) fn spawn(
+        plugin_conf: &PluginConf,
+        host_conf: &PluginHostConf,
+        plugin_paths: &PluginPaths,
+    ) -> Result<Self, String> {
+        let path = plugin_paths.resolve(&plugin_conf.name).ok_or(format!(
+            "'{}' plugin was not found in the plugin search path",
+            plugin_conf.name
+        ))?;
+        let (process, width, height) = start_process(plugin_conf, &path, host_conf)?;
+
+        Ok(Self {
+            plugin_conf: plugin_conf.clone(),
+            path,
+            host_conf: host_conf.clone(),
+            process: Some(process),
+            started_at: Instant::now(),
+            kv_version: None,
+            last_frame: Matrix::default(),
+            width,
+            height,
+        })
+    }
+
+    fn call(&mut self, request: &HostRequest) -> Option<HostResponse> {
+        if self.process.is_none() && self.started_at.elapsed() >= RESTART_DELAY {
+            self.restart();
+        }
+        let process = self.process.as_mut()?;
+
+        match process.call(request) {
+            Ok(HostResponse::Error(err)) => {
+                error!("Plugin host for '{}' failed: {}", self.plugin_conf.id(), err);
+                None
+            }
+            Ok(response) => Some(response),
+            Err(err) => {
+                error!("Plugin host for '{}' died: {}", self.plugin_conf.id(), err);
+                // Dropping the process kills it, in case it hung
+                self.process = None;
+                None
+            }
+        }
+    }
+
+    fn restart(&mut self) {
+        self.started_at = Instant::now();
+        self.kv_version = None;
+
+        match start_process(&self.plugin_conf, &self.path, &self.host_conf) {
+            Ok((process, width, height)) if (width, height) == (self.width, self.height) => {
+                info!("Restarted plugin host for '{}'", self.plugin_conf.id());
+                self.process = Some(process);
+            }
+            Ok(_) => {
+                error!("Restarted plugin host for '{}' reports a different size, reload the configuration", self.plugin_conf.id())
+            }
+            Err(err) => error!("Failed to restart plugin host for '{}': {}", self.plugin_conf.id(), err),
+        }
+    }
+}
+
+fn start_process(
+    plugin_conf: &PluginConf,
+    path: &Path,
+    host_conf: &PluginHostConf,
+) -> Result<(HostProcess, usize, usize), String> {
+    let mut process = HostProcess::spawn(host_conf)
+        .map_err(|err| format!("failed to start plugin host: {}", err))?;
+
+    let request = HostRequest::Load {
+        plugin: plugin_conf.clone(),
+        path: path.to_path_buf(),
+    };
+    match process.call(&request) {
+        Ok(HostResponse::Loaded { width, height }) => Ok((process, width, height)),
+        Ok(HostResponse::Error(err)) => Err(err),
+        Ok(response) => Err(format!("unexpected plugin host response: {:?}", response)),
+        Err(err) => Err(format!("plugin host died while loading: {}", err)),
+    }
+}
+
+impl Picture for RemotePicture {
+    fn draw(&mut self) -> Matrix {
+        let kv = {
+            let kv_bus = KV_BUS.lock().unwrap();
+            let changed = self.kv_version != Some(kv_bus.version());
+            self.kv_version = Some(kv_bus.version());
+            changed.then(|| kv_bus.snapshot())
+        };
+
+        let Some(HostResponse::Frame { matrix, kv_writes }) = self.call(&HostRequest::Draw { kv }) else {
+            self.kv_version = None;
+            return self.last_frame.clone();
+        };
+
+        // The plugin host is untrusted, it may only write to the namespace of its plugin
+        let namespace = format!("{}.", self.plugin_conf.id());
+        let mut kv_bus = KV_BUS.lock().unwrap();
+        for (key, value) in kv_writes {
+            if !key.starts_with(&namespace) {
+                warn!("Plugin host for '{}' wrote outside of its namespace: {}", self.plugin_conf.id(), key);
+                continue;
+            }
+            if let Err(err) = kv_bus.set(&key, value) {
+                warn!("'{}' module failed to set kv value: {}", self.plugin_conf.id(), err);
+            }
+        }
+
+        match Matrix::try_from(matrix.as_slice()) {
+            Ok(matrix) => self.last_frame = matrix,
+            Err(_) => error!("Plugin host for '{}' sent a malformed frame", self.plugin_conf.id()),
+        }
+        self.last_frame.clone()
+    }
+
+    fn shutdown(&mut self) {
+        if self.process.is_some() {
+            self.call(&HostRequest::Shutdown);
+        }
+        self.process = None;
+    }
+
+    fn save_state(&mut self) -> Option<Vec<u8>> {
+        match self.call(&HostRequest::SaveState)? {
+            HostResponse::State(state) => state,
+            _ => None,
+        }
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        self.call(&HostRequest::LoadState(state.to_vec()));
+    }
+}
+
+#[cfg(test)]
+mod plugin_host_tests {
+    use std::io::{BufRead, BufReader, ErrorKind, Write};
+    use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
+    use std::process::Command;
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    use crate::matrix::Matrix;
+    use crate::picture::Picture;
+    use crate::plugin_host::{
+        read_response, write_message, HostProcess, HostRequest, HostResponse, RemotePicture, MAX_RESPONSE_SIZE,
+    };
+    use crate::plugin_worker::PluginWorker;
+
+    #[test]
+    fn encodes_one_message_per_line() {
+        let mut buffer = vec![];
+
+        write_message(&mut buffer, &HostRequest::LoadState(vec![1, 2])).unwrap();
+        write_message(&mut buffer, &HostRequest::Draw { kv: None }).unwrap();
+
+        let lines = String::from_utf8(buffer).unwrap();
+        let requests = lines
+            .lines()
+            .map(|line| serde_json::from_str::<HostRequest>(line).unwrap())
+            .collect::<Vec<HostRequest>>();
+        assert!(matches!(requests[0], HostRequest::LoadState(ref state) if state == &[1, 2]));
+        assert!(matches!(requests[1], HostRequest::Draw { kv: None }));
+    }
+
+    #[test]
+    fn times_out_trickled_response() {
+        let (socket, mut host) = UnixStream::pair().unwrap();
+        let mut reader = BufReader::new(socket);
+        host.write_all(b"\"Done\"\n").unwrap();
+
+        // Each byte comes within a read timeout, the whole line doesn't come before the deadline
+        let trickle = std::thread::spawn(move || {
+            for _ in 0..10 {
+                let _ = host.write_all(b"x");
+                sleep(Duration::from_millis(50));
+            }
+        });
+
+        let done = read_response(&mut reader, Instant::now() + Duration::from_secs(1)).unwrap();
+        let err = read_response(&mut reader, Instant::now() + Duration::from_millis(200)).unwrap_err();
+
+        assert_eq!(done, b"\"Done\"\n");
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        trickle.join().unwrap();
+    }
+
+    #[test]
+    fn rejects_oversized_response() {
+        let (socket, mut host) = UnixStream::pair().unwrap();
+        let mut reader = BufReader::new(socket);
+        std::thread::spawn(move || host.write_all(&vec![b'x'; MAX_RESPONSE_SIZE + 1]));
+
+        let err = read_response(&mut reader, Instant::now() + Duration::from_secs(5)).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn waits_for_slow_remote_save_state() {
+        let (socket, host) = UnixStream::pair().unwrap();
+        // Stands in for the plugin host, answering on the other end of the socket
+        let fake_host = std::thread::spawn(move || {
+            let mut writer = host.try_clone().unwrap();
+            for line in BufReader::new(host).lines() {
+                match serde_json::from_str::<HostRequest>(&line.unwrap()).unwrap() {
+                    HostRequest::SaveState => {
+                        sleep(Duration::from_secs(2));
+                        write_message(&mut writer, &HostResponse::State(Some(vec![7]))).unwrap();
+                    }
+                    _ => {
+                        write_message(&mut writer, &HostResponse::Done).unwrap();
+                        break;
+                    }
+                }
+            }
+        });
+        let remote = RemotePicture {
+            plugin_conf: toml::from_str("name = 'slow'\npos_x = 0\npos_y = 0").unwrap(),
+            path: PathBuf::from("slow.wasm"),
+            host_conf: Default::default(),
+            process: Some(HostProcess {
+                child: Command::new("sleep").arg("30").spawn().unwrap(),
+                reader: BufReader::new(socket.try_clone().unwrap()),
+                writer: socket,
+            }),
+            started_at: Instant::now(),
+            kv_version: None,
+            last_frame: Matrix::default(),
+            width: 1,
+            height: 1,
+        };
+        let mut worker = PluginWorker::spawn("slow-remote", remote);
+
+        assert_eq!(worker.save_state(), Some(vec![7]));
+        worker.shutdown();
+        fake_host.join().unwrap();
+    }
+}
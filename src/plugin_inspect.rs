@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use wasmer::{ExternType, FunctionType, Type};
+
+use crate::component;
+use crate::config::{PluginConf, WasiConf};
+use crate::matrix::Matrix;
+use crate::permissions::{required_capability, Capability, LEGACY_CAPABILITIES};
+use crate::picture::Picture;
+use crate::stat_provider::{HostStats, ScriptedStats};
+use crate::wasm_module::{
+    check_abi_version, check_size, compile_file, host_import_types, read_metadata, CompiledPlugin, Metadata,
+    WASMError, WasmModule,
+};
+
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+// Exports the host calls on core modules: name, params, results and whether plugins have to provide it
+const EXPECTED_EXPORTS: [(&str, &[Type], &[Type], bool); 11] = [
+    ("draw", &[], &[Type::I32], true),
+    ("init", &[Type::I32, Type::I32], &[], false),
+    ("tick", &[Type::I32], &[], false),
+    ("shutdown", &[], &[], false),
+    ("save_state", &[], &[Type::I64], false),
+    ("load_state", &[Type::I32, Type::I32], &[], false),
+    ("alloc", &[Type::I32], &[Type::I32], false),
+    ("_initialize", &[], &[], false),
+    // AssemblyScript runtime, used when there's no `alloc`
+    ("__new", &[Type::I32, Type::I32], &[Type::I32], false),
+    ("__pin", &[Type::I32], &[Type::I32], false),
+    ("__unpin", &[Type::I32], &[], false),
+];
+
+// Stats the trial draw runs with
+const MOCK_STATS: HostStats = HostStats {
+    global_cpu_usage: 50.0,
+    memory_usage: 0.5,
+    battery_state_of_charge: 0.75,
+    // 2024-01-01 12:34:56 UTC
+    epoch_time: 1_704_112_496,
+};
+
+// What `inspect-plugin` found out about a module. Any problem means the host would refuse to load it,
+// notes point out things that load but may not work as intended.
+pub struct Inspection {
+    pub metadata: Option<Metadata>,
+    pub memory: Option<String>,
+    pub exports: Vec<String>,
+    pub imports: Vec<String>,
+    // Plugin area of the trial draw, as printed by `Matrix`
+    pub frame: Option<String>,
+    pub notes: Vec<String>,
+    pub problems: Vec<String>,
+}
+
+pub fn inspect_plugin(path: &Path) -> Result<(), String> {
+    let compiled = compile_file(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let inspection = inspect(&compiled);
+
+    if let Some(metadata) = &inspection.metadata {
+        println!("name:        {}", metadata.name);
+        println!("version:     {}", metadata.version.as_deref().unwrap_or("unknown"));
+        if let Some(author) = &metadata.author {
+            println!("author:      {}", author);
+        }
+        if let Some(description) = &metadata.description {
+            println!("description: {}", description);
+        }
+        println!("size:        {}x{}", metadata.width, metadata.height);
+        println!("abi version: {}", metadata.abi_version);
+        if let Some(refresh_ms) = metadata.refresh_ms {
+            println!("refresh:     {}ms", refresh_ms);
+        }
+        println!("permissions: {}", describe_permissions(metadata));
+        if let Some(settings_schema) = &metadata.settings_schema {
+            println!("settings:");
+            for (key, setting) in settings_schema {
+                println!("  {} ({})", key, setting.kind);
+            }
+        }
+    }
+    if let Some(memory) = &inspection.memory {
+        println!("memory:      {}", memory);
+    }
+
+    let sections = [
+        ("exports", &inspection.exports),
+        ("imports", &inspection.imports),
+        ("notes", &inspection.notes),
+        ("problems", &inspection.problems),
+    ];
+    for (title, lines) in sections {
+        if !lines.is_empty() {
+            println!("{}:", title);
+            for line in lines {
+                println!("  {}", line);
+            }
+        }
+    }
+    if let Some(frame) = &inspection.frame {
+        println!(
+            "trial draw (cpu {}%, memory {}%, battery {}%, epoch time {}):",
+            MOCK_STATS.global_cpu_usage,
+            MOCK_STATS.memory_usage * 100.0,
+            MOCK_STATS.battery_state_of_charge * 100.0,
+            MOCK_STATS.epoch_time
+        );
+        for row in frame.lines() {
+            println!("  {}", row);
+        }
+    }
+
+    match inspection.problems.len() {
+        0 => Ok(()),
+        count => Err(format!("{}: {} problem(s) found", path.display(), count)),
+    }
+}
+
+pub(crate) fn inspect(compiled: &CompiledPlugin) -> Inspection {
+    let module = &compiled.module;
+    let mut inspection = Inspection {
+        metadata: None,
+        memory: None,
+        exports: vec![],
+        imports: vec![],
+        frame: None,
+        notes: vec![],
+        problems: vec![],
+    };
+
+    match read_metadata(module) {
+        Ok(metadata) => {
+            check_metadata(&metadata, &mut inspection.problems);
+            inspection.metadata = Some(metadata);
+        }
+        Err(err) => inspection.problems.push(err.to_string()),
+    }
+
+    check_exports(compiled, &mut inspection);
+    check_imports(compiled, &mut inspection);
+
+    // Only a module that passed the static checks gets instantiated
+    if let (Some(metadata), true) = (&inspection.metadata, inspection.problems.is_empty()) {
+        match trial_draw(compiled, metadata) {
+            Ok(frame) => inspection.frame = Some(frame),
+            Err(WASMError::InvalidSettings(errors)) => {
+                let errors = errors.iter().map(|err| err.to_string()).collect::<Vec<String>>();
+                inspection
+                    .notes
+                    .push(format!("trial draw skipped, default settings are invalid: {}", errors.join(", ")));
+            }
+            Err(err) => inspection.problems.push(format!("trial draw failed: {}", err)),
+        }
+    }
+    inspection
+}
+
+fn check_metadata(metadata: &Metadata, problems: &mut Vec<String>) {
+    if let Err(err) = check_abi_version(metadata) {
+        problems.push(err.to_string());
+    }
+    if let Err(err) = check_size(metadata) {
+        problems.push(err.to_string());
+    }
+}
+
+fn check_exports(compiled: &CompiledPlugin, inspection: &mut Inspection) {
+    let exports = compiled
+        .module
+        .exports()
+        .map(|export| (export.name().to_string(), export.ty().clone()))
+        .collect::<HashMap<String, ExternType>>();
+
+    match exports.get("memory") {
+        Some(ExternType::Memory(memory)) => {
+            let maximum = memory
+                .maximum
+                .map(|maximum| format!("{} pages", maximum.0))
+                .unwrap_or("unbounded".to_string());
+            inspection.memory = Some(format!("{} pages minimum, {} maximum", memory.minimum.0, maximum));
+        }
+        Some(other) => inspection.problems.push(format!("'memory' export should be a memory, found {:?}", other)),
+        None => inspection.problems.push("missing 'memory' export".to_string()),
+    }
+
+    let expected_exports = match compiled.component {
+        true => component::EXPECTED_EXPORTS.as_slice(),
+        false => EXPECTED_EXPORTS.as_slice(),
+    };
+    for &(name, params, results, required) in expected_exports {
+        let expected = FunctionType::new(params, results);
+        match exports.get(name) {
+            Some(ExternType::Function(function)) if *function == expected => {
+                inspection.exports.push(format!("{}: {}", name, signature(function)));
+            }
+            Some(ExternType::Function(function)) => inspection.problems.push(format!(
+                "'{}' export should be {}, found {}",
+                name,
+                signature(&expected),
+                signature(function)
+            )),
+            Some(other) => inspection
+                .problems
+                .push(format!("'{}' export should be a function, found {:?}", name, other)),
+            None if required => inspection.problems.push(format!("missing required '{}' export", name)),
+            None => {}
+        }
+    }
+
+    let mut unused = exports
+        .keys()
+        .filter(|name| *name != "memory" && !expected_exports.iter().any(|(expected, ..)| expected == name))
+        .cloned()
+        .collect::<Vec<String>>();
+    unused.sort();
+    for name in unused {
+        inspection.exports.push(format!("{} (not used by the host)", name));
+    }
+
+    // Components get their settings through an import, and state passed with `cabi_realloc`
+    let has_allocator = compiled.component
+        || exports.contains_key("alloc")
+        || (exports.contains_key("__new") && exports.contains_key("__pin"));
+    for name in ["init", "load_state"] {
+        if exports.contains_key(name) && !has_allocator {
+            inspection.notes.push(format!(
+                "'{}' is exported without 'alloc' or '__new'/'__pin', it will be called without data",
+                name
+            ));
+        }
+    }
+}
+
+// Requested capabilities, noting when a module without `permissions` gets the legacy stats
+pub(crate) fn describe_permissions(metadata: &Metadata) -> String {
+    let permissions = metadata
+        .permissions
+        .as_deref()
+        .unwrap_or(&LEGACY_CAPABILITIES)
+        .iter()
+        .map(Capability::to_string)
+        .collect::<Vec<String>>()
+        .join(", ");
+    match metadata.permissions {
+        Some(_) => permissions,
+        None => format!("{} (not declared, granted without approval)", permissions),
+    }
+}
+
+fn check_imports(compiled: &CompiledPlugin, inspection: &mut Inspection) {
+    let host_imports = host_import_types();
+    let declared = inspection
+        .metadata
+        .as_ref()
+        .map(|metadata| metadata.permissions.clone().unwrap_or(LEGACY_CAPABILITIES.to_vec()))
+        .unwrap_or_default();
+
+    for import in compiled.module.imports() {
+        let qualified_name = format!("{}.{}", import.module(), import.name());
+        let key = (import.module().to_string(), import.name().to_string());
+        let Some(provided) = host_imports.get(&key) else {
+            inspection.problems.push(format!("'{}' import isn't provided by the host", qualified_name));
+            continue;
+        };
+
+        match (provided, import.ty()) {
+            (ExternType::Function(provided), ExternType::Function(requested)) if provided != requested => {
+                inspection.problems.push(format!(
+                    "'{}' import should be {}, found {}",
+                    qualified_name,
+                    signature(provided),
+                    signature(requested)
+                ));
+                continue;
+            }
+            (ExternType::Function(provided), _) => {
+                let capability = required_capability(import.module(), import.name());
+                match capability {
+                    Some(capability) => inspection.imports.push(format!(
+                        "{}: {}, needs '{}' permission",
+                        qualified_name,
+                        signature(provided),
+                        capability
+                    )),
+                    None => inspection.imports.push(format!("{}: {}", qualified_name, signature(provided))),
+                }
+                if let Some(capability) = capability.filter(|capability| !declared.contains(capability)) {
+                    inspection.problems.push(format!(
+                        "'{}' import needs '{}' in metadata permissions",
+                        qualified_name, capability
+                    ));
+                }
+            }
+            _ => inspection.imports.push(qualified_name),
+        }
+    }
+
+    if compiled.module.imports().any(|import| import.module() == WASI_MODULE) {
+        inspection
+            .notes
+            .push("imports WASI, the plugin entry in config.toml needs a [plugins.wasi] table".to_string());
+    }
+}
+
+fn trial_draw(compiled: &CompiledPlugin, metadata: &Metadata) -> Result<String, WASMError> {
+    let uses_wasi = compiled.module.imports().any(|import| import.module() == WASI_MODULE);
+    let plugin_conf = PluginConf {
+        name: metadata.name.clone(),
+        id: None,
+        pos_x: 0,
+        pos_y: 0,
+        wasi: uses_wasi.then(|| toml::from_str::<WasiConf>("").expect("WASI config fields have defaults")),
+        permissions: metadata.permissions.clone().unwrap_or_default(),
+        settings: toml::Table::new(),
+    };
+
+    let mut module = WasmModule::instantiate(compiled, &plugin_conf, Arc::new(ScriptedStats::new(MOCK_STATS)))?;
+    let frame = module.try_draw_at(Instant::now())?;
+    module.shutdown();
+    Ok(plugin_area(&frame, metadata.width, metadata.height))
+}
+
+fn plugin_area(frame: &Matrix, width: usize, height: usize) -> String {
+    frame
+        .to_string()
+        .split("\r\n")
+        .take(height)
+        .map(|row| &row[..width])
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+fn signature(function: &FunctionType) -> String {
+    let names = |types: &[Type]| {
+        types
+            .iter()
+            .map(|ty| format!("{:?}", ty).to_lowercase())
+            .collect::<Vec<String>>()
+    };
+    let results = match names(function.results()).as_slice() {
+        [] => "()".to_string(),
+        [result] => result.clone(),
+        results => format!("({})", results.join(", ")),
+    };
+    format!("({}) -> {}", names(function.params()).join(", "), results)
+}
+
+#[cfg(test)]
+mod plugin_inspect_tests {
+    use crate::plugin_inspect::inspect;
+    use crate::wasm_module::compile;
+
+    #[test]
+    fn draws_valid_plugin_with_mocked_stats() {
+        let compiled = compile(
+            br#"(module
+                (import "env" "get_epoch_time" (func $time (result i64)))
+                (memory (export "memory") 1 2)
+                (@custom "metadata" "{\"name\":\"even\",\"width\":2,\"height\":1,\"permissions\":[\"time\"]}")
+                (func (export "draw") (result i32)
+                    (i32.store8 (i32.const 1) (i64.eqz (i64.rem_u (call $time) (i64.const 2))))
+                    (i32.const 0)))"#,
+        )
+        .unwrap();
+
+        let inspection = inspect(&compiled);
+
+        assert_eq!(inspection.problems, Vec::<String>::new());
+        assert_eq!(inspection.memory.as_deref(), Some("1 pages minimum, 2 pages maximum"));
+        assert_eq!(inspection.exports, vec!["draw: () -> i32"]);
+        assert_eq!(inspection.imports, vec!["env.get_epoch_time: () -> i64, needs 'time' permission"]);
+        assert_eq!(inspection.frame.as_deref(), Some(".#"));
+    }
+
+    #[test]
+    fn reports_abi_violations() {
+        let compiled = compile(
+            br#"(module
+                (import "env" "get_epoch_time" (func (result i64)))
+                (import "env" "get_memory_usage" (func (result i64)))
+                (import "env" "get_weather" (func (result f32)))
+                (memory (export "memory") 1)
+                (@custom "metadata" "{\"name\":\"broken\",\"width\":10,\"height\":1,\"abi_version\":2,\"permissions\":[]}")
+                (func (export "draw") (param i32) (result i32) (i32.const 0)))"#,
+        )
+        .unwrap();
+
+        let inspection = inspect(&compiled);
+
+        assert_eq!(
+            inspection.problems,
+            vec![
+                "plugin requires ABI version 2, host supports versions 1 to 1",
+                "plugin is 10x1, width should be between 1 and 9 and height between 1 and 34",
+                "'draw' export should be () -> i32, found (i32) -> i32",
+                "'env.get_epoch_time' import needs 'time' in metadata permissions",
+                "'env.get_memory_usage' import should be () -> f32, found () -> i64",
+                "'env.get_weather' import isn't provided by the host",
+            ]
+        );
+        assert_eq!(inspection.frame, None);
+    }
+}
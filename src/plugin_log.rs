@@ -0,0 +1,169 @@
+use byteorder::{ByteOrder, LittleEndian};
+use log::{error, log, Level};
+use wasmer::{FunctionEnvMut, MemoryAccessError, MemoryView};
+
+use crate::wasm_module::PluginEnv;
+
+// Longer strings are truncated, whatever length the plugin passes
+const MAX_STRING_BYTES: usize = 4096;
+
+// Per-plugin log target, e.g. `RUST_LOG=plugin::time=debug`
+pub(crate) fn log_target(plugin_name: &str) -> String {
+    format!("plugin::{}", plugin_name)
+}
+
+// Host import for UTF-8 strings, `len` is the length in bytes
+pub fn log_utf8(env: FunctionEnvMut<PluginEnv>, level: i32, ptr: i32, len: i32) {
+    let truncated = len.max(0) as usize > MAX_STRING_BYTES;
+    log_with(env, level, truncated, |view| read_utf8(view, ptr, len))
+}
+
+// Host import for UTF-16 strings (e.g. AssemblyScript), `len` is the length in code units
+pub fn log_utf16(env: FunctionEnvMut<PluginEnv>, level: i32, ptr: i32, len: i32) {
+    let truncated = len.max(0) as usize * 2 > MAX_STRING_BYTES;
+    log_with(env, level, truncated, |view| read_utf16(view, ptr, len))
+}
+
+// AssemblyScript runtime calls this on failed assertions and `throw` statements
+pub fn abort(env: FunctionEnvMut<PluginEnv>, msg: i32, file: i32, line: i32, col: i32) {
+    let data = env.data();
+    let (msg, file) = match &data.memory {
+        Some(memory) => {
+            let view = memory.view(&env);
+            (
+                read_assemblyscript_string(&view, msg)
+                    .unwrap_or_else(|_| format!("<msg_ptr={}>", msg)),
+                read_assemblyscript_string(&view, file)
+                    .unwrap_or_else(|_| format!("<file_ptr={}>", file)),
+            )
+        }
+        None => (format!("<msg_ptr={}>", msg), format!("<file_ptr={}>", file)),
+    };
+
+    error!(target: &log_target(&data.name), "AssemblyScript abort called: '{}' at {}:{}:{}", msg, file, line, col);
+}
+
+fn log_with<F>(env: FunctionEnvMut<PluginEnv>, level: i32, truncated: bool, read: F)
+where
+    F: FnOnce(&MemoryView) -> Result<String, MemoryAccessError>,
+{
+    let data = env.data();
+    let Some(memory) = &data.memory else {
+        return;
+    };
+
+    match read(&memory.view(&env)) {
+        Ok(message) if truncated => {
+            log!(target: &log_target(&data.name), level_from(level), "{}... (truncated)", message)
+        }
+        Ok(message) => log!(target: &log_target(&data.name), level_from(level), "{}", message),
+        Err(err) => {
+            error!(target: "WASM", "Failed to read log message at '{}' module: {}", data.name, err)
+        }
+    }
+}
+
+// 0 = error, 1 = warn, 2 = info, 3 = debug, 4 = trace
+fn level_from(level: i32) -> Level {
+    match level {
+        i32::MIN..=0 => Level::Error,
+        1 => Level::Warn,
+        2 => Level::Info,
+        3 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+// Reads at most `MAX_STRING_BYTES`, once the whole string is known to be in plugin memory
+pub(crate) fn read_utf8(
+    view: &MemoryView,
+    ptr: i32,
+    len: i32,
+) -> Result<String, MemoryAccessError> {
+    let len = len.max(0) as u64;
+    check_range(view, ptr, len)?;
+
+    let mut bytes = vec![0u8; len.min(MAX_STRING_BYTES as u64) as usize];
+    view.read(ptr as u32 as u64, &mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Same as `read_utf8`, `len` counts code units
+pub(crate) fn read_utf16(
+    view: &MemoryView,
+    ptr: i32,
+    len: i32,
+) -> Result<String, MemoryAccessError> {
+    let len = len.max(0) as u64;
+    check_range(view, ptr, len * 2)?;
+
+    let mut bytes = vec![0u8; (len * 2).min(MAX_STRING_BYTES as u64) as usize];
+    view.read(ptr as u32 as u64, &mut bytes)?;
+
+    let mut units = vec![0u16; bytes.len() / 2];
+    LittleEndian::read_u16_into(&bytes, &mut units);
+    Ok(String::from_utf16_lossy(&units))
+}
+
+fn check_range(view: &MemoryView, ptr: i32, len: u64) -> Result<(), MemoryAccessError> {
+    if ptr as u32 as u64 + len > view.data_size() {
+        return Err(MemoryAccessError::HeapOutOfBounds);
+    }
+    Ok(())
+}
+
+// AssemblyScript objects keep their byte length in the header, right before the pointer
+pub(crate) fn read_assemblyscript_string(
+    view: &MemoryView,
+    ptr: i32,
+) -> Result<String, MemoryAccessError> {
+    if ptr < 4 {
+        return Err(MemoryAccessError::HeapOutOfBounds);
+    }
+    let mut header = [0u8; 4];
+    view.read(ptr as u32 as u64 - 4, &mut header)?;
+    let byte_length = LittleEndian::read_u32(&header);
+
+    read_utf16(view, ptr, (byte_length / 2) as i32)
+}
+
+#[cfg(test)]
+mod plugin_log_tests {
+    use wasmer::{Memory, MemoryType, Store};
+
+    use crate::plugin_log::{read_assemblyscript_string, read_utf16, read_utf8, MAX_STRING_BYTES};
+
+    #[test]
+    fn decodes_strings_from_memory() {
+        let mut store = Store::default();
+        let memory = Memory::new(&mut store, MemoryType::new(1, None, false)).unwrap();
+        let view = memory.view(&store);
+
+        view.write(0, "zażółć".as_bytes()).unwrap();
+        // AssemblyScript string: byte length header followed by UTF-16LE code units
+        view.write(96, &[8, 0, 0, 0]).unwrap();
+        view.write(100, &[b'f', 0, b'i', 0, b'l', 0, b'e', 0])
+            .unwrap();
+
+        assert_eq!(
+            read_utf8(&view, 0, "zażółć".len() as i32).unwrap(),
+            "zażółć"
+        );
+        assert_eq!(read_utf16(&view, 100, 2).unwrap(), "fi");
+        assert_eq!(read_assemblyscript_string(&view, 100).unwrap(), "file");
+    }
+
+    #[test]
+    fn bounds_string_lengths() {
+        let mut store = Store::default();
+        let memory = Memory::new(&mut store, MemoryType::new(1, None, false)).unwrap();
+        let view = memory.view(&store);
+        view.write(0, &[b'a'; 8192]).unwrap();
+
+        assert_eq!(read_utf8(&view, 0, 8192).unwrap().len(), MAX_STRING_BYTES);
+        assert_eq!(read_utf16(&view, 0, 4096).unwrap().chars().count(), MAX_STRING_BYTES / 2);
+        assert!(read_utf8(&view, 0, i32::MAX).is_err());
+        assert!(read_utf16(&view, 0, i32::MAX).is_err());
+        assert!(read_utf8(&view, -1, 2).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::env::current_exe;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bundle::BUNDLE_EXTENSION;
+
+const PLUGINS_SUBDIR: &str = "fw-led-stat-control/plugins";
+
+// Ordered list of directories plugins are looked up in. For a given name, the first directory with
+// `<name>.fwled` or `<name>.wasm` wins and shadows the files in later directories. Within a directory,
+// a bundle shadows a plain module.
+pub struct PluginPaths {
+    dirs: Vec<PathBuf>,
+}
+
+impl PluginPaths {
+    // Directories from config come first, followed by the user, system and legacy `<exe dir>/plugins` ones
+    pub fn new(config_dirs: &[PathBuf]) -> Self {
+        let mut dirs = config_dirs.to_vec();
+
+        let data_home = std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")));
+        if let Some(data_home) = data_home {
+            dirs.push(data_home.join(PLUGINS_SUBDIR));
+        }
+        dirs.push(Path::new("/usr/local/share").join(PLUGINS_SUBDIR));
+        dirs.push(Path::new("/usr/share").join(PLUGINS_SUBDIR));
+        if let Some(exe_dir) = current_exe().ok().and_then(|path| path.parent().map(Path::to_path_buf)) {
+            dirs.push(exe_dir.join("plugins"));
+        }
+
+        Self { dirs }
+    }
+
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    // File a plugin name resolves to
+    pub fn resolve(&self, name: &str) -> Option<PathBuf> {
+        self.candidates(name).into_iter().next()
+    }
+
+    // All files for a plugin name, in priority order. All but the first are shadowed.
+    pub fn candidates(&self, name: &str) -> Vec<PathBuf> {
+        self.dirs
+            .iter()
+            .flat_map(|dir| {
+                [BUNDLE_EXTENSION, "wasm"]
+                    .map(|extension| dir.join(format!("{}.{}", name, extension)))
+            })
+            .filter(|path| path.is_file())
+            .collect()
+    }
+
+    // Every plugin found in the search path, with its files in priority order
+    pub fn list(&self) -> BTreeMap<String, Vec<PathBuf>> {
+        let mut plugins = BTreeMap::<String, Vec<PathBuf>>::new();
+
+        for dir in &self.dirs {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for path in entries.flatten().map(|entry| entry.path()) {
+                let is_plugin = path
+                    .extension()
+                    .is_some_and(|extension| extension == "wasm" || extension == BUNDLE_EXTENSION);
+                if let (true, Some(name)) = (is_plugin, path.file_stem().and_then(OsStr::to_str)) {
+                    plugins.entry(name.to_string()).or_default();
+                }
+            }
+        }
+        for (name, files) in plugins.iter_mut() {
+            *files = self.candidates(name);
+        }
+        plugins
+    }
+}
+
+#[cfg(test)]
+mod plugin_paths_tests {
+    use std::fs;
+
+    use crate::plugin_paths::PluginPaths;
+
+    #[test]
+    fn earlier_directories_and_bundles_shadow_later_files() {
+        let root = std::env::temp_dir().join("fw-led-plugin-paths");
+        let _ = fs::remove_dir_all(&root);
+        let (user, system) = (root.join("user"), root.join("system"));
+        fs::create_dir_all(&user).unwrap();
+        fs::create_dir_all(&system).unwrap();
+        fs::write(user.join("cpu.wasm"), "").unwrap();
+        fs::write(system.join("cpu.wasm"), "").unwrap();
+        fs::write(system.join("time.wasm"), "").unwrap();
+        fs::write(system.join("time.fwled"), "").unwrap();
+
+        let paths = PluginPaths::new(&[user.clone(), system.clone()]);
+        let plugins = paths.list();
+
+        assert_eq!(paths.resolve("cpu"), Some(user.join("cpu.wasm")));
+        assert_eq!(paths.resolve("time"), Some(system.join("time.fwled")));
+        assert_eq!(paths.resolve("memory"), None);
+        assert_eq!(plugins["cpu"], vec![user.join("cpu.wasm"), system.join("cpu.wasm")]);
+        assert_eq!(plugins["time"], vec![system.join("time.fwled"), system.join("time.wasm")]);
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+// Largest state blob kept for a single plugin
+pub const MAX_STATE_SIZE: usize = 64 * 1024;
+
+const DEFAULT_STATE_DIR: &str = "/var/lib/fw-led-stat-control";
+const STATE_FILE_EXTENSION: &str = "state";
+
+// State blobs returned by the plugins `save_state` export, keyed by plugin name.
+// Kept in memory across reloads and written to the state directory across restarts.
+pub struct PluginStates {
+    dir: Option<PathBuf>,
+    states: HashMap<String, Vec<u8>>,
+    // Names of the states changed since they were last written
+    changed: HashSet<String>,
+}
+
+// systemd sets $STATE_DIRECTORY for services with `StateDirectory=`
+pub fn state_dir() -> PathBuf {
+    std::env::var_os("STATE_DIRECTORY")
+        .map(PathBuf::from)
+        .unwrap_or(PathBuf::from(DEFAULT_STATE_DIR))
+}
+
+impl PluginStates {
+    // Read states persisted by a previous run. A missing or unreadable directory starts out empty.
+    pub fn load(dir: PathBuf) -> Self {
+        let mut states = HashMap::new();
+
+        match fs::read_dir(&dir) {
+            Ok(entries) => {
+                for path in entries.flatten().map(|entry| entry.path()) {
+                    if path.extension().is_none_or(|extension| extension != STATE_FILE_EXTENSION) {
+                        continue;
+                    }
+                    let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                        continue;
+                    };
+                    match fs::read(&path) {
+                        Ok(state) if state.len() <= MAX_STATE_SIZE => {
+                            states.insert(name.to_string(), state);
+                        }
+                        Ok(_) => warn!("Ignoring state file {} exceeding {} bytes", path.display(), MAX_STATE_SIZE),
+                        Err(err) => warn!("Failed to read state file {}: {}", path.display(), err),
+                    }
+                }
+            }
+            Err(err) => debug!("No plugin states loaded from {}: {}", dir.display(), err),
+        }
+
+        Self {
+            dir: Some(dir),
+            states,
+            changed: HashSet::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.states.get(name).map(Vec::as_slice)
+    }
+
+    pub fn insert(&mut self, name: &str, state: Vec<u8>) {
+        if state.len() > MAX_STATE_SIZE {
+            warn!("Dropping '{}' module state of {} bytes, the limit is {} bytes", name, state.len(), MAX_STATE_SIZE);
+            return;
+        }
+        if self.states.get(name) != Some(&state) {
+            self.changed.insert(name.to_string());
+            self.states.insert(name.to_string(), state);
+        }
+    }
+
+    // Write the states changed since the last call to the state directory. Failures are logged, states stay in
+    // memory and are written again next time.
+    pub fn persist(&mut self) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+        if let Err(err) = fs::create_dir_all(dir) {
+            warn!("Failed to create state directory {}, plugin states won't survive restarts: {}", dir.display(), err);
+            self.dir = None;
+            return;
+        }
+
+        for name in std::mem::take(&mut self.changed) {
+            // Plugin names end up in file names, don't let them point outside the directory
+            if name.contains(std::path::MAIN_SEPARATOR) || name.starts_with('.') {
+                continue;
+            }
+            let path = dir.join(format!("{}.{}", name, STATE_FILE_EXTENSION));
+            if let Err(err) = write_atomically(&path, &self.states[&name]) {
+                warn!("Failed to write state file {}: {}", path.display(), err);
+                self.changed.insert(name);
+            }
+        }
+    }
+}
+
+// Write to a temporary file synced to disk, then rename it over `path`, so a crash leaves either the previous
+// or the new state behind but never a truncated one
+fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)
+}
+
+#[cfg(test)]
+mod plugin_state_tests {
+    use std::fs;
+
+    use crate::plugin_state::{PluginStates, MAX_STATE_SIZE};
+
+    #[test]
+    fn persists_states_across_restarts() {
+        let dir = std::env::temp_dir().join("fw-led-plugin-states");
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut states = PluginStates::load(dir.clone());
+        states.insert("counter", vec![1, 2, 3]);
+        states.insert("huge", vec![0; MAX_STATE_SIZE + 1]);
+        states.persist();
+
+        let restored = PluginStates::load(dir);
+        assert_eq!(restored.get("counter"), Some([1u8, 2, 3].as_slice()));
+        assert_eq!(restored.get("huge"), None);
+    }
+
+    #[test]
+    fn persists_only_changed_states() {
+        let dir = std::env::temp_dir().join("fw-led-changed-plugin-states");
+        let _ = fs::remove_dir_all(&dir);
+        let mut states = PluginStates::load(dir.clone());
+        states.insert("counter", vec![1]);
+        states.persist();
+        fs::remove_file(dir.join("counter.state")).unwrap();
+
+        states.insert("counter", vec![1]);
+        states.persist();
+        assert!(!dir.join("counter.state").exists());
+
+        states.insert("counter", vec![2]);
+        states.persist();
+        assert_eq!(fs::read(dir.join("counter.state")).unwrap(), vec![2]);
+        assert!(!dir.join("counter.tmp").exists());
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+use inotify::{Inotify, WatchMask};
+use log::{error, info, warn};
+
+use crate::bundle::BUNDLE_EXTENSION;
+use crate::controller::ControllerMessage;
+use crate::plugin_paths::PluginPaths;
+
+// Watch the plugin search path and ask the controller to reload plugins whose file changed
+pub fn spawn(tx: Sender<ControllerMessage>, plugin_paths: &PluginPaths) {
+    let mut inotify = match Inotify::init() {
+        Ok(inotify) => inotify,
+        Err(err) => {
+            warn!("Failed to initialize inotify, plugin hot-reload is disabled: {}", err);
+            return;
+        }
+    };
+    // Directories missing at startup aren't watched
+    for dir in plugin_paths.dirs().iter().filter(|dir| dir.is_dir()) {
+        // Copying a file in place ends with CLOSE_WRITE, build tools writing to a temporary file
+        // and renaming it trigger MOVED_TO
+        match inotify.watches().add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO) {
+            Ok(_) => info!("Watching {} for plugin changes", dir.display()),
+            Err(err) => warn!("Failed to watch {} for plugin changes: {}", dir.display(), err),
+        }
+    }
+
+    std::thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+        loop {
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events,
+                Err(err) => {
+                    error!("Failed to read plugin directory events, plugin hot-reload stopped: {}", err);
+                    return;
+                }
+            };
+
+            // A single write may produce several events, reload each plugin once per batch
+            let changed = events
+                .filter_map(|event| event.name.and_then(plugin_name))
+                .collect::<BTreeSet<String>>();
+            for name in changed {
+                info!("Plugin file for '{}' changed, reloading", name);
+                if tx.send(ControllerMessage::ReloadPlugin(name)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+// Plugin name for a `<name>.wasm` or `<name>.fwled` file in the plugins directory
+fn plugin_name(file_name: &OsStr) -> Option<String> {
+    let path = Path::new(file_name);
+    let extension = path.extension()?;
+    if extension != "wasm" && extension != BUNDLE_EXTENSION {
+        return None;
+    }
+    path.file_stem()
+        .and_then(OsStr::to_str)
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod plugin_watcher_tests {
+    use std::ffi::OsStr;
+
+    use crate::plugin_watcher::plugin_name;
+
+    #[test]
+    fn maps_wasm_files_to_plugin_names() {
+        assert_eq!(plugin_name(OsStr::new("time.wasm")), Some("time".to_string()));
+        assert_eq!(plugin_name(OsStr::new("clock.fwled")), Some("clock".to_string()));
+        assert_eq!(plugin_name(OsStr::new("time.wasm.tmp")), None);
+        assert_eq!(plugin_name(OsStr::new("README.md")), None);
+    }
+}
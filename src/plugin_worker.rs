@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{debug, error, warn};
+use serde::Serialize;
+
+use crate::canvas::FRAME_DEADLINE;
+use crate::matrix::Matrix;
+use crate::picture::Picture;
+use crate::plugin_host::RESPONSE_TIMEOUT;
+
+// Time allowed to the worker to answer state and shutdown requests. A plugin host may take its whole response
+// timeout to finish a frame still being drawn, then as long again for the request itself.
+const REQUEST_TIMEOUT: Duration = RESPONSE_TIMEOUT.saturating_mul(2);
+
+#[derive(Serialize, Default)]
+pub struct FrameStats {
+    frames: AtomicU64,
+    // Frames for which the last good matrix was shown, because the plugin missed the deadline
+    late: AtomicU64,
+}
+
+// Per plugin instance frame stats, keyed by instance id
+pub static FRAME_STATS: LazyLock<Mutex<BTreeMap<String, Arc<FrameStats>>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+pub fn frame_stats_json() -> String {
+    let frame_stats = FRAME_STATS.lock().unwrap();
+    let frame_stats = frame_stats
+        .iter()
+        .map(|(id, stats)| (id, stats.as_ref()))
+        .collect::<BTreeMap<&String, &FrameStats>>();
+    serde_json::to_string(&frame_stats).expect("Frame stats should serialize to JSON")
+}
+
+enum Request {
+    Draw,
+    SaveState(Sender<Option<Vec<u8>>>),
+    LoadState(Vec<u8>),
+    Shutdown(Sender<()>),
+}
+
+// Draws a picture on a dedicated thread, so a slow plugin doesn't hold back the others
+pub struct PluginWorker {
+    id: String,
+    requests: Sender<Request>,
+    frames: Receiver<Matrix>,
+    last_frame: Matrix,
+    // Set while a requested frame hasn't been received yet
+    pending: bool,
+    deadline: Instant,
+    stats: Arc<FrameStats>,
+    // Whether `stats` is in `FRAME_STATS`
+    registered: bool,
+}
+
+impl PluginWorker {
+    pub fn spawn<P>(id: &str, mut picture: P) -> Self
+    where
+        P: Picture + Send + 'static,
+    {
+        let (requests, request_rx) = channel::<Request>();
+        let (frame_tx, frames) = channel::<Matrix>();
+
+        std::thread::Builder::new()
+            .name(format!("plugin-{}", id))
+            .spawn(move || {
+                for request in request_rx {
+                    match request {
+                        Request::Draw => {
+                            if frame_tx.send(picture.draw()).is_err() {
+                                break;
+                            }
+                        }
+                        Request::SaveState(reply) => {
+                            let _ = reply.send(picture.save_state());
+                        }
+                        Request::LoadState(state) => picture.load_state(&state),
+                        Request::Shutdown(reply) => {
+                            picture.shutdown();
+                            let _ = reply.send(());
+                            break;
+                        }
+                    }
+                }
+            })
+            .unwrap_or_else(|err| {
+                error!("Failed to spawn worker thread for plugin: {}, with error: {}", id, err);
+                std::process::exit(1)
+            });
+
+        Self {
+            id: id.to_string(),
+            requests,
+            frames,
+            last_frame: Matrix::default(),
+            pending: false,
+            deadline: Instant::now(),
+            stats: Arc::new(FrameStats::default()),
+            registered: false,
+        }
+    }
+
+    fn request<T>(&self, request: impl FnOnce(Sender<T>) -> Request) -> Option<T> {
+        let (reply_tx, reply) = channel();
+        self.requests.send(request(reply_tx)).ok()?;
+        match reply.recv_timeout(REQUEST_TIMEOUT) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                warn!("Plugin worker for '{}' didn't respond: {}", self.id, err);
+                None
+            }
+        }
+    }
+}
+
+impl Picture for PluginWorker {
+    fn begin_draw(&mut self, deadline: Instant) {
+        self.deadline = deadline;
+        // A plugin still busy with the previous frame gets no new request
+        if !self.pending {
+            self.pending = self.requests.send(Request::Draw).is_ok();
+        }
+    }
+
+    fn draw(&mut self) -> Matrix {
+        if !self.pending {
+            // Drawn outside of a frame started by the canvas
+            self.begin_draw(Instant::now() + FRAME_DEADLINE);
+        }
+
+        let timeout = self.deadline.saturating_duration_since(Instant::now());
+        match self.frames.recv_timeout(timeout) {
+            Ok(frame) => {
+                self.pending = false;
+                self.last_frame = frame;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let late = self.stats.late.fetch_add(1, Ordering::Relaxed) + 1;
+                debug!("Plugin '{}' missed the frame deadline ({} late frames)", self.id, late);
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                // Worker is gone, keep showing the last frame
+                self.pending = false;
+            }
+        }
+
+        // Only workers which made it onto the canvas are drawn, a reload which failed doesn't replace the stats
+        // of the running instance
+        if !self.registered {
+            FRAME_STATS
+                .lock()
+                .unwrap()
+                .insert(self.id.clone(), Arc::clone(&self.stats));
+            self.registered = true;
+        }
+        self.stats.frames.fetch_add(1, Ordering::Relaxed);
+        self.last_frame.clone()
+    }
+
+    fn shutdown(&mut self) {
+        self.request(Request::Shutdown);
+
+        let mut frame_stats = FRAME_STATS.lock().unwrap();
+        if frame_stats
+            .get(&self.id)
+            .is_some_and(|stats| Arc::ptr_eq(stats, &self.stats))
+        {
+            frame_stats.remove(&self.id);
+        }
+    }
+
+    fn save_state(&mut self) -> Option<Vec<u8>> {
+        self.request(Request::SaveState).flatten()
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let _ = self.requests.send(Request::LoadState(state.to_vec()));
+    }
+}
+
+#[cfg(test)]
+mod plugin_worker_tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    use crate::matrix::Matrix;
+    use crate::picture::Picture;
+    use crate::plugin_worker::{PluginWorker, FRAME_STATS};
+
+    // Lights the first pixel with the number of frames drawn, taking `delay` for each
+    struct SlowPicture {
+        delay: Duration,
+        frames: u8,
+    }
+
+    impl Picture for SlowPicture {
+        fn draw(&mut self) -> Matrix {
+            sleep(self.delay);
+            self.frames += 1;
+            Matrix::from_picture(vec![self.frames], 1, 1)
+        }
+    }
+
+    #[test]
+    fn reuses_last_frame_when_deadline_is_missed() {
+        let picture = SlowPicture {
+            delay: Duration::from_millis(100),
+            frames: 0,
+        };
+        let mut worker = PluginWorker::spawn("slow", picture);
+
+        worker.begin_draw(Instant::now() + Duration::from_millis(500));
+        let first = worker.draw();
+        worker.begin_draw(Instant::now() + Duration::from_millis(10));
+        let late = worker.draw();
+
+        assert_eq!(first.get_el(0, 0), 1);
+        assert_eq!(late.get_el(0, 0), 1);
+        assert_eq!(worker.stats.late.load(Ordering::Relaxed), 1);
+        assert_eq!(worker.stats.frames.load(Ordering::Relaxed), 2);
+
+        worker.shutdown();
+    }
+
+    #[test]
+    fn keeps_stats_of_drawn_worker() {
+        let picture = || SlowPicture {
+            delay: Duration::ZERO,
+            frames: 0,
+        };
+        let mut running = PluginWorker::spawn("reloaded", picture());
+        running.draw();
+
+        // Built for a reload which then failed
+        let rejected = PluginWorker::spawn("reloaded", picture());
+        drop(rejected);
+
+        let registered = FRAME_STATS.lock().unwrap()["reloaded"].clone();
+        assert!(Arc::ptr_eq(&registered, &running.stats));
+        running.shutdown();
+        assert!(!FRAME_STATS.lock().unwrap().contains_key("reloaded"));
+    }
+}
@@ -0,0 +1,171 @@
+use std::env::current_exe;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::matrix::{MATRIX_HEIGHT, MATRIX_WIDTH};
+
+const SDK_DIR: &str = "sdk/fw-led-plugin-sdk";
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Language {
+    #[value(name = "assemblyscript")]
+    AssemblyScript,
+    Rust,
+}
+
+// Project files relative to the project directory, with `{{placeholders}}` for plugin properties
+const ASSEMBLYSCRIPT_TEMPLATE: [(&str, &str); 9] = [
+    ("package.json", include_str!("../templates/new-plugin/assemblyscript/package.json.tmpl")),
+    ("asconfig.json", include_str!("../templates/new-plugin/assemblyscript/asconfig.json.tmpl")),
+    ("assembly/index.ts", include_str!("../templates/new-plugin/assemblyscript/assembly/index.ts.tmpl")),
+    ("assembly/env.d.ts", include_str!("../templates/new-plugin/assemblyscript/assembly/env.d.ts.tmpl")),
+    ("assembly/transform.ts", include_str!("../templates/new-plugin/assemblyscript/assembly/transform.ts.tmpl")),
+    ("assembly/tsconfig.json", include_str!("../templates/new-plugin/assemblyscript/assembly/tsconfig.json.tmpl")),
+    ("tests/index.js", include_str!("../templates/new-plugin/assemblyscript/tests/index.js.tmpl")),
+    (".gitignore", include_str!("../templates/new-plugin/assemblyscript/gitignore.tmpl")),
+    ("README.md", include_str!("../templates/new-plugin/assemblyscript/README.md.tmpl")),
+];
+
+const RUST_TEMPLATE: [(&str, &str); 4] = [
+    ("Cargo.toml", include_str!("../templates/new-plugin/rust/Cargo.toml.tmpl")),
+    ("src/lib.rs", include_str!("../templates/new-plugin/rust/src/lib.rs.tmpl")),
+    (".gitignore", include_str!("../templates/new-plugin/rust/gitignore.tmpl")),
+    ("README.md", include_str!("../templates/new-plugin/rust/README.md.tmpl")),
+];
+
+// Generate a plugin project in `output`, `./<name>` by default. Returns the project directory.
+// Rust projects depend on the SDK at `sdk_path` when given, see `sdk_dependency` otherwise.
+pub fn new_plugin(
+    name: &str,
+    width: usize,
+    height: usize,
+    language: Language,
+    output: Option<PathBuf>,
+    sdk_path: Option<&Path>,
+) -> Result<PathBuf, String> {
+    let valid_name = name
+        .chars()
+        .all(|char| char.is_ascii_lowercase() || char.is_ascii_digit() || char == '-');
+    if name.is_empty() || !valid_name || name.starts_with('-') {
+        return Err(format!(
+            "invalid plugin name '{}', use lowercase letters, digits and dashes",
+            name
+        ));
+    }
+    if !(1..=MATRIX_WIDTH).contains(&width) {
+        return Err(format!("width should be between 1 and {}", MATRIX_WIDTH));
+    }
+    if !(1..=MATRIX_HEIGHT).contains(&height) {
+        return Err(format!("height should be between 1 and {}", MATRIX_HEIGHT));
+    }
+
+    let dir = output.unwrap_or_else(|| PathBuf::from(name));
+    let is_empty = fs::read_dir(&dir).map(|mut entries| entries.next().is_none());
+    if let Ok(false) = is_empty {
+        return Err(format!("{} already exists and isn't empty", dir.display()));
+    }
+
+    let (template, sdk_dependency): (&[(&str, &str)], String) = match language {
+        Language::AssemblyScript => (&ASSEMBLYSCRIPT_TEMPLATE, String::new()),
+        Language::Rust => (&RUST_TEMPLATE, sdk_dependency(&dir, sdk_path)?),
+    };
+    let placeholders = [
+        ("{{name}}", name.to_string()),
+        ("{{crate_name}}", name.replace('-', "_")),
+        ("{{width}}", width.to_string()),
+        ("{{height}}", height.to_string()),
+        ("{{sdk_dependency}}", sdk_dependency),
+    ];
+
+    for (path, contents) in template {
+        let contents = placeholders
+            .iter()
+            .fold(contents.to_string(), |contents, (placeholder, value)| {
+                contents.replace(placeholder, value)
+            });
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| format!("failed to create {}: {}", parent.display(), err))?;
+        }
+        fs::write(&path, contents).map_err(|err| format!("failed to write {}: {}", path.display(), err))?;
+    }
+    Ok(dir)
+}
+
+// Projects created in a checkout of this repository use the SDK next to them, others the one next to the
+// executable of a development build. The SDK isn't published, so released binaries need `--sdk-path`.
+fn sdk_dependency(project_dir: &Path, sdk_path: Option<&Path>) -> Result<String, String> {
+    if let Some(sdk_path) = sdk_path {
+        if !sdk_path.join("Cargo.toml").is_file() {
+            return Err(format!("{} doesn't contain the plugin SDK crate", sdk_path.display()));
+        }
+        let sdk_path = std::path::absolute(sdk_path).unwrap_or(sdk_path.to_path_buf());
+        return Ok(format!("fw-led-plugin-sdk = {{ path = \"{}\" }}", sdk_path.display()));
+    }
+
+    let project_dir = std::path::absolute(project_dir).unwrap_or(project_dir.to_path_buf());
+    let in_checkout = project_dir
+        .ancestors()
+        .skip(1)
+        .position(|dir| dir.join(SDK_DIR).join("Cargo.toml").is_file());
+    if let Some(depth) = in_checkout {
+        let path = format!("{}{}", "../".repeat(depth + 1), SDK_DIR);
+        return Ok(format!("fw-led-plugin-sdk = {{ path = \"{}\" }}", path));
+    }
+
+    let next_to_exe = current_exe()
+        .ok()
+        .and_then(|exe| exe.ancestors().map(|dir| dir.join(SDK_DIR)).find(|sdk| sdk.join("Cargo.toml").is_file()));
+    match next_to_exe {
+        Some(sdk) => Ok(format!("fw-led-plugin-sdk = {{ path = \"{}\" }}", sdk.display())),
+        None => Err(format!(
+            "the plugin SDK isn't published, pass --sdk-path with the {} directory of a fw-led-stat-control checkout",
+            SDK_DIR
+        )),
+    }
+}
+
+#[cfg(test)]
+mod scaffold_tests {
+    use std::fs;
+
+    use crate::scaffold::{new_plugin, Language};
+
+    #[test]
+    fn generates_projects_from_templates() {
+        let root = std::env::temp_dir().join("fw-led-scaffold");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sdk/fw-led-plugin-sdk")).unwrap();
+        fs::write(root.join("sdk/fw-led-plugin-sdk/Cargo.toml"), "").unwrap();
+
+        let rust = new_plugin("cpu-bar", 2, 10, Language::Rust, Some(root.join("plugins/cpu-bar")), None).unwrap();
+        let assemblyscript =
+            new_plugin("cpu-bar", 3, 4, Language::AssemblyScript, Some(root.join("cpu-bar-as")), None).unwrap();
+        let sdk_path = root.join("sdk/fw-led-plugin-sdk");
+        let outside = new_plugin("cpu-bar", 2, 10, Language::Rust, Some(root.join("outside")), Some(&sdk_path));
+
+        let cargo_toml = fs::read_to_string(rust.join("Cargo.toml")).unwrap();
+        let lib = fs::read_to_string(rust.join("src/lib.rs")).unwrap();
+        let transform = fs::read_to_string(assemblyscript.join("assembly/transform.ts")).unwrap();
+        assert!(cargo_toml.contains("fw-led-plugin-sdk = { path = \"../../sdk/fw-led-plugin-sdk\" }"));
+        let outside = fs::read_to_string(outside.unwrap().join("Cargo.toml")).unwrap();
+        assert!(outside.contains(&format!("fw-led-plugin-sdk = {{ path = \"{}\" }}", sdk_path.display())));
+        assert!(lib.contains("#[plugin(name = \"cpu-bar\", width = 2, height = 10"));
+        assert!(transform.contains("width: 3,\n      height: 4,"));
+        assert!(assemblyscript.join("tests/index.js").is_file());
+        assert!(!lib.contains("{{") && !transform.contains("{{"));
+        assert_eq!(
+            new_plugin("cpu-bar", 2, 10, Language::Rust, Some(rust), None),
+            Err(format!("{} already exists and isn't empty", root.join("plugins/cpu-bar").display()))
+        );
+        assert!(new_plugin("Cpu", 2, 10, Language::Rust, None, None).is_err());
+        assert!(new_plugin("cpu", 10, 10, Language::Rust, None, None).is_err());
+        assert_eq!(
+            new_plugin("cpu-bar", 2, 10, Language::Rust, Some(root.join("no-sdk")), Some(&root)),
+            Err(format!("{} doesn't contain the plugin SDK crate", root.display()))
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use wasmer::{FunctionEnvMut, MemoryView};
+
+use crate::wasm_module::PluginEnv;
+
+// Longer keys can't name a setting, they're rejected before reading them from plugin memory
+const MAX_KEY_LENGTH: usize = 256;
+
+// Declared by plugins under `settings_schema` in the `metadata` custom section, keyed by setting name
+pub type SettingsSchema = BTreeMap<String, SettingSchema>;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SettingSchema {
+    #[serde(rename = "type")]
+    pub kind: SettingType,
+    // Settings without a default are required
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<toml::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SettingType {
+    Integer,
+    Float,
+    Boolean,
+    String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SettingsError {
+    UnknownKey(String),
+    MissingKey(String),
+    InvalidType {
+        key: String,
+        expected: SettingType,
+    },
+    OutOfRange {
+        key: String,
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+}
+
+impl Display for SettingType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SettingType::Integer => "integer",
+            SettingType::Float => "float",
+            SettingType::Boolean => "boolean",
+            SettingType::String => "string",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for SettingsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::UnknownKey(key) => write!(f, "unknown setting '{}'", key),
+            SettingsError::MissingKey(key) => write!(f, "missing required setting '{}'", key),
+            SettingsError::InvalidType { key, expected } => {
+                write!(f, "setting '{}' should be of type {}", key, expected)
+            }
+            SettingsError::OutOfRange { key, min, max } => {
+                let min = min.map(|min| min.to_string()).unwrap_or("-inf".to_string());
+                let max = max.map(|max| max.to_string()).unwrap_or("inf".to_string());
+                write!(f, "setting '{}' should be in range [{}, {}]", key, min, max)
+            }
+        }
+    }
+}
+
+// Check settings against the schema and fill in defaults. All problems are reported at once.
+pub fn validate_settings(
+    schema: &SettingsSchema,
+    settings: &toml::Table,
+) -> Result<toml::Table, Vec<SettingsError>> {
+    let mut errors = vec![];
+    let mut validated = toml::Table::new();
+
+    for key in settings.keys() {
+        if !schema.contains_key(key) {
+            errors.push(SettingsError::UnknownKey(key.clone()));
+        }
+    }
+
+    for (key, setting) in schema {
+        let value = match settings.get(key).or(setting.default.as_ref()) {
+            Some(value) => value,
+            None => {
+                errors.push(SettingsError::MissingKey(key.clone()));
+                continue;
+            }
+        };
+
+        let number = match (setting.kind, value) {
+            (SettingType::Integer, toml::Value::Integer(value)) => Some(*value as f64),
+            (SettingType::Float, toml::Value::Float(value)) => Some(*value),
+            // Integers are accepted where floats are expected, `1` reads better than `1.0`
+            (SettingType::Float, toml::Value::Integer(value)) => Some(*value as f64),
+            (SettingType::Boolean, toml::Value::Boolean(_))
+            | (SettingType::String, toml::Value::String(_)) => None,
+            _ => {
+                errors.push(SettingsError::InvalidType {
+                    key: key.clone(),
+                    expected: setting.kind,
+                });
+                continue;
+            }
+        };
+
+        if let Some(number) = number {
+            let below_min = setting.min.is_some_and(|min| number < min);
+            let above_max = setting.max.is_some_and(|max| number > max);
+            if below_min || above_max {
+                errors.push(SettingsError::OutOfRange {
+                    key: key.clone(),
+                    min: setting.min,
+                    max: setting.max,
+                });
+                continue;
+            }
+        }
+
+        let value = match (setting.kind, value) {
+            (SettingType::Float, toml::Value::Integer(value)) => toml::Value::Float(*value as f64),
+            _ => value.clone(),
+        };
+        validated.insert(key.clone(), value);
+    }
+
+    if errors.is_empty() {
+        Ok(validated)
+    } else {
+        Err(errors)
+    }
+}
+
+// Host imports reading settings by key. Keys are passed as UTF-8 (ptr, len) pairs.
+pub fn get_setting_i64(env: FunctionEnvMut<PluginEnv>, key_ptr: i32, key_len: i32) -> i64 {
+    match lookup_setting(&env, key_ptr, key_len) {
+        Some(toml::Value::Integer(value)) => value,
+        Some(toml::Value::Float(value)) => value as i64,
+        Some(toml::Value::Boolean(value)) => value as i64,
+        _ => 0,
+    }
+}
+
+pub fn get_setting_f64(env: FunctionEnvMut<PluginEnv>, key_ptr: i32, key_len: i32) -> f64 {
+    match lookup_setting(&env, key_ptr, key_len) {
+        Some(toml::Value::Float(value)) => value,
+        Some(toml::Value::Integer(value)) => value as f64,
+        _ => 0.0,
+    }
+}
+
+// Copies up to `buf_len` bytes of a string setting and returns its full length, or -1 if missing
+pub fn get_setting_string(
+    env: FunctionEnvMut<PluginEnv>,
+    key_ptr: i32,
+    key_len: i32,
+    buf_ptr: i32,
+    buf_len: i32,
+) -> i32 {
+    let Some(toml::Value::String(value)) = lookup_setting(&env, key_ptr, key_len) else {
+        return -1;
+    };
+    let Some(memory) = &env.data().memory else {
+        return -1;
+    };
+
+    let bytes = value.as_bytes();
+    let len = bytes.len().min(buf_len.max(0) as usize);
+    match memory.view(&env).write(buf_ptr as u32 as u64, &bytes[..len]) {
+        Ok(()) => bytes.len() as i32,
+        Err(_) => -1,
+    }
+}
+
+pub(crate) fn lookup_setting(env: &FunctionEnvMut<PluginEnv>, key_ptr: i32, key_len: i32) -> Option<toml::Value> {
+    let data = env.data();
+    let memory = data.memory.as_ref()?;
+
+    let Some(key) = read_key(&memory.view(env), key_ptr, key_len) else {
+        warn!(target: "WASM", "'{}' module requested a setting with an invalid key", data.name);
+        return None;
+    };
+
+    let value = data.settings.get(&key).cloned();
+    if value.is_none() {
+        warn!(target: "WASM", "'{}' module requested unknown setting '{}'", data.name, key);
+    }
+    value
+}
+
+fn read_key(view: &MemoryView, key_ptr: i32, key_len: i32) -> Option<String> {
+    let key_len = usize::try_from(key_len).ok().filter(|len| *len <= MAX_KEY_LENGTH)?;
+    let mut key = vec![0u8; key_len];
+    view.read(key_ptr as u32 as u64, &mut key).ok()?;
+    String::from_utf8(key).ok()
+}
+
+#[cfg(test)]
+mod settings_tests {
+    use wasmer::{Memory, MemoryType, Store};
+
+    use crate::settings::{read_key, validate_settings, SettingType, SettingsError, SettingsSchema};
+
+    fn schema() -> SettingsSchema {
+        serde_json::from_str(
+            r#"{
+                "lit_value": { "type": "integer", "default": 255, "min": 0, "max": 255 },
+                "scale": { "type": "float", "default": 1.5 },
+                "label": { "type": "string" }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        let settings = toml::from_str("label = 'cpu'\nscale = 2").unwrap();
+
+        let validated = validate_settings(&schema(), &settings).unwrap();
+
+        assert_eq!(validated["lit_value"], toml::Value::Integer(255));
+        assert_eq!(validated["scale"], toml::Value::Float(2.0));
+        assert_eq!(validated["label"], toml::Value::String("cpu".to_string()));
+    }
+
+    #[test]
+    fn reports_all_errors() {
+        let settings = toml::from_str("lit_value = 300\nscale = 'big'\nunknown = 1").unwrap();
+
+        let errors = validate_settings(&schema(), &settings).unwrap_err();
+
+        assert_eq!(
+            errors,
+            vec![
+                SettingsError::UnknownKey("unknown".to_string()),
+                SettingsError::MissingKey("label".to_string()),
+                SettingsError::OutOfRange {
+                    key: "lit_value".to_string(),
+                    min: Some(0.0),
+                    max: Some(255.0),
+                },
+                SettingsError::InvalidType {
+                    key: "scale".to_string(),
+                    expected: SettingType::Float,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_key_lengths() {
+        let mut store = Store::default();
+        let memory = Memory::new(&mut store, MemoryType::new(1, None, false)).unwrap();
+        let view = memory.view(&store);
+        view.write(0, b"label").unwrap();
+
+        assert_eq!(read_key(&view, 0, 5), Some("label".to_string()));
+        assert_eq!(read_key(&view, 0, -1), None);
+        assert_eq!(read_key(&view, 0, i32::MAX), None);
+        assert_eq!(read_key(&view, 65535, 2), None);
+    }
+}
@@ -0,0 +1,202 @@
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::system_stat_monitor::SystemStatMonitor;
+
+// Source of the values returned by the stat host imports
+pub trait StatProvider: Send + Sync {
+    fn global_cpu_usage(&self) -> f32;
+
+    fn memory_usage(&self) -> f32;
+
+    fn battery_state_of_charge(&self) -> f32;
+
+    fn epoch_time(&self) -> u64;
+
+    // Called once before every frame a plugin draws, providers replaying data move on to the next sample
+    fn next_frame(&self) {}
+}
+
+// Values of every stat at one point in time
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(default)]
+pub struct HostStats {
+    pub global_cpu_usage: f32,
+    pub memory_usage: f32,
+    pub battery_state_of_charge: f32,
+    pub epoch_time: u64,
+}
+
+// Live values from the system, shared by all plugins
+pub struct SystemStats;
+
+static SYSTEM_STAT_MONITOR: LazyLock<Mutex<SystemStatMonitor>> =
+    LazyLock::new(|| Mutex::new(SystemStatMonitor::new()));
+
+pub fn system_stats() -> Arc<dyn StatProvider> {
+    Arc::new(SystemStats)
+}
+
+impl StatProvider for SystemStats {
+    fn global_cpu_usage(&self) -> f32 {
+        SYSTEM_STAT_MONITOR.lock().unwrap().get_global_cpu_usage()
+    }
+
+    fn memory_usage(&self) -> f32 {
+        SYSTEM_STAT_MONITOR.lock().unwrap().get_memory_usage()
+    }
+
+    fn battery_state_of_charge(&self) -> f32 {
+        SystemStatMonitor::get_battery_state_of_charge()
+    }
+
+    fn epoch_time(&self) -> u64 {
+        SystemStatMonitor::get_epoch_time()
+    }
+}
+
+// Values set by the caller, e.g. a test stepping through a scenario
+#[derive(Default)]
+pub struct ScriptedStats {
+    stats: Mutex<HostStats>,
+}
+
+impl ScriptedStats {
+    pub fn new(stats: HostStats) -> Self {
+        Self {
+            stats: Mutex::new(stats),
+        }
+    }
+
+    pub fn set(&self, stats: HostStats) {
+        *self.stats.lock().unwrap() = stats;
+    }
+
+    fn get(&self) -> HostStats {
+        *self.stats.lock().unwrap()
+    }
+}
+
+impl StatProvider for ScriptedStats {
+    fn global_cpu_usage(&self) -> f32 {
+        self.get().global_cpu_usage
+    }
+
+    fn memory_usage(&self) -> f32 {
+        self.get().memory_usage
+    }
+
+    fn battery_state_of_charge(&self) -> f32 {
+        self.get().battery_state_of_charge
+    }
+
+    fn epoch_time(&self) -> u64 {
+        self.get().epoch_time
+    }
+}
+
+// Samples replayed one per frame, e.g. by a test driving a plugin through a recorded day, starting over after
+// the last one. Frames are counted per provider, so every plugin instance needs its own. Stored as JSON lines,
+// one `HostStats` per line.
+pub struct RecordedStats {
+    samples: Vec<HostStats>,
+    // Advanced before the first frame, so it starts one before the first sample
+    position: AtomicUsize,
+}
+
+impl RecordedStats {
+    pub fn new(samples: Vec<HostStats>) -> Result<Self, String> {
+        if samples.is_empty() {
+            return Err("recording has no samples".to_string());
+        }
+        Ok(Self {
+            position: AtomicUsize::new(samples.len() - 1),
+            samples,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let recording = fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        let samples = recording
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line)
+                    .map_err(|err| format!("{}:{}: {}", path.display(), index + 1, err))
+            })
+            .collect::<Result<Vec<HostStats>, String>>()?;
+
+        Self::new(samples)
+    }
+
+    pub fn save(samples: &[HostStats], path: &Path) -> Result<(), String> {
+        let recording = samples
+            .iter()
+            .map(|sample| serde_json::to_string(sample).map(|line| line + "\n"))
+            .collect::<Result<String, _>>()
+            .map_err(|err| err.to_string())?;
+        fs::write(path, recording).map_err(|err| format!("failed to write {}: {}", path.display(), err))
+    }
+
+    pub fn samples(&self) -> &[HostStats] {
+        &self.samples
+    }
+
+    fn get(&self) -> HostStats {
+        self.samples[self.position.load(Ordering::Relaxed)]
+    }
+}
+
+impl StatProvider for RecordedStats {
+    fn global_cpu_usage(&self) -> f32 {
+        self.get().global_cpu_usage
+    }
+
+    fn memory_usage(&self) -> f32 {
+        self.get().memory_usage
+    }
+
+    fn battery_state_of_charge(&self) -> f32 {
+        self.get().battery_state_of_charge
+    }
+
+    fn epoch_time(&self) -> u64 {
+        self.get().epoch_time
+    }
+
+    fn next_frame(&self) {
+        let next = (self.position.load(Ordering::Relaxed) + 1) % self.samples.len();
+        self.position.store(next, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod stat_provider_tests {
+    use crate::stat_provider::{HostStats, RecordedStats, StatProvider};
+
+    #[test]
+    fn replays_recording_one_sample_per_frame() {
+        let path = std::env::temp_dir().join("fw-led-recorded-stats.jsonl");
+        let samples = [10, 11, 12].map(|epoch_time| HostStats {
+            epoch_time,
+            ..HostStats::default()
+        });
+        RecordedStats::save(&samples, &path).unwrap();
+        let recorded = RecordedStats::load(&path).unwrap();
+
+        let epoch_times = (0..4)
+            .map(|_| {
+                recorded.next_frame();
+                recorded.epoch_time()
+            })
+            .collect::<Vec<u64>>();
+
+        assert_eq!(epoch_times, vec![10, 11, 12, 10]);
+    }
+}
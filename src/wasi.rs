@@ -0,0 +1,1412 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::{File, Metadata};
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Component, Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use byteorder::{ByteOrder, LittleEndian};
+use log::{info, warn};
+use wasmer::{Function, FunctionEnv, FunctionEnvMut, Imports, MemoryView, RuntimeError, Store};
+
+use crate::canvas::FRAME_DEADLINE;
+use crate::config::WasiConf;
+use crate::permissions::Capability;
+use crate::plugin_log::log_target;
+use crate::wasm_module::{PluginEnv, WASMError};
+
+// Subset of WASI preview1 errno values used by the host
+type Errno = i32;
+const ERRNO_SUCCESS: Errno = 0;
+const ERRNO_ACCES: Errno = 2;
+const ERRNO_BADF: Errno = 8;
+const ERRNO_FAULT: Errno = 21;
+const ERRNO_INVAL: Errno = 28;
+const ERRNO_IO: Errno = 29;
+const ERRNO_NAMETOOLONG: Errno = 37;
+const ERRNO_NOENT: Errno = 44;
+const ERRNO_NOTDIR: Errno = 54;
+const ERRNO_NOTSUP: Errno = 58;
+const ERRNO_ROFS: Errno = 69;
+const ERRNO_SPIPE: Errno = 70;
+const ERRNO_NOTCAPABLE: Errno = 76;
+
+const FILETYPE_UNKNOWN: u8 = 0;
+const FILETYPE_CHARACTER_DEVICE: u8 = 2;
+const FILETYPE_DIRECTORY: u8 = 3;
+const FILETYPE_REGULAR_FILE: u8 = 4;
+const FILETYPE_SYMBOLIC_LINK: u8 = 7;
+
+const RIGHTS_FD_READ: u64 = 1 << 1;
+const RIGHTS_FD_SEEK: u64 = 1 << 2;
+const RIGHTS_FD_TELL: u64 = 1 << 5;
+const RIGHTS_FD_WRITE: u64 = 1 << 6;
+const RIGHTS_FD_ADVISE: u64 = 1 << 7;
+const RIGHTS_PATH_OPEN: u64 = 1 << 13;
+const RIGHTS_FD_READDIR: u64 = 1 << 14;
+const RIGHTS_PATH_FILESTAT_GET: u64 = 1 << 18;
+const RIGHTS_FD_FILESTAT_GET: u64 = 1 << 21;
+const RIGHTS_POLL_FD_READWRITE: u64 = 1 << 27;
+
+const FILE_RIGHTS: u64 = RIGHTS_FD_READ
+    | RIGHTS_FD_SEEK
+    | RIGHTS_FD_TELL
+    | RIGHTS_FD_ADVISE
+    | RIGHTS_FD_FILESTAT_GET
+    | RIGHTS_POLL_FD_READWRITE;
+const DIR_RIGHTS: u64 =
+    RIGHTS_PATH_OPEN | RIGHTS_FD_READDIR | RIGHTS_PATH_FILESTAT_GET | RIGHTS_FD_FILESTAT_GET;
+
+const OFLAGS_CREAT: i32 = 1 << 0;
+const OFLAGS_DIRECTORY: i32 = 1 << 1;
+const OFLAGS_EXCL: i32 = 1 << 2;
+const OFLAGS_TRUNC: i32 = 1 << 3;
+const FDFLAGS_APPEND: i32 = 1 << 0;
+
+const CLOCK_REALTIME: i32 = 0;
+const CLOCK_MONOTONIC: i32 = 1;
+const CLOCK_PROCESS_CPUTIME: i32 = 2;
+const CLOCK_THREAD_CPUTIME: i32 = 3;
+
+const STDIN_FD: u32 = 0;
+const STDOUT_FD: u32 = 1;
+const STDERR_FD: u32 = 2;
+const PREOPEN_FD: u32 = 3;
+
+const EVENTTYPE_CLOCK: u8 = 0;
+const SUBCLOCKFLAGS_ABSTIME: u16 = 1 << 0;
+
+const DIRENT_HEADER_SIZE: usize = 24;
+const SUBSCRIPTION_SIZE: u32 = 48;
+const EVENT_SIZE: u32 = 32;
+
+// Guest buffers are copied through host buffers of at most this size, whatever length the guest passes
+const IO_CHUNK_SIZE: usize = 64 * 1024;
+const MAX_IOVECS: u32 = 1024;
+const MAX_PATH_LEN: u32 = 4096;
+// Output without a newline is logged once it grows past this length
+const MAX_LINE_LEN: usize = 4096;
+
+// Per-plugin WASI state: fd table, preopened directory and buffered stdio lines
+pub struct WasiCtx {
+    args: Vec<String>,
+    preopen: Option<Preopen>,
+    fds: HashMap<u32, WasiFd>,
+    next_fd: u32,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    started: Instant,
+    // Wall clock time is gated behind the 'time' capability, monotonic clocks are always available
+    realtime_clock: bool,
+}
+
+struct Preopen {
+    host_path: PathBuf,
+    guest_path: String,
+}
+
+enum WasiFd {
+    File(File),
+    Dir(PathBuf),
+}
+
+impl WasiCtx {
+    // The preopened directory is only linked for plugins granted the 'fs' capability
+    pub fn new(
+        plugin_name: &str,
+        conf: &WasiConf,
+        granted: &HashSet<Capability>,
+    ) -> Result<Self, WASMError> {
+        let preopen = match &conf.preopen {
+            Some(_) if !granted.contains(&Capability::Fs) => return Err(WASMError::FsPermissionRequired),
+            Some(path) => Some(Preopen {
+                host_path: fs::canonicalize(path).map_err(|err| WASMError::WasiSetupFailed(err.to_string()))?,
+                guest_path: conf.guest_path.clone(),
+            }),
+            None => None,
+        };
+
+        let mut fds = HashMap::new();
+        if let Some(preopen) = &preopen {
+            fds.insert(PREOPEN_FD, WasiFd::Dir(preopen.host_path.clone()));
+        }
+
+        Ok(Self {
+            args: vec![plugin_name.to_string()],
+            preopen,
+            fds,
+            next_fd: PREOPEN_FD + 1,
+            stdout: vec![],
+            stderr: vec![],
+            started: Instant::now(),
+            realtime_clock: granted.contains(&Capability::Time),
+        })
+    }
+
+    fn insert_fd(&mut self, fd: WasiFd) -> u32 {
+        let id = self.next_fd;
+        self.next_fd += 1;
+        self.fds.insert(id, fd);
+        id
+    }
+
+    // Resolve a guest path relative to a directory fd, without escaping the preopened directory
+    fn resolve_path(&self, dir_fd: u32, path: &str) -> Result<PathBuf, Errno> {
+        let root = &self.preopen.as_ref().ok_or(ERRNO_BADF)?.host_path;
+        let mut resolved = match self.fds.get(&dir_fd) {
+            Some(WasiFd::Dir(path)) => path.clone(),
+            Some(WasiFd::File(_)) => return Err(ERRNO_NOTDIR),
+            None => return Err(ERRNO_BADF),
+        };
+
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(part) => resolved.push(part),
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if resolved == *root {
+                        return Err(ERRNO_NOTCAPABLE);
+                    }
+                    resolved.pop();
+                }
+                Component::RootDir | Component::Prefix(_) => return Err(ERRNO_NOTCAPABLE),
+            }
+        }
+
+        // Symlinks inside the directory may still point outside of it
+        let canonical = fs::canonicalize(&resolved).map_err(errno_from_io)?;
+        if !canonical.starts_with(root) {
+            return Err(ERRNO_NOTCAPABLE);
+        }
+        Ok(canonical)
+    }
+}
+
+pub fn register_imports(imports: &mut Imports, store: &mut Store, env: &FunctionEnv<PluginEnv>) {
+    let functions = [
+        (
+            "args_get",
+            Function::new_typed_with_env(store, env, args_get),
+        ),
+        (
+            "args_sizes_get",
+            Function::new_typed_with_env(store, env, args_sizes_get),
+        ),
+        (
+            "environ_get",
+            Function::new_typed_with_env(store, env, environ_get),
+        ),
+        (
+            "environ_sizes_get",
+            Function::new_typed_with_env(store, env, environ_sizes_get),
+        ),
+        (
+            "clock_res_get",
+            Function::new_typed_with_env(store, env, clock_res_get),
+        ),
+        (
+            "clock_time_get",
+            Function::new_typed_with_env(store, env, clock_time_get),
+        ),
+        (
+            "fd_advise",
+            Function::new_typed_with_env(store, env, fd_advise),
+        ),
+        (
+            "fd_allocate",
+            Function::new_typed_with_env(store, env, fd_allocate),
+        ),
+        (
+            "fd_close",
+            Function::new_typed_with_env(store, env, fd_close),
+        ),
+        (
+            "fd_datasync",
+            Function::new_typed_with_env(store, env, fd_sync),
+        ),
+        (
+            "fd_fdstat_get",
+            Function::new_typed_with_env(store, env, fd_fdstat_get),
+        ),
+        (
+            "fd_fdstat_set_flags",
+            Function::new_typed_with_env(store, env, fd_fdstat_set_flags),
+        ),
+        (
+            "fd_fdstat_set_rights",
+            Function::new_typed_with_env(store, env, fd_fdstat_set_rights),
+        ),
+        (
+            "fd_filestat_get",
+            Function::new_typed_with_env(store, env, fd_filestat_get),
+        ),
+        (
+            "fd_filestat_set_size",
+            Function::new_typed_with_env(store, env, fd_filestat_set_size),
+        ),
+        (
+            "fd_filestat_set_times",
+            Function::new_typed_with_env(store, env, fd_filestat_set_times),
+        ),
+        (
+            "fd_pread",
+            Function::new_typed_with_env(store, env, fd_pread),
+        ),
+        (
+            "fd_prestat_get",
+            Function::new_typed_with_env(store, env, fd_prestat_get),
+        ),
+        (
+            "fd_prestat_dir_name",
+            Function::new_typed_with_env(store, env, fd_prestat_dir_name),
+        ),
+        (
+            "fd_pwrite",
+            Function::new_typed_with_env(store, env, fd_pwrite),
+        ),
+        ("fd_read", Function::new_typed_with_env(store, env, fd_read)),
+        (
+            "fd_readdir",
+            Function::new_typed_with_env(store, env, fd_readdir),
+        ),
+        (
+            "fd_renumber",
+            Function::new_typed_with_env(store, env, fd_renumber),
+        ),
+        ("fd_seek", Function::new_typed_with_env(store, env, fd_seek)),
+        ("fd_sync", Function::new_typed_with_env(store, env, fd_sync)),
+        ("fd_tell", Function::new_typed_with_env(store, env, fd_tell)),
+        (
+            "fd_write",
+            Function::new_typed_with_env(store, env, fd_write),
+        ),
+        (
+            "path_create_directory",
+            Function::new_typed_with_env(store, env, path_modify),
+        ),
+        (
+            "path_filestat_get",
+            Function::new_typed_with_env(store, env, path_filestat_get),
+        ),
+        (
+            "path_filestat_set_times",
+            Function::new_typed_with_env(store, env, path_filestat_set_times),
+        ),
+        (
+            "path_link",
+            Function::new_typed_with_env(store, env, path_link),
+        ),
+        (
+            "path_open",
+            Function::new_typed_with_env(store, env, path_open),
+        ),
+        (
+            "path_readlink",
+            Function::new_typed_with_env(store, env, path_readlink),
+        ),
+        (
+            "path_remove_directory",
+            Function::new_typed_with_env(store, env, path_modify),
+        ),
+        (
+            "path_rename",
+            Function::new_typed_with_env(store, env, path_rename),
+        ),
+        (
+            "path_symlink",
+            Function::new_typed_with_env(store, env, path_symlink),
+        ),
+        (
+            "path_unlink_file",
+            Function::new_typed_with_env(store, env, path_modify),
+        ),
+        (
+            "poll_oneoff",
+            Function::new_typed_with_env(store, env, poll_oneoff),
+        ),
+        (
+            "proc_exit",
+            Function::new_typed_with_env(store, env, proc_exit),
+        ),
+        ("proc_raise", Function::new_typed(store, proc_raise)),
+        ("sched_yield", Function::new_typed(store, sched_yield)),
+        (
+            "random_get",
+            Function::new_typed_with_env(store, env, random_get),
+        ),
+        ("sock_accept", Function::new_typed(store, sock_accept)),
+        ("sock_recv", Function::new_typed(store, sock_recv)),
+        ("sock_send", Function::new_typed(store, sock_send)),
+        ("sock_shutdown", Function::new_typed(store, sock_shutdown)),
+    ];
+
+    for (name, function) in functions {
+        imports.define("wasi_snapshot_preview1", name, function);
+    }
+}
+
+// Run a WASI call against the plugin memory and WASI context, mapping the outcome to an errno
+fn with_ctx<F>(env: &mut FunctionEnvMut<PluginEnv>, call: F) -> Errno
+where
+    F: FnOnce(&mut WasiCtx, &MemoryView, &str) -> Result<(), Errno>,
+{
+    let (data, store) = env.data_and_store_mut();
+    let view = match &data.memory {
+        Some(memory) => memory.view(&store),
+        None => return ERRNO_FAULT,
+    };
+    match data.wasi.as_mut() {
+        Some(ctx) => match call(ctx, &view, &data.name) {
+            Ok(()) => ERRNO_SUCCESS,
+            Err(errno) => errno,
+        },
+        None => ERRNO_NOTSUP,
+    }
+}
+
+fn args_get(mut env: FunctionEnvMut<PluginEnv>, argv: i32, argv_buf: i32) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| {
+        write_string_list(view, &ctx.args, argv, argv_buf)
+    })
+}
+
+fn args_sizes_get(mut env: FunctionEnvMut<PluginEnv>, argc: i32, argv_buf_size: i32) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| {
+        write_string_list_sizes(view, &ctx.args, argc, argv_buf_size)
+    })
+}
+
+fn environ_get(mut env: FunctionEnvMut<PluginEnv>, environ: i32, environ_buf: i32) -> Errno {
+    with_ctx(&mut env, |_, view, _| {
+        write_string_list(view, &[], environ, environ_buf)
+    })
+}
+
+fn environ_sizes_get(mut env: FunctionEnvMut<PluginEnv>, count: i32, buf_size: i32) -> Errno {
+    with_ctx(&mut env, |_, view, _| {
+        write_string_list_sizes(view, &[], count, buf_size)
+    })
+}
+
+fn clock_res_get(mut env: FunctionEnvMut<PluginEnv>, clock_id: i32, resolution: i32) -> Errno {
+    with_ctx(&mut env, |_, view, _| match clock_id {
+        CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_PROCESS_CPUTIME | CLOCK_THREAD_CPUTIME => {
+            write_u64(view, resolution, 1)
+        }
+        _ => Err(ERRNO_INVAL),
+    })
+}
+
+fn clock_time_get(
+    mut env: FunctionEnvMut<PluginEnv>,
+    clock_id: i32,
+    _precision: i64,
+    time: i32,
+) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| {
+        let nanos = match clock_id {
+            CLOCK_REALTIME if !ctx.realtime_clock => return Err(ERRNO_NOTCAPABLE),
+            CLOCK_REALTIME => UNIX_EPOCH.elapsed().map_err(|_| ERRNO_IO)?.as_nanos(),
+            // CPU time clocks are approximated with the time elapsed since the plugin was loaded
+            CLOCK_MONOTONIC | CLOCK_PROCESS_CPUTIME | CLOCK_THREAD_CPUTIME => {
+                ctx.started.elapsed().as_nanos()
+            }
+            _ => return Err(ERRNO_INVAL),
+        };
+        write_u64(view, time, nanos as u64)
+    })
+}
+
+fn fd_advise(
+    mut env: FunctionEnvMut<PluginEnv>,
+    fd: i32,
+    _offset: i64,
+    _len: i64,
+    _advice: i32,
+) -> Errno {
+    with_ctx(&mut env, |ctx, _, _| match ctx.fds.get(&(fd as u32)) {
+        Some(_) => Ok(()),
+        None => Err(ERRNO_BADF),
+    })
+}
+
+fn fd_allocate(_env: FunctionEnvMut<PluginEnv>, _fd: i32, _offset: i64, _len: i64) -> Errno {
+    ERRNO_ROFS
+}
+
+fn fd_close(mut env: FunctionEnvMut<PluginEnv>, fd: i32) -> Errno {
+    with_ctx(&mut env, |ctx, _, _| match fd as u32 {
+        STDIN_FD | STDOUT_FD | STDERR_FD => Ok(()),
+        fd => ctx.fds.remove(&fd).map(|_| ()).ok_or(ERRNO_BADF),
+    })
+}
+
+fn fd_sync(mut env: FunctionEnvMut<PluginEnv>, fd: i32) -> Errno {
+    // Nothing is ever written, so there is nothing to sync
+    with_ctx(&mut env, |ctx, _, _| match fd as u32 {
+        STDIN_FD | STDOUT_FD | STDERR_FD => Ok(()),
+        fd => ctx.fds.get(&fd).map(|_| ()).ok_or(ERRNO_BADF),
+    })
+}
+
+fn fd_fdstat_get(mut env: FunctionEnvMut<PluginEnv>, fd: i32, stat: i32) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| {
+        let (filetype, rights_base, rights_inheriting) = match fd as u32 {
+            STDIN_FD => (FILETYPE_CHARACTER_DEVICE, RIGHTS_FD_READ, 0),
+            STDOUT_FD | STDERR_FD => (FILETYPE_CHARACTER_DEVICE, RIGHTS_FD_WRITE, 0),
+            fd => match ctx.fds.get(&fd) {
+                Some(WasiFd::File(_)) => (FILETYPE_REGULAR_FILE, FILE_RIGHTS, 0),
+                Some(WasiFd::Dir(_)) => (FILETYPE_DIRECTORY, DIR_RIGHTS, DIR_RIGHTS | FILE_RIGHTS),
+                None => return Err(ERRNO_BADF),
+            },
+        };
+
+        let mut buffer = [0u8; 24];
+        buffer[0] = filetype;
+        LittleEndian::write_u64(&mut buffer[8..16], rights_base);
+        LittleEndian::write_u64(&mut buffer[16..24], rights_inheriting);
+        write_bytes(view, stat, &buffer)
+    })
+}
+
+fn fd_fdstat_set_flags(_env: FunctionEnvMut<PluginEnv>, _fd: i32, _flags: i32) -> Errno {
+    ERRNO_NOTSUP
+}
+
+fn fd_fdstat_set_rights(
+    _env: FunctionEnvMut<PluginEnv>,
+    _fd: i32,
+    _rights_base: i64,
+    _rights_inheriting: i64,
+) -> Errno {
+    ERRNO_NOTSUP
+}
+
+fn fd_filestat_get(mut env: FunctionEnvMut<PluginEnv>, fd: i32, stat: i32) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| match fd as u32 {
+        STDIN_FD | STDOUT_FD | STDERR_FD => {
+            let mut buffer = [0u8; 64];
+            buffer[16] = FILETYPE_CHARACTER_DEVICE;
+            write_bytes(view, stat, &buffer)
+        }
+        fd => {
+            let metadata = match ctx.fds.get(&fd) {
+                Some(WasiFd::File(file)) => file.metadata(),
+                Some(WasiFd::Dir(path)) => fs::metadata(path),
+                None => return Err(ERRNO_BADF),
+            };
+            write_filestat(view, stat, &metadata.map_err(errno_from_io)?)
+        }
+    })
+}
+
+fn fd_filestat_set_size(_env: FunctionEnvMut<PluginEnv>, _fd: i32, _size: i64) -> Errno {
+    ERRNO_ROFS
+}
+
+fn fd_filestat_set_times(
+    _env: FunctionEnvMut<PluginEnv>,
+    _fd: i32,
+    _atim: i64,
+    _mtim: i64,
+    _fst_flags: i32,
+) -> Errno {
+    ERRNO_ROFS
+}
+
+fn fd_pread(
+    mut env: FunctionEnvMut<PluginEnv>,
+    fd: i32,
+    iovs: i32,
+    iovs_len: i32,
+    offset: i64,
+    nread: i32,
+) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| {
+        let file = match ctx.fds.get(&(fd as u32)) {
+            Some(WasiFd::File(file)) => file,
+            Some(WasiFd::Dir(_)) => return Err(ERRNO_BADF),
+            None if fd as u32 <= STDERR_FD => return Err(ERRNO_SPIPE),
+            None => return Err(ERRNO_BADF),
+        };
+
+        let offset = u64::try_from(offset).or(Err(ERRNO_INVAL))?;
+        let iovecs = read_iovecs(view, iovs, iovs_len)?;
+        let total = read_to_iovecs(view, &iovecs, |chunk, done| {
+            file.read_at(chunk, offset + done)
+        })?;
+        write_u32(view, nread, total)
+    })
+}
+
+fn fd_prestat_get(mut env: FunctionEnvMut<PluginEnv>, fd: i32, prestat: i32) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| match &ctx.preopen {
+        Some(preopen) if fd as u32 == PREOPEN_FD => {
+            // Tag 0 marks a directory preopen
+            let mut buffer = [0u8; 8];
+            LittleEndian::write_u32(&mut buffer[4..8], preopen.guest_path.len() as u32);
+            write_bytes(view, prestat, &buffer)
+        }
+        _ => Err(ERRNO_BADF),
+    })
+}
+
+fn fd_prestat_dir_name(
+    mut env: FunctionEnvMut<PluginEnv>,
+    fd: i32,
+    path: i32,
+    path_len: i32,
+) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| match &ctx.preopen {
+        Some(preopen) if fd as u32 == PREOPEN_FD => {
+            let name = preopen.guest_path.as_bytes();
+            let len = name.len().min(path_len as usize);
+            write_bytes(view, path, &name[..len])
+        }
+        _ => Err(ERRNO_BADF),
+    })
+}
+
+fn fd_pwrite(
+    _env: FunctionEnvMut<PluginEnv>,
+    _fd: i32,
+    _iovs: i32,
+    _iovs_len: i32,
+    _offset: i64,
+    _nwritten: i32,
+) -> Errno {
+    ERRNO_ROFS
+}
+
+fn fd_read(
+    mut env: FunctionEnvMut<PluginEnv>,
+    fd: i32,
+    iovs: i32,
+    iovs_len: i32,
+    nread: i32,
+) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| {
+        // Plugins have no stdin, reading from it always hits EOF
+        if fd as u32 == STDIN_FD {
+            return write_u32(view, nread, 0);
+        }
+        let file = match ctx.fds.get_mut(&(fd as u32)) {
+            Some(WasiFd::File(file)) => file,
+            _ => return Err(ERRNO_BADF),
+        };
+
+        let iovecs = read_iovecs(view, iovs, iovs_len)?;
+        let total = read_to_iovecs(view, &iovecs, |chunk, _| file.read(chunk))?;
+        write_u32(view, nread, total)
+    })
+}
+
+fn fd_readdir(
+    mut env: FunctionEnvMut<PluginEnv>,
+    fd: i32,
+    buf: i32,
+    buf_len: i32,
+    cookie: i64,
+    bufused: i32,
+) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| {
+        let buf_len = guest_len(buf_len)?;
+        check_range(view, buf, buf_len)?;
+        let path = match ctx.fds.get(&(fd as u32)) {
+            Some(WasiFd::Dir(path)) => path,
+            Some(WasiFd::File(_)) => return Err(ERRNO_NOTDIR),
+            None => return Err(ERRNO_BADF),
+        };
+
+        let mut entries = vec![
+            (".".to_string(), FILETYPE_DIRECTORY, 0),
+            ("..".to_string(), FILETYPE_DIRECTORY, 0),
+        ];
+        let mut children = fs::read_dir(path)
+            .map_err(errno_from_io)?
+            .filter_map(Result::ok)
+            .map(|entry| {
+                let metadata = entry.metadata().ok();
+                let filetype = metadata
+                    .as_ref()
+                    .map(filetype_of)
+                    .unwrap_or(FILETYPE_UNKNOWN);
+                let inode = metadata.map(|metadata| metadata.ino()).unwrap_or(0);
+                (
+                    entry.file_name().to_string_lossy().into_owned(),
+                    filetype,
+                    inode,
+                )
+            })
+            .collect::<Vec<_>>();
+        children.sort();
+        entries.extend(children);
+
+        // Entries are serialized back to back; a truncated last entry tells the guest to call again
+        let mut output = vec![];
+        for (index, (name, filetype, inode)) in entries.iter().enumerate().skip(cookie as usize) {
+            let mut header = [0u8; DIRENT_HEADER_SIZE];
+            LittleEndian::write_u64(&mut header[0..8], index as u64 + 1);
+            LittleEndian::write_u64(&mut header[8..16], *inode);
+            LittleEndian::write_u32(&mut header[16..20], name.len() as u32);
+            header[20] = *filetype;
+            output.extend_from_slice(&header);
+            output.extend_from_slice(name.as_bytes());
+            if output.len() >= buf_len as usize {
+                break;
+            }
+        }
+        output.truncate(buf_len as usize);
+
+        write_bytes(view, buf, &output)?;
+        write_u32(view, bufused, output.len() as u32)
+    })
+}
+
+fn fd_renumber(mut env: FunctionEnvMut<PluginEnv>, fd: i32, to: i32) -> Errno {
+    with_ctx(&mut env, |ctx, _, _| {
+        if fd as u32 <= STDERR_FD || to as u32 <= STDERR_FD {
+            return Err(ERRNO_NOTSUP);
+        }
+        let entry = ctx.fds.remove(&(fd as u32)).ok_or(ERRNO_BADF)?;
+        ctx.fds.insert(to as u32, entry);
+        Ok(())
+    })
+}
+
+fn fd_seek(
+    mut env: FunctionEnvMut<PluginEnv>,
+    fd: i32,
+    offset: i64,
+    whence: i32,
+    new_offset: i32,
+) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| {
+        let file = match ctx.fds.get_mut(&(fd as u32)) {
+            Some(WasiFd::File(file)) => file,
+            Some(WasiFd::Dir(_)) => return Err(ERRNO_BADF),
+            None if fd as u32 <= STDERR_FD => return Err(ERRNO_SPIPE),
+            None => return Err(ERRNO_BADF),
+        };
+        let position = match whence {
+            0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(ERRNO_INVAL),
+        };
+        let position = file.seek(position).map_err(errno_from_io)?;
+        write_u64(view, new_offset, position)
+    })
+}
+
+fn fd_tell(mut env: FunctionEnvMut<PluginEnv>, fd: i32, offset: i32) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| {
+        let file = match ctx.fds.get_mut(&(fd as u32)) {
+            Some(WasiFd::File(file)) => file,
+            None if fd as u32 <= STDERR_FD => return Err(ERRNO_SPIPE),
+            _ => return Err(ERRNO_BADF),
+        };
+        let position = file.stream_position().map_err(errno_from_io)?;
+        write_u64(view, offset, position)
+    })
+}
+
+fn fd_write(
+    mut env: FunctionEnvMut<PluginEnv>,
+    fd: i32,
+    iovs: i32,
+    iovs_len: i32,
+    nwritten: i32,
+) -> Errno {
+    with_ctx(&mut env, |ctx, view, plugin_name| {
+        let buffer = match fd as u32 {
+            STDOUT_FD => &mut ctx.stdout,
+            STDERR_FD => &mut ctx.stderr,
+            fd if ctx.fds.contains_key(&fd) => return Err(ERRNO_ROFS),
+            _ => return Err(ERRNO_BADF),
+        };
+
+        let target = log_target(plugin_name);
+        let mut total = 0u32;
+        for (buf, buf_len) in read_iovecs(view, iovs, iovs_len)? {
+            check_range(view, buf as i32, buf_len)?;
+            for start in (0..buf_len).step_by(IO_CHUNK_SIZE) {
+                let size = (buf_len - start).min(IO_CHUNK_SIZE as u32);
+                buffer.extend(read_bytes(view, (buf + start) as i32, size)?);
+                log_lines(buffer, fd as u32, &target);
+            }
+            total += buf_len;
+        }
+        write_u32(view, nwritten, total)
+    })
+}
+
+fn path_filestat_get(
+    mut env: FunctionEnvMut<PluginEnv>,
+    fd: i32,
+    _flags: i32,
+    path: i32,
+    path_len: i32,
+    stat: i32,
+) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| {
+        let path = read_string(view, path, path_len)?;
+        let resolved = ctx.resolve_path(fd as u32, &path)?;
+        let metadata = fs::metadata(resolved).map_err(errno_from_io)?;
+        write_filestat(view, stat, &metadata)
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn path_filestat_set_times(
+    _env: FunctionEnvMut<PluginEnv>,
+    _fd: i32,
+    _flags: i32,
+    _path: i32,
+    _path_len: i32,
+    _atim: i64,
+    _mtim: i64,
+    _fst_flags: i32,
+) -> Errno {
+    ERRNO_ROFS
+}
+
+// Shared by every call that would create or remove an entry in the read-only directory
+fn path_modify(_env: FunctionEnvMut<PluginEnv>, _fd: i32, _path: i32, _path_len: i32) -> Errno {
+    ERRNO_ROFS
+}
+
+#[allow(clippy::too_many_arguments)]
+fn path_link(
+    _env: FunctionEnvMut<PluginEnv>,
+    _old_fd: i32,
+    _old_flags: i32,
+    _old_path: i32,
+    _old_path_len: i32,
+    _new_fd: i32,
+    _new_path: i32,
+    _new_path_len: i32,
+) -> Errno {
+    ERRNO_ROFS
+}
+
+#[allow(clippy::too_many_arguments)]
+fn path_open(
+    mut env: FunctionEnvMut<PluginEnv>,
+    fd: i32,
+    _dirflags: i32,
+    path: i32,
+    path_len: i32,
+    oflags: i32,
+    rights_base: i64,
+    _rights_inheriting: i64,
+    fdflags: i32,
+    opened_fd: i32,
+) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| {
+        if oflags & (OFLAGS_CREAT | OFLAGS_EXCL | OFLAGS_TRUNC) != 0
+            || fdflags & FDFLAGS_APPEND != 0
+            || rights_base as u64 & RIGHTS_FD_WRITE != 0
+        {
+            return Err(ERRNO_ROFS);
+        }
+
+        let path = read_string(view, path, path_len)?;
+        let resolved = ctx.resolve_path(fd as u32, &path)?;
+        let metadata = fs::metadata(&resolved).map_err(errno_from_io)?;
+
+        let entry = if metadata.is_dir() {
+            WasiFd::Dir(resolved)
+        } else if oflags & OFLAGS_DIRECTORY != 0 {
+            return Err(ERRNO_NOTDIR);
+        } else {
+            WasiFd::File(File::open(resolved).map_err(errno_from_io)?)
+        };
+
+        let new_fd = ctx.insert_fd(entry);
+        write_u32(view, opened_fd, new_fd)
+    })
+}
+
+fn path_readlink(
+    _env: FunctionEnvMut<PluginEnv>,
+    _fd: i32,
+    _path: i32,
+    _path_len: i32,
+    _buf: i32,
+    _buf_len: i32,
+    _bufused: i32,
+) -> Errno {
+    ERRNO_NOTSUP
+}
+
+fn path_rename(
+    _env: FunctionEnvMut<PluginEnv>,
+    _fd: i32,
+    _old_path: i32,
+    _old_path_len: i32,
+    _new_fd: i32,
+    _new_path: i32,
+    _new_path_len: i32,
+) -> Errno {
+    ERRNO_ROFS
+}
+
+fn path_symlink(
+    _env: FunctionEnvMut<PluginEnv>,
+    _old_path: i32,
+    _old_path_len: i32,
+    _fd: i32,
+    _new_path: i32,
+    _new_path_len: i32,
+) -> Errno {
+    ERRNO_ROFS
+}
+
+// Plugins must never block the paint loop, so every subscription is reported as ready right away
+// File subscriptions are always ready. Without any, waits for the first clock subscription to expire,
+// for at most a frame deadline so a sleeping plugin doesn't miss its frame.
+fn poll_oneoff(
+    mut env: FunctionEnvMut<PluginEnv>,
+    subscriptions: i32,
+    events: i32,
+    subscription_count: i32,
+    event_count: i32,
+) -> Errno {
+    with_ctx(&mut env, |ctx, view, _| {
+        let count = guest_len(subscription_count)?;
+        let subscriptions_size = count.checked_mul(SUBSCRIPTION_SIZE).ok_or(ERRNO_FAULT)?;
+        let events_size = count.checked_mul(EVENT_SIZE).ok_or(ERRNO_FAULT)?;
+        check_range(view, subscriptions, subscriptions_size)?;
+        check_range(view, events, events_size)?;
+        let subscriptions = (0..count)
+            .map(|index| {
+                read_bytes(view, (subscriptions as u32 + index * SUBSCRIPTION_SIZE) as i32, SUBSCRIPTION_SIZE)
+            })
+            .collect::<Result<Vec<Vec<u8>>, Errno>>()?;
+        // Time left until each clock subscription expires, `None` for file subscriptions
+        let timeouts = subscriptions
+            .iter()
+            .map(|subscription| clock_timeout(ctx, subscription))
+            .collect::<Result<Vec<Option<Duration>>, Errno>>()?;
+
+        let ready = match timeouts.iter().all(Option::is_some) {
+            true => timeouts.iter().flatten().min().copied().unwrap_or_default(),
+            false => Duration::ZERO,
+        };
+        sleep(ready.min(FRAME_DEADLINE));
+
+        let mut written = 0;
+        for (subscription, timeout) in subscriptions.iter().zip(&timeouts) {
+            if timeout.is_some_and(|timeout| timeout > ready) {
+                continue;
+            }
+            let mut event = [0u8; EVENT_SIZE as usize];
+            event[0..8].copy_from_slice(&subscription[0..8]);
+            event[10] = subscription[8];
+            write_bytes(view, (events as u32 + written * EVENT_SIZE) as i32, &event)?;
+            written += 1;
+        }
+        write_u32(view, event_count, written)
+    })
+}
+
+// Time until a clock subscription expires, relative or absolute on the clocks `clock_time_get` serves
+fn clock_timeout(ctx: &WasiCtx, subscription: &[u8]) -> Result<Option<Duration>, Errno> {
+    if subscription[8] != EVENTTYPE_CLOCK {
+        return Ok(None);
+    }
+    let clock_id = LittleEndian::read_u32(&subscription[16..20]) as i32;
+    let timeout = Duration::from_nanos(LittleEndian::read_u64(&subscription[24..32]));
+    let absolute = LittleEndian::read_u16(&subscription[40..42]) & SUBCLOCKFLAGS_ABSTIME != 0;
+    let now = match clock_id {
+        CLOCK_REALTIME if !ctx.realtime_clock => return Err(ERRNO_NOTCAPABLE),
+        CLOCK_REALTIME => UNIX_EPOCH.elapsed().map_err(|_| ERRNO_IO)?,
+        CLOCK_MONOTONIC | CLOCK_PROCESS_CPUTIME | CLOCK_THREAD_CPUTIME => ctx.started.elapsed(),
+        _ => return Err(ERRNO_INVAL),
+    };
+    match absolute {
+        true => Ok(Some(timeout.saturating_sub(now))),
+        false => Ok(Some(timeout)),
+    }
+}
+
+fn proc_exit(env: FunctionEnvMut<PluginEnv>, code: i32) -> Result<(), RuntimeError> {
+    Err(RuntimeError::new(format!(
+        "Plugin '{}' called proc_exit with code {}",
+        env.data().name,
+        code
+    )))
+}
+
+fn proc_raise(_signal: i32) -> Errno {
+    ERRNO_NOTSUP
+}
+
+fn sched_yield() -> Errno {
+    ERRNO_SUCCESS
+}
+
+fn random_get(mut env: FunctionEnvMut<PluginEnv>, buf: i32, buf_len: i32) -> Errno {
+    with_ctx(&mut env, |_, view, _| {
+        let len = guest_len(buf_len)?;
+        check_range(view, buf, len)?;
+        let mut urandom = File::open("/dev/urandom").map_err(errno_from_io)?;
+        let mut chunk = vec![0u8; IO_CHUNK_SIZE];
+        for start in (0..len).step_by(IO_CHUNK_SIZE) {
+            let size = ((len - start) as usize).min(IO_CHUNK_SIZE);
+            urandom
+                .read_exact(&mut chunk[..size])
+                .map_err(errno_from_io)?;
+            write_bytes(view, (buf as u32 + start) as i32, &chunk[..size])?;
+        }
+        Ok(())
+    })
+}
+
+fn sock_accept(_fd: i32, _flags: i32, _opened_fd: i32) -> Errno {
+    ERRNO_NOTSUP
+}
+
+fn sock_recv(
+    _fd: i32,
+    _ri_data: i32,
+    _ri_data_len: i32,
+    _ri_flags: i32,
+    _ro_datalen: i32,
+    _ro_flags: i32,
+) -> Errno {
+    ERRNO_NOTSUP
+}
+
+fn sock_send(
+    _fd: i32,
+    _si_data: i32,
+    _si_data_len: i32,
+    _si_flags: i32,
+    _so_datalen: i32,
+) -> Errno {
+    ERRNO_NOTSUP
+}
+
+fn sock_shutdown(_fd: i32, _how: i32) -> Errno {
+    ERRNO_NOTSUP
+}
+
+fn errno_from_io(err: std::io::Error) -> Errno {
+    match err.kind() {
+        ErrorKind::NotFound => ERRNO_NOENT,
+        ErrorKind::PermissionDenied => ERRNO_ACCES,
+        _ => ERRNO_IO,
+    }
+}
+
+fn filetype_of(metadata: &Metadata) -> u8 {
+    let filetype = metadata.file_type();
+    if filetype.is_dir() {
+        FILETYPE_DIRECTORY
+    } else if filetype.is_file() {
+        FILETYPE_REGULAR_FILE
+    } else if filetype.is_symlink() {
+        FILETYPE_SYMBOLIC_LINK
+    } else {
+        FILETYPE_UNKNOWN
+    }
+}
+
+fn write_filestat(view: &MemoryView, ptr: i32, metadata: &Metadata) -> Result<(), Errno> {
+    let as_nanos = |secs: i64, nanos: i64| (secs as u64) * 1_000_000_000 + nanos as u64;
+
+    let mut buffer = [0u8; 64];
+    LittleEndian::write_u64(&mut buffer[0..8], metadata.dev());
+    LittleEndian::write_u64(&mut buffer[8..16], metadata.ino());
+    buffer[16] = filetype_of(metadata);
+    LittleEndian::write_u64(&mut buffer[24..32], metadata.nlink());
+    LittleEndian::write_u64(&mut buffer[32..40], metadata.size());
+    LittleEndian::write_u64(
+        &mut buffer[40..48],
+        as_nanos(metadata.atime(), metadata.atime_nsec()),
+    );
+    LittleEndian::write_u64(
+        &mut buffer[48..56],
+        as_nanos(metadata.mtime(), metadata.mtime_nsec()),
+    );
+    LittleEndian::write_u64(
+        &mut buffer[56..64],
+        as_nanos(metadata.ctime(), metadata.ctime_nsec()),
+    );
+    write_bytes(view, ptr, &buffer)
+}
+
+// Writes a list of NUL-terminated strings along with the pointer table pointing into it
+fn write_string_list(
+    view: &MemoryView,
+    list: &[String],
+    pointers: i32,
+    buffer: i32,
+) -> Result<(), Errno> {
+    let size = list.iter().map(|item| item.len() as u32 + 1).sum();
+    check_range(view, pointers, 4 * list.len() as u32)?;
+    check_range(view, buffer, size)?;
+
+    let mut offset = buffer as u32;
+    for (index, item) in list.iter().enumerate() {
+        write_u32(view, (pointers as u32 + 4 * index as u32) as i32, offset)?;
+        write_bytes(view, offset as i32, item.as_bytes())?;
+        write_bytes(view, (offset + item.len() as u32) as i32, &[0])?;
+        offset += item.len() as u32 + 1;
+    }
+    Ok(())
+}
+
+fn write_string_list_sizes(
+    view: &MemoryView,
+    list: &[String],
+    count: i32,
+    buffer_size: i32,
+) -> Result<(), Errno> {
+    let size = list.iter().map(|item| item.len() as u32 + 1).sum();
+    write_u32(view, count, list.len() as u32)?;
+    write_u32(view, buffer_size, size)
+}
+
+// Log the complete lines of a stdio buffer, the rest waits for the next write unless it grew too long
+fn log_lines(buffer: &mut Vec<u8>, fd: u32, target: &str) {
+    while let Some(position) = buffer.iter().position(|byte| *byte == b'\n') {
+        let line = buffer.drain(..=position).collect::<Vec<u8>>();
+        log_line(fd, target, &line[..position]);
+    }
+    if buffer.len() > MAX_LINE_LEN {
+        log_line(fd, target, buffer);
+        buffer.clear();
+    }
+}
+
+fn log_line(fd: u32, target: &str, line: &[u8]) {
+    let line = String::from_utf8_lossy(line);
+    if fd == STDOUT_FD {
+        info!(target: target, "{}", line);
+    } else {
+        warn!(target: target, "{}", line);
+    }
+}
+
+// Fill guest buffers from `read`, called with a bounded host buffer and the number of bytes read so far.
+// Stops at the first short read.
+fn read_to_iovecs<F>(view: &MemoryView, iovecs: &[(u32, u32)], mut read: F) -> Result<u32, Errno>
+where
+    F: FnMut(&mut [u8], u64) -> std::io::Result<usize>,
+{
+    let mut chunk = vec![0u8; IO_CHUNK_SIZE];
+    let mut total = 0u32;
+    for &(buf, buf_len) in iovecs {
+        check_range(view, buf as i32, buf_len)?;
+        let mut done = 0u32;
+        while done < buf_len {
+            let size = ((buf_len - done) as usize).min(IO_CHUNK_SIZE);
+            let count = read(&mut chunk[..size], total as u64).map_err(errno_from_io)?;
+            write_bytes(view, (buf + done) as i32, &chunk[..count])?;
+            done += count as u32;
+            total += count as u32;
+            if count < size {
+                return Ok(total);
+            }
+        }
+    }
+    Ok(total)
+}
+
+fn read_iovecs(view: &MemoryView, iovs: i32, iovs_len: i32) -> Result<Vec<(u32, u32)>, Errno> {
+    let count = guest_len(iovs_len)?;
+    if count > MAX_IOVECS {
+        return Err(ERRNO_INVAL);
+    }
+    let raw = read_bytes(view, iovs, count * 8)?;
+    let iovecs = raw
+        .chunks_exact(8)
+        .map(|iovec| {
+            (
+                LittleEndian::read_u32(&iovec[0..4]),
+                LittleEndian::read_u32(&iovec[4..8]),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // Like readv and writev, the total length has to fit in the returned count
+    iovecs
+        .iter()
+        .try_fold(0u32, |total, (_, len)| total.checked_add(*len))
+        .ok_or(ERRNO_INVAL)?;
+    Ok(iovecs)
+}
+
+fn read_string(view: &MemoryView, ptr: i32, len: i32) -> Result<String, Errno> {
+    let len = guest_len(len)?;
+    if len > MAX_PATH_LEN {
+        return Err(ERRNO_NAMETOOLONG);
+    }
+    String::from_utf8(read_bytes(view, ptr, len)?).or(Err(ERRNO_INVAL))
+}
+
+// Lengths come from the guest as i32, negative ones are invalid rather than huge
+fn guest_len(len: i32) -> Result<u32, Errno> {
+    u32::try_from(len).or(Err(ERRNO_INVAL))
+}
+
+// Check a guest buffer lies in its memory before the host sizes anything after it
+fn check_range(view: &MemoryView, ptr: i32, len: u32) -> Result<(), Errno> {
+    let end = (ptr as u32 as u64)
+        .checked_add(len as u64)
+        .ok_or(ERRNO_FAULT)?;
+    if end > view.data_size() {
+        return Err(ERRNO_FAULT);
+    }
+    Ok(())
+}
+
+fn read_bytes(view: &MemoryView, ptr: i32, len: u32) -> Result<Vec<u8>, Errno> {
+    check_range(view, ptr, len)?;
+    let mut buffer = vec![0u8; len as usize];
+    view.read(ptr as u32 as u64, &mut buffer)
+        .or(Err(ERRNO_FAULT))?;
+    Ok(buffer)
+}
+
+fn write_bytes(view: &MemoryView, ptr: i32, data: &[u8]) -> Result<(), Errno> {
+    view.write(ptr as u32 as u64, data).or(Err(ERRNO_FAULT))
+}
+
+fn write_u32(view: &MemoryView, ptr: i32, value: u32) -> Result<(), Errno> {
+    let mut buffer = [0u8; 4];
+    LittleEndian::write_u32(&mut buffer, value);
+    write_bytes(view, ptr, &buffer)
+}
+
+fn write_u64(view: &MemoryView, ptr: i32, value: u64) -> Result<(), Errno> {
+    let mut buffer = [0u8; 8];
+    LittleEndian::write_u64(&mut buffer, value);
+    write_bytes(view, ptr, &buffer)
+}
+
+#[cfg(test)]
+mod wasi_tests {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::time::{Duration, Instant};
+
+    use crate::canvas::FRAME_DEADLINE;
+    use crate::config::{PluginConf, WasiConf};
+    use crate::permissions::Capability;
+    use crate::picture::Picture;
+    use crate::wasi::{
+        WasiCtx, ERRNO_BADF, ERRNO_FAULT, ERRNO_INVAL, ERRNO_NOTCAPABLE, ERRNO_SPIPE, ERRNO_SUCCESS, PREOPEN_FD,
+    };
+    use crate::wasm_module::{WASMError, WasmModule};
+
+    // Draws the errno of each call, made with lengths a host sizing buffers from them couldn't survive
+    const BAD_LENGTHS_PLUGIN: &str = r#"(module
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_open"
+            (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "random_get" (func $random_get (param i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (@custom "metadata" "{\"name\":\"bad-lengths\",\"width\":8,\"height\":1,\"permissions\":[\"fs\"]}")
+        ;; iovec at 16 claiming 2 GiB of a 64 KiB memory
+        (data (i32.const 16) "\20\00\00\00\ff\ff\ff\7f")
+        (data (i32.const 48) "fonts/digits.bin")
+        (func (export "draw") (result i32)
+            (i32.store8 (i32.const 0) (call $fd_write (i32.const 1) (i32.const 16) (i32.const -1) (i32.const 8)))
+            (i32.store8 (i32.const 1) (call $fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 8)))
+            (i32.store8 (i32.const 2) (call $random_get (i32.const 32) (i32.const -1)))
+            (i32.store8 (i32.const 3) (call $random_get (i32.const 32) (i32.const 0x7fffffff)))
+            (i32.store8 (i32.const 4) (call $poll_oneoff (i32.const 64) (i32.const 128) (i32.const -1) (i32.const 8)))
+            (i32.store8 (i32.const 5) (call $path_open (i32.const 3) (i32.const 0) (i32.const 48) (i32.const 16)
+                (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 12)))
+            (i32.store8 (i32.const 6) (call $fd_read (i32.load (i32.const 12)) (i32.const 16) (i32.const 1) (i32.const 8)))
+            (i32.store8 (i32.const 7) (call $fd_read (i32.load (i32.const 12)) (i32.const 16) (i32.const -1) (i32.const 8)))
+            (i32.const 0)))"#;
+
+    // Draws the errno or result of reads and seeks on `fonts/digits.bin` (bytes 1, 2 and 3) and on invalid fds
+    const FILE_READS_PLUGIN: &str = r#"(module
+        (import "wasi_snapshot_preview1" "path_open"
+            (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_seek" (func $fd_seek (param i32 i64 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (@custom "metadata" "{\"name\":\"file-reads\",\"width\":8,\"height\":1,\"permissions\":[\"fs\"]}")
+        ;; iovec at 16 for 8 bytes at 32
+        (data (i32.const 16) "\20\00\00\00\08\00\00\00")
+        (data (i32.const 48) "fonts/digits.bin")
+        (func (export "draw") (result i32)
+            (drop (call $path_open (i32.const 3) (i32.const 0) (i32.const 48) (i32.const 16)
+                (i32.const 0) (i64.const 6) (i64.const 0) (i32.const 0) (i32.const 12)))
+            ;; Seek past the end, then read nothing from there
+            (i32.store8 (i32.const 0) (call $fd_seek (i32.load (i32.const 12)) (i64.const 10) (i32.const 0) (i32.const 64)))
+            (i32.store8 (i32.const 1) (call $fd_read (i32.load (i32.const 12)) (i32.const 16) (i32.const 1) (i32.const 8)))
+            (i32.store8 (i32.const 2) (i32.load (i32.const 8)))
+            ;; Read the last two bytes, asking for more
+            (drop (call $fd_seek (i32.load (i32.const 12)) (i64.const 1) (i32.const 0) (i32.const 64)))
+            (drop (call $fd_read (i32.load (i32.const 12)) (i32.const 16) (i32.const 1) (i32.const 8)))
+            (i32.store8 (i32.const 3) (i32.add (i32.mul (i32.load (i32.const 8)) (i32.const 10)) (i32.load8_u (i32.const 32))))
+            ;; Unknown fd, seek on stdout, read from the preopened directory
+            (i32.store8 (i32.const 4) (call $fd_read (i32.const 99) (i32.const 16) (i32.const 1) (i32.const 8)))
+            (i32.store8 (i32.const 5) (call $fd_seek (i32.const 99) (i64.const 0) (i32.const 0) (i32.const 64)))
+            (i32.store8 (i32.const 6) (call $fd_seek (i32.const 1) (i64.const 0) (i32.const 0) (i32.const 64)))
+            (i32.store8 (i32.const 7) (call $fd_read (i32.const 3) (i32.const 16) (i32.const 1) (i32.const 8)))
+            (i32.const 0)))"#;
+
+    // Polls a monotonic clock subscription, 50ms on the first frame and 10s after. Draws the errno,
+    // the number of events and the userdata of the first one.
+    const SLEEP_PLUGIN: &str = r#"(module
+        (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (global $timeout (mut i64) (i64.const 50000000))
+        (@custom "metadata" "{\"name\":\"sleep\",\"width\":3,\"height\":1,\"permissions\":[]}")
+        (func (export "draw") (result i32)
+            (i64.store (i32.const 64) (i64.const 7))
+            (i32.store8 (i32.const 72) (i32.const 0))
+            (i32.store (i32.const 80) (i32.const 1))
+            (i64.store (i32.const 88) (global.get $timeout))
+            (i32.store8 (i32.const 0) (call $poll_oneoff (i32.const 64) (i32.const 128) (i32.const 1) (i32.const 8)))
+            (i32.store8 (i32.const 1) (i32.load (i32.const 8)))
+            (i32.store8 (i32.const 2) (i32.load8_u (i32.const 128)))
+            (global.set $timeout (i64.const 10000000000))
+            (i32.const 0)))"#;
+
+    fn create_ctx(dir_name: &str) -> WasiCtx {
+        let root = std::env::temp_dir().join(dir_name);
+        fs::create_dir_all(root.join("fonts")).unwrap();
+        fs::write(root.join("fonts/digits.bin"), [1, 2, 3]).unwrap();
+
+        let conf = WasiConf {
+            preopen: Some(root),
+            guest_path: "/".to_string(),
+        };
+        WasiCtx::new("test", &conf, &HashSet::from([Capability::Fs])).unwrap()
+    }
+
+    #[test]
+    fn resolves_path_inside_preopen() {
+        let ctx = create_ctx("fw-led-wasi-inside");
+
+        let resolved = ctx
+            .resolve_path(PREOPEN_FD, "fonts/../fonts/./digits.bin")
+            .unwrap();
+
+        assert_eq!(fs::read(resolved).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn requires_fs_capability_for_preopen() {
+        let conf = WasiConf {
+            preopen: Some(std::env::temp_dir()),
+            guest_path: "/".to_string(),
+        };
+
+        assert!(matches!(
+            WasiCtx::new("test", &conf, &HashSet::from([Capability::Time])),
+            Err(WASMError::FsPermissionRequired)
+        ));
+        assert!(WasiCtx::new("test", &WasiConf { preopen: None, ..conf }, &HashSet::new()).is_ok());
+    }
+
+    #[test]
+    fn rejects_path_escaping_preopen() {
+        let ctx = create_ctx("fw-led-wasi-escape");
+
+        assert_eq!(
+            ctx.resolve_path(PREOPEN_FD, "fonts/../../etc/passwd"),
+            Err(ERRNO_NOTCAPABLE)
+        );
+        assert_eq!(
+            ctx.resolve_path(PREOPEN_FD, "/etc/passwd"),
+            Err(ERRNO_NOTCAPABLE)
+        );
+    }
+
+    #[test]
+    fn rejects_symlinks_escaping_preopen() {
+        let ctx = create_ctx("fw-led-wasi-symlinks");
+        let root = std::env::temp_dir().join("fw-led-wasi-symlinks");
+        let _ = fs::remove_file(root.join("outside"));
+        let _ = fs::remove_file(root.join("inside"));
+        symlink("/etc", root.join("outside")).unwrap();
+        symlink(root.join("fonts"), root.join("inside")).unwrap();
+
+        assert_eq!(ctx.resolve_path(PREOPEN_FD, "outside/passwd"), Err(ERRNO_NOTCAPABLE));
+        assert_eq!(ctx.resolve_path(PREOPEN_FD, "outside"), Err(ERRNO_NOTCAPABLE));
+        assert!(ctx.resolve_path(PREOPEN_FD, "inside/digits.bin").is_ok());
+    }
+
+    #[test]
+    fn reads_past_eof_and_rejects_invalid_fds() {
+        let root = std::env::temp_dir().join("fw-led-wasi-file-reads");
+        create_ctx("fw-led-wasi-file-reads");
+        let plugin_conf: PluginConf = toml::from_str(&format!(
+            "name = 'file-reads'\npos_x = 0\npos_y = 0\npermissions = ['fs']\n[wasi]\npreopen = '{}'",
+            root.display()
+        ))
+        .unwrap();
+        let mut module = WasmModule::new(FILE_READS_PLUGIN.as_bytes().to_vec(), &plugin_conf);
+
+        let frame = module.draw();
+
+        let results = (0..8).map(|x| frame.get_el(0, x) as i32).collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![ERRNO_SUCCESS, ERRNO_SUCCESS, 0, 22, ERRNO_BADF, ERRNO_BADF, ERRNO_SPIPE, ERRNO_BADF]
+        );
+    }
+
+    #[test]
+    fn sleeps_on_clock_subscriptions_up_to_frame_deadline() {
+        let plugin_conf: PluginConf = toml::from_str("name = 'sleep'\npos_x = 0\npos_y = 0\n[wasi]").unwrap();
+        let mut module = WasmModule::new(SLEEP_PLUGIN.as_bytes().to_vec(), &plugin_conf);
+
+        let started = Instant::now();
+        let short = module.draw();
+        let short_sleep = started.elapsed();
+        let started = Instant::now();
+        let long = module.draw();
+        let long_sleep = started.elapsed();
+
+        assert_eq!([short.get_el(0, 0), short.get_el(0, 1), short.get_el(0, 2)], [0, 1, 7]);
+        assert_eq!([long.get_el(0, 0), long.get_el(0, 1), long.get_el(0, 2)], [0, 1, 7]);
+        assert!(short_sleep >= Duration::from_millis(50) && short_sleep < FRAME_DEADLINE, "{:?}", short_sleep);
+        assert!(long_sleep >= FRAME_DEADLINE && long_sleep < Duration::from_secs(1), "{:?}", long_sleep);
+    }
+
+    #[test]
+    fn rejects_negative_and_oversized_lengths() {
+        let root = std::env::temp_dir().join("fw-led-wasi-bad-lengths");
+        create_ctx("fw-led-wasi-bad-lengths");
+        let plugin_conf: PluginConf = toml::from_str(&format!(
+            "name = 'bad-lengths'\npos_x = 0\npos_y = 0\npermissions = ['fs']\n[wasi]\npreopen = '{}'",
+            root.display()
+        ))
+        .unwrap();
+        let mut module = WasmModule::new(BAD_LENGTHS_PLUGIN.as_bytes().to_vec(), &plugin_conf);
+
+        let frame = module.draw();
+
+        let errnos = (0..8).map(|x| frame.get_el(0, x) as i32).collect::<Vec<_>>();
+        assert_eq!(
+            errnos,
+            vec![
+                ERRNO_INVAL,
+                ERRNO_FAULT,
+                ERRNO_INVAL,
+                ERRNO_FAULT,
+                ERRNO_INVAL,
+                ERRNO_SUCCESS,
+                ERRNO_FAULT,
+                ERRNO_INVAL
+            ]
+        );
+    }
+}
@@ -1,139 +1,694 @@
-use std::env::current_exe;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::{Display, Formatter};
 use std::fs;
-use std::io::ErrorKind;
-use std::path::PathBuf;
-use std::sync::{LazyLock, Mutex};
+use std::path::Path;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
 
-use log::{error, warn};
+use log::{debug, error, info, warn};
 use serde::Deserialize;
-use wasmer::{Function, imports, Imports, Instance, Module, Store, TypedFunction, WasmPtr};
+use wasmer::{
+    Engine, ExportError, ExternType, Function, FunctionEnv, FunctionEnvMut, imports, Imports, Instance, Memory, Module, RuntimeError,
+    Store, TypedFunction, WasmPtr, WasmTypeList,
+};
 use wasmer_compiler_singlepass::Singlepass;
 
-use crate::matrix::Matrix;
+use crate::bundle;
+use crate::component;
+use crate::component::ComponentExports;
+use crate::bundle::{read_bundle, Bundle, BUNDLE_EXTENSION};
+use crate::config::PluginConf;
+use crate::kv_bus;
+use crate::matrix::{Matrix, MATRIX_HEIGHT, MATRIX_WIDTH};
+use crate::permissions::{grant_permissions, required_capability, Capability};
 use crate::picture::Picture;
-use crate::system_stat_monitor::SystemStatMonitor;
+use crate::plugin_log;
+use crate::plugin_paths::PluginPaths;
+use crate::plugin_state::MAX_STATE_SIZE;
+use crate::settings::{
+    get_setting_f64, get_setting_i64, get_setting_string, validate_settings, SettingsError,
+    SettingsSchema,
+};
+use crate::stat_provider::StatProvider;
+use crate::wasi;
+use crate::wasi::WasiCtx;
 
 pub struct WasmModule {
     instance: Instance,
     pub(crate) metadata: Metadata,
     store: Store,
+    tick: Option<TypedFunction<i32, ()>>,
+    shutdown: Option<TypedFunction<(), ()>>,
+    // Returns (ptr, len) packed into i64, ptr in the high 32 bits. Singlepass has no multi-value support.
+    save_state: Option<TypedFunction<(), i64>>,
+    load_state: Option<TypedFunction<(i32, i32), ()>>,
+    // Canonical ABI exports of components, which replace the core `draw`, `init` and state exports
+    component: Option<ComponentExports>,
+    last_tick: Option<Instant>,
+    last_frame: Option<(Instant, Matrix)>,
+    stats: Arc<dyn StatProvider>,
 }
 
-#[derive(Debug)]
-enum WASMError {
+// AssemblyScript runtime class id of ArrayBuffer
+const AS_ARRAY_BUFFER_ID: i32 = 1;
+
+// Buffer reserved in plugin memory through one of the supported allocators
+enum GuestBuffer {
+    Alloc(i32),
+    // Pinned AssemblyScript object, has to be unpinned once the plugin is done with it
+    AssemblyScript(i32),
+}
+
+impl GuestBuffer {
+    fn ptr(&self) -> i32 {
+        match self {
+            GuestBuffer::Alloc(ptr) | GuestBuffer::AssemblyScript(ptr) => *ptr,
+        }
+    }
+}
+
+// Host state shared with imported functions that need access to the plugin
+pub struct PluginEnv {
+    pub(crate) name: String,
+    pub(crate) memory: Option<Memory>,
+    pub(crate) wasi: Option<WasiCtx>,
+    // Plugin settings, validated against the schema from metadata when one is declared
+    pub(crate) settings: toml::Table,
+    // Files shipped in the plugin bundle
+    pub(crate) assets: Arc<HashMap<String, Vec<u8>>>,
+    pub(crate) stats: Arc<dyn StatProvider>,
+    // `cabi_realloc` of components, which imports returning strings allocate them with
+    pub(crate) realloc: Option<TypedFunction<(i32, i32, i32, i32), i32>>,
+}
+
+// Version of the host API (imports, exports and memory layout) plugins are built against
+pub const HOST_ABI_VERSION: u32 = 1;
+// Oldest plugin ABI version the host still supports
+pub const MIN_ABI_VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq)]
+pub enum WASMError {
+    NotFound(String),
+    ReadFailed(String),
+    CompileFailed(String),
+    InvalidComponent(String),
     NoCustomSection,
-    InvalidCustomSection,
-}
-
-impl From<&str> for WasmModule {
-    fn from(value: &str) -> Self {
-        let module = current_exe()
-            .map(|path| path.as_path().parent().map(PathBuf::from))
-            .ok()
-            .flatten()
-            .map(|path| path.join(format!("plugins/{}.wasm", value)))
-            .ok_or(std::io::Error::from(ErrorKind::Other))
-            .and_then(fs::canonicalize)
-            .and_then(fs::read);
-
-        Self::from(module.unwrap_or_else(|err| {
-            match err.kind() {
-                ErrorKind::NotFound => {
-                    error!(
-                        "WASM module with name {} was not found in the plugins directory.",
-                        value
-                    );
-                }
-                _ => {
-                    error!("Unknown error occurred {}", err);
-                }
+    InvalidCustomSection(String),
+    InvalidBundle(String),
+    IncompatibleAbiVersion(u32),
+    InvalidSize { width: usize, height: usize },
+    InvalidSettings(Vec<SettingsError>),
+    UnapprovedPermissions(Vec<Capability>),
+    FsPermissionRequired,
+    WasiSetupFailed(String),
+    MissingImport { module: String, name: String },
+    InstantiationFailed(String),
+    InvalidExport { name: String, err: String },
+    CallFailed { function: String, err: String },
+    MemoryAccessFailed(String),
+}
+
+impl Display for WASMError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            WASMError::NotFound(name) => write!(
+                f,
+                "neither '{0}.{1}' nor '{0}.wasm' was found in the plugin search path",
+                name, BUNDLE_EXTENSION
+            ),
+            WASMError::ReadFailed(err) => write!(f, "failed to read module file: {}", err),
+            WASMError::CompileFailed(err) => write!(f, "failed to compile module: {}", err),
+            WASMError::InvalidComponent(err) => write!(f, "unsupported component: {}", err),
+            WASMError::NoCustomSection => write!(f, "missing 'metadata' custom section"),
+            WASMError::InvalidCustomSection(err) => {
+                write!(f, "invalid 'metadata' custom section: {}", err)
+            }
+            WASMError::InvalidBundle(err) => write!(f, "invalid bundle: {}", err),
+            WASMError::IncompatibleAbiVersion(version) => write!(
+                f,
+                "plugin requires ABI version {}, host supports versions {} to {}",
+                version, MIN_ABI_VERSION, HOST_ABI_VERSION
+            ),
+            WASMError::InvalidSize { width, height } => write!(
+                f,
+                "plugin is {}x{}, width should be between 1 and {} and height between 1 and {}",
+                width, height, MATRIX_WIDTH, MATRIX_HEIGHT
+            ),
+            WASMError::InvalidSettings(errors) => {
+                let errors = errors.iter().map(SettingsError::to_string).collect::<Vec<String>>();
+                write!(f, "invalid settings: {}", errors.join("; "))
+            }
+            WASMError::UnapprovedPermissions(unapproved) => {
+                let unapproved = unapproved.iter().map(Capability::to_string).collect::<Vec<String>>();
+                write!(
+                    f,
+                    "requested permissions not approved in config: {}. Add them to `permissions` of its [[plugins]] entry",
+                    unapproved.join(", ")
+                )
+            }
+            WASMError::FsPermissionRequired => {
+                write!(f, "the 'fs' permission is needed to access a WASI preopened directory")
             }
-            std::process::exit(1)
-        }))
+            WASMError::WasiSetupFailed(err) => write!(f, "failed to set up WASI: {}", err),
+            WASMError::MissingImport { module, name } if required_capability(module, name).is_some() => write!(
+                f,
+                "plugin imports '{}.{}', which requires the '{}' permission in its metadata",
+                module,
+                name,
+                required_capability(module, name).unwrap()
+            ),
+            WASMError::MissingImport { module, name } if module.starts_with("wasi") => write!(
+                f,
+                "plugin imports '{}.{}', enable WASI for it with a [plugins.wasi] table",
+                module, name
+            ),
+            WASMError::MissingImport { module, name } => write!(
+                f,
+                "plugin imports '{}.{}', which is not provided by host ABI version {}",
+                module, name, HOST_ABI_VERSION
+            ),
+            WASMError::InstantiationFailed(err) => write!(f, "failed to construct module instance: {}", err),
+            WASMError::InvalidExport { name, err } => write!(f, "invalid '{}' export: {}", name, err),
+            WASMError::CallFailed { function, err } => {
+                write!(f, "call to '{}' function failed with error: {}", function, err)
+            }
+            WASMError::MemoryAccessFailed(err) => write!(f, "failed to access module memory: {}", err),
+        }
     }
 }
 
-impl From<Vec<u8>> for WasmModule {
-    fn from(value: Vec<u8>) -> Self {
-        let compiler = Singlepass::default();
-        let mut store = Store::new(compiler);
-        let module = Module::new(&store, value).unwrap_or_else(|err| {
-            error!(target: "WASM","Failed to compile WASM module: {}", err);
-            std::process::exit(1)
-        });
-        let import_object = create_imports(&mut store);
+// Shared by all stores, so a module compiled once can be instantiated by every plugin instance
+static ENGINE: LazyLock<Engine> = LazyLock::new(|| Singlepass::default().into());
 
-        let instance = Instance::new(&mut store, &module, &import_object).unwrap_or_else(|err| {
-            error!(target: "WASM","Failed to construct module Instance: {}", err);
-            std::process::exit(1)
-        });
+// Compiled module, along with the files its bundle ships besides it
+#[derive(Clone)]
+pub(crate) struct CompiledPlugin {
+    pub(crate) module: Module,
+    // Loaded from a component, through the `plugin` world of wit/plugin.wit
+    pub(crate) component: bool,
+    assets: Arc<HashMap<String, Vec<u8>>>,
+    // From the bundle manifest, takes precedence over the schema in metadata
+    settings_schema: Option<SettingsSchema>,
+}
 
-        let metadata = module
-            .custom_sections("metadata")
-            .next()
-            .ok_or(WASMError::NoCustomSection)
-            .map(Vec::from)
-            .map(String::from_utf8)
-            .and_then(|val| val.or(Err(WASMError::InvalidCustomSection)))
-            .and_then(|str| {
-                serde_json::from_str::<Metadata>(&str).or(Err(WASMError::InvalidCustomSection))
-            })
-            .unwrap_or_else(|err| {
-                error!(target: "WASM","Failed to read 'metadata' custom section: {:?}", err);
-                std::process::exit(1);
-            });
+// Compiled modules by plugin name, shared by the instances of a plugin
+pub(crate) type CompiledPlugins = HashMap<String, CompiledPlugin>;
+
+pub(crate) fn compile(value: &[u8]) -> Result<CompiledPlugin, WASMError> {
+    let component = component::is_component(value);
+    let value = if component { component::core_module(value)? } else { value };
+    let module = Module::new(&*ENGINE, value).map_err(|err| WASMError::CompileFailed(err.to_string()))?;
+
+    Ok(CompiledPlugin {
+        module,
+        component,
+        assets: Arc::new(HashMap::new()),
+        settings_schema: None,
+    })
+}
+
+pub(crate) fn compile_bundle(bundle: Bundle) -> Result<CompiledPlugin, WASMError> {
+    let compiled = compile(&bundle.module)?;
+
+    Ok(CompiledPlugin {
+        assets: Arc::new(bundle.assets),
+        settings_schema: bundle.manifest.settings_schema,
+        ..compiled
+    })
+}
+
+// Read and compile a `.fwled` bundle or a plain `.wasm` module
+pub(crate) fn compile_file(path: &Path) -> Result<CompiledPlugin, WASMError> {
+    if path.extension().is_some_and(|extension| extension == BUNDLE_EXTENSION) {
+        let bundle = read_bundle(path).map_err(|err| WASMError::InvalidBundle(err.to_string()))?;
+        return compile_bundle(bundle);
+    }
+
+    let value = fs::read(path).map_err(|err| WASMError::ReadFailed(err.to_string()))?;
+    compile(&value)
+}
+
+// Compile the file a plugin name resolves to in the search path
+pub(crate) fn compile_plugin(paths: &PluginPaths, name: &str) -> Result<CompiledPlugin, WASMError> {
+    let path = paths.resolve(name).ok_or(WASMError::NotFound(name.to_string()))?;
+    compile_file(&path)
+}
+
+pub(crate) fn read_metadata(module: &Module) -> Result<Metadata, WASMError> {
+    module
+        .custom_sections("metadata")
+        .next()
+        .ok_or(WASMError::NoCustomSection)
+        .map(Vec::from)
+        .map(String::from_utf8)
+        .and_then(|val| val.map_err(|err| WASMError::InvalidCustomSection(err.to_string())))
+        .and_then(|str| {
+            serde_json::from_str::<Metadata>(&str)
+                .map_err(|err| WASMError::InvalidCustomSection(err.to_string()))
+        })
+}
+
+impl WasmModule {
+    #[cfg(test)]
+    pub(crate) fn new(value: Vec<u8>, plugin_conf: &PluginConf) -> Self {
+        compile(&value)
+            .and_then(|compiled| Self::instantiate(&compiled, plugin_conf, crate::stat_provider::system_stats()))
+            .unwrap_or_else(|err| panic!("Failed to load '{}' module: {}", plugin_conf.name, err))
+    }
+
+    // Create a plugin instance with its own store, settings and state from a compiled module
+    // Stat imports read from `stats`
+    pub(crate) fn instantiate(
+        compiled: &CompiledPlugin,
+        plugin_conf: &PluginConf,
+        stats: Arc<dyn StatProvider>,
+    ) -> Result<Self, WASMError> {
+        let module = &compiled.module;
+        let mut store = Store::new(ENGINE.clone());
+
+        let metadata = read_metadata(module)?;
+        check_abi_version(&metadata)?;
+        check_size(&metadata)?;
+
+        let settings_schema = compiled.settings_schema.as_ref().or(metadata.settings_schema.as_ref());
+        let settings = match settings_schema {
+            Some(schema) => validate_settings(schema, &plugin_conf.settings)
+                .map_err(WASMError::InvalidSettings)?,
+            None => plugin_conf.settings.clone(),
+        };
+
+        let granted = grant_permissions(metadata.permissions.as_deref(), &plugin_conf.permissions)
+            .map_err(WASMError::UnapprovedPermissions)?;
+
+        let wasi_ctx = plugin_conf
+            .wasi
+            .as_ref()
+            .map(|wasi_conf| WasiCtx::new(plugin_conf.id(), wasi_conf, &granted))
+            .transpose()?;
+        let has_wasi = wasi_ctx.is_some();
+        let env = FunctionEnv::new(
+            &mut store,
+            PluginEnv {
+                name: plugin_conf.id().to_string(),
+                memory: None,
+                wasi: wasi_ctx,
+                settings,
+                assets: Arc::clone(&compiled.assets),
+                stats: Arc::clone(&stats),
+                realloc: None,
+            },
+        );
+
+        let mut import_object = match compiled.component {
+            true => component::create_imports(&mut store, &env, &granted),
+            false => create_imports(&mut store, &env, &granted),
+        };
+        if has_wasi {
+            wasi::register_imports(&mut import_object, &mut store, &env);
+        }
+        check_imports(module, &import_object)?;
+
+        let instance = Instance::new(&mut store, module, &import_object)
+            .map_err(|err| WASMError::InstantiationFailed(err.to_string()))?;
+
+        if let Ok(memory) = instance.exports.get_memory("memory") {
+            env.as_mut(&mut store).memory = Some(memory.clone());
+        }
+
+        // WASI reactor modules expect `_initialize` to run before any other export
+        let initialize: Option<TypedFunction<(), ()>> =
+            get_optional_function(&instance, &store, "_initialize")?;
+        if let Some(initialize) = initialize {
+            initialize
+                .call(&mut store)
+                .map_err(|err| call_failed("_initialize", err))?;
+        }
+
+        let tick = get_optional_function(&instance, &store, "tick")?;
+        let shutdown = get_optional_function(&instance, &store, "shutdown")?;
+        let (save_state, load_state, component) = match compiled.component {
+            true => {
+                let component = ComponentExports::new(&instance, &store)?;
+                env.as_mut(&mut store).realloc = component.realloc.clone();
+                (None, None, Some(component))
+            }
+            false => (
+                get_optional_function(&instance, &store, "save_state")?,
+                get_optional_function(&instance, &store, "load_state")?,
+                None,
+            ),
+        };
+
+        info!(
+            target: "WASM",
+            "Loaded '{}' module, version {} (ABI version {})",
+            metadata.name,
+            metadata.version.as_deref().unwrap_or("unknown"),
+            metadata.abi_version
+        );
+        debug!(
+            target: "WASM",
+            "'{}' module: author {:?}, description {:?}, permissions {:?}",
+            metadata.name, metadata.author, metadata.description, metadata.permissions
+        );
 
-        Self {
+        let mut wasm_module = Self {
             instance,
             store,
             metadata,
+            tick,
+            shutdown,
+            save_state,
+            load_state,
+            component,
+            last_tick: None,
+            last_frame: None,
+            stats,
+        };
+        wasm_module.init(&env)?;
+        Ok(wasm_module)
+    }
+
+    // Pass plugin settings serialized as JSON to the optional `init` export
+    fn init(&mut self, env: &FunctionEnv<PluginEnv>) -> Result<(), WASMError> {
+        // Components read their settings through the `get-setting` import
+        if let Some(component) = &self.component {
+            return component.init(&mut self.store);
+        }
+
+        let init: Option<TypedFunction<(i32, i32), ()>> =
+            get_optional_function(&self.instance, &self.store, "init")?;
+        let Some(init) = init else {
+            return Ok(());
+        };
+
+        let config = serde_json::to_vec(&env.as_ref(&self.store).settings)
+            .expect("TOML table should serialize to JSON");
+        let buffer = self.alloc_guest_buffer(config.len())?;
+        let (ptr, len) = match &buffer {
+            Some(buffer) => {
+                self.write_guest_memory(buffer.ptr(), &config)?;
+                (buffer.ptr(), config.len() as i32)
+            }
+            None => {
+                warn!(target: "WASM", "'{}' module exports 'init' but no allocator, settings won't be passed", self.metadata.name);
+                (0, 0)
+            }
+        };
+
+        init.call(&mut self.store, ptr, len)
+            .map_err(|err| call_failed("init", err))?;
+
+        if let Some(buffer) = buffer {
+            self.free_guest_buffer(buffer);
         }
+        Ok(())
     }
-}
 
-impl Picture for WasmModule {
-    fn draw(&mut self) -> Matrix {
-        let draw_function: TypedFunction<(), WasmPtr<u8>> = self
-            .instance
-            .exports
-            .get_typed_function(&mut self.store, "draw")
-            .unwrap_or_else(|err| {
-                error!(target: "WASM", "Failed to get 'draw' function at '{}' module, with error: '{}' ", self.metadata.name, err);
-                std::process::exit(1);
-            });
+    // Reserve `len` bytes in plugin memory. Plugins may export `alloc(len) -> ptr`,
+    // AssemblyScript ones built with `--exportRuntime` are handled through `__new`.
+    fn alloc_guest_buffer(&mut self, len: usize) -> Result<Option<GuestBuffer>, WASMError> {
+        let alloc: Option<TypedFunction<i32, i32>> =
+            get_optional_function(&self.instance, &self.store, "alloc")?;
+        if let Some(alloc) = alloc {
+            let ptr = alloc
+                .call(&mut self.store, len as i32)
+                .map_err(|err| call_failed("alloc", err))?;
+            return Ok(Some(GuestBuffer::Alloc(ptr)));
+        }
 
-        let picture_ptr = draw_function
-            .call_sys(&mut self.store)
-            .unwrap_or_else(|err| {
-                error!(target: "WASM", "Call to 'draw' function failed at '{}' module, with error: {}", self.metadata.name, err);
-                std::process::exit(1);
-            });
+        let new: Option<TypedFunction<(i32, i32), i32>> =
+            get_optional_function(&self.instance, &self.store, "__new")?;
+        let pin: Option<TypedFunction<i32, i32>> =
+            get_optional_function(&self.instance, &self.store, "__pin")?;
+        match (new, pin) {
+            (Some(new), Some(pin)) => {
+                let ptr = new
+                    .call(&mut self.store, len as i32, AS_ARRAY_BUFFER_ID)
+                    .map_err(|err| call_failed("__new", err))?;
+                let ptr = pin
+                    .call(&mut self.store, ptr)
+                    .map_err(|err| call_failed("__pin", err))?;
+                Ok(Some(GuestBuffer::AssemblyScript(ptr)))
+            }
+            _ => Ok(None),
+        }
+    }
 
-        let view = self
+    // Buffers from `alloc` are owned by the plugin, pinned AssemblyScript ones go back to its GC
+    fn free_guest_buffer(&mut self, buffer: GuestBuffer) {
+        if let GuestBuffer::AssemblyScript(ptr) = buffer {
+            let unpin: Result<Option<TypedFunction<i32, ()>>, WASMError> =
+                get_optional_function(&self.instance, &self.store, "__unpin");
+            if let Ok(Some(unpin)) = unpin {
+                if let Err(err) = unpin.call(&mut self.store, ptr) {
+                    warn!(target: "WASM", "Call to '__unpin' function failed at '{}' module, with error: {}", self.metadata.name, err);
+                }
+            }
+        }
+    }
+
+    fn write_guest_memory(&mut self, ptr: i32, data: &[u8]) -> Result<(), WASMError> {
+        let memory = self
             .instance
             .exports
-            .get_memory("memory").unwrap_or_else(|err| {
-            error!(target: "WASM", "Could not retrieve memory at key 'memory' at '{}' module, with error: {}",self.metadata.name, err);
+            .get_memory("memory")
+            .map_err(|err| WASMError::MemoryAccessFailed(err.to_string()))?;
+        memory
+            .view(&self.store)
+            .write(ptr as u32 as u64, data)
+            .map_err(|err| WASMError::MemoryAccessFailed(err.to_string()))
+    }
+
+    // Draw as if called at `now`, which drives `tick` and `refresh_ms`
+    pub(crate) fn draw_at(&mut self, now: Instant) -> Matrix {
+        self.try_draw_at(now).unwrap_or_else(|err| {
+            error!(target: "WASM", "Failed to draw '{}' module: {}", self.metadata.name, err);
             std::process::exit(1);
         })
-            .view(&self.store);
+    }
 
-        let picture_deref_ptr = picture_ptr.deref(&view);
+    pub(crate) fn try_draw_at(&mut self, now: Instant) -> Result<Matrix, WASMError> {
+        self.stats.next_frame();
+
+        // Plugins declaring `refresh_ms` keep showing their last frame until it's due
+        if let (Some(refresh_ms), Some((drawn_at, frame))) =
+            (self.metadata.refresh_ms, &self.last_frame)
+        {
+            if now.duration_since(*drawn_at) < Duration::from_millis(refresh_ms) {
+                return Ok(frame.clone());
+            }
+        }
+
+        self.tick(now)?;
 
         let payload_length = self.metadata.width * self.metadata.height;
+        let picture = match &self.component {
+            Some(component) => {
+                let memory = self.memory()?.clone();
+                component.draw(&mut self.store, &memory, payload_length)?
+            }
+            None => self.draw_picture(payload_length)?,
+        };
+
+        // Map picture to a 9x39 matrix
+        let matrix = Matrix::from_picture(picture, self.metadata.width, self.metadata.height);
+        self.last_frame = Some((now, matrix.clone()));
+        Ok(matrix)
+    }
+
+    fn memory(&self) -> Result<&Memory, WASMError> {
+        self.instance
+            .exports
+            .get_memory("memory")
+            .map_err(|err| WASMError::InvalidExport {
+                name: "memory".to_string(),
+                err: err.to_string(),
+            })
+    }
+
+    // Core module `draw` returns a pointer to `len` bytes of picture data
+    fn draw_picture(&mut self, payload_length: usize) -> Result<Vec<u8>, WASMError> {
+        let draw_function: TypedFunction<(), WasmPtr<u8>> = self
+            .instance
+            .exports
+            .get_typed_function(&self.store, "draw")
+            .map_err(|err| WASMError::InvalidExport {
+                name: "draw".to_string(),
+                err: err.to_string(),
+            })?;
+
+        let picture_ptr = draw_function
+            .call(&mut self.store)
+            .map_err(|err| call_failed("draw", err))?;
+
+        let view = self.memory()?.view(&self.store);
+
+        let picture_deref_ptr = picture_ptr.deref(&view);
 
         // Picture data has contiguous memory allocation, starting at pointer offset and ending at offset + payload len
-        let picture = view
-            .copy_range_to_vec(
-                picture_deref_ptr.offset()..picture_deref_ptr.offset() + payload_length as u64,
-            )
-            .unwrap_or_else(|_| {
-                error!(target: "WASM", "Failed to copy picture data at '{}' module", self.metadata.name);
-                std::process::exit(1)
+        view.copy_range_to_vec(
+            picture_deref_ptr.offset()..picture_deref_ptr.offset() + payload_length as u64,
+        )
+        .map_err(|err| WASMError::MemoryAccessFailed(err.to_string()))
+    }
+
+    fn tick(&mut self, now: Instant) -> Result<(), WASMError> {
+        let dt_ms = self
+            .last_tick
+            .map(|last_tick| now.duration_since(last_tick).as_millis() as i32)
+            .unwrap_or(0);
+        self.last_tick = Some(now);
+
+        if let Some(tick) = &self.tick {
+            tick.call(&mut self.store, dt_ms)
+                .map_err(|err| call_failed("tick", err))?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn check_abi_version(metadata: &Metadata) -> Result<(), WASMError> {
+    if (MIN_ABI_VERSION..=HOST_ABI_VERSION).contains(&metadata.abi_version) {
+        Ok(())
+    } else {
+        Err(WASMError::IncompatibleAbiVersion(metadata.abi_version))
+    }
+}
+
+// Frames are copied into the matrix as they are, they have to fit in it
+pub(crate) fn check_size(metadata: &Metadata) -> Result<(), WASMError> {
+    if (1..=MATRIX_WIDTH).contains(&metadata.width) && (1..=MATRIX_HEIGHT).contains(&metadata.height) {
+        Ok(())
+    } else {
+        Err(WASMError::InvalidSize {
+            width: metadata.width,
+            height: metadata.height,
+        })
+    }
+}
+
+// Report imports the host doesn't provide, before instantiation fails with a less helpful error
+fn check_imports(module: &Module, imports: &Imports) -> Result<(), WASMError> {
+    match module
+        .imports()
+        .find(|import| !imports.exists(import.module(), import.name()))
+    {
+        Some(import) => Err(WASMError::MissingImport {
+            module: import.module().to_string(),
+            name: import.name().to_string(),
+        }),
+        None => Ok(()),
+    }
+}
+
+// Look up an export the plugin may leave out. Exports with an unexpected signature are an error.
+pub(crate) fn get_optional_function<Args, Rets>(
+    instance: &Instance,
+    store: &Store,
+    name: &str,
+) -> Result<Option<TypedFunction<Args, Rets>>, WASMError>
+where
+    Args: WasmTypeList,
+    Rets: WasmTypeList,
+{
+    match instance.exports.get_typed_function(store, name) {
+        Ok(function) => Ok(Some(function)),
+        Err(ExportError::Missing(_)) => Ok(None),
+        Err(err) => Err(WASMError::InvalidExport {
+            name: name.to_string(),
+            err: err.to_string(),
+        }),
+    }
+}
+
+pub(crate) fn call_failed(function: &str, err: RuntimeError) -> WASMError {
+    WASMError::CallFailed {
+        function: function.to_string(),
+        err: err.to_string(),
+    }
+}
+
+impl Picture for WasmModule {
+    fn draw(&mut self) -> Matrix {
+        self.draw_at(Instant::now())
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(shutdown) = &self.shutdown {
+            if let Err(err) = shutdown.call(&mut self.store) {
+                error!(target: "WASM", "Call to 'shutdown' function failed at '{}' module, with error: {}", self.metadata.name, err);
+            }
+        }
+    }
+
+    // Copy the blob returned by the optional `save_state` export out of plugin memory
+    fn save_state(&mut self) -> Option<Vec<u8>> {
+        if let Some(component) = &self.component {
+            let memory = self.memory().ok()?.clone();
+            return component.save_state(&mut self.store, &memory).unwrap_or_else(|err| {
+                error!(target: "WASM", "Failed to save state of '{}' module: {}", self.metadata.name, err);
+                None
             });
+        }
 
-        // Map picture to a 9x39 matrix
-        Matrix::from_picture(picture, self.metadata.width, self.metadata.height)
+        let (ptr, len) = match self.save_state.as_ref()?.call(&mut self.store) {
+            Ok(packed) => ((packed >> 32) as u32, packed as u32),
+            Err(err) => {
+                error!(target: "WASM", "Call to 'save_state' function failed at '{}' module, with error: {}", self.metadata.name, err);
+                return None;
+            }
+        };
+        if len as usize > MAX_STATE_SIZE {
+            warn!(target: "WASM", "'{}' module returned state of {} bytes, the limit is {} bytes", self.metadata.name, len, MAX_STATE_SIZE);
+            return None;
+        }
+
+        let memory = self.instance.exports.get_memory("memory").ok()?;
+        let mut state = vec![0u8; len as usize];
+        match memory.view(&self.store).read(ptr as u64, &mut state) {
+            Ok(()) => Some(state),
+            Err(err) => {
+                error!(target: "WASM", "Failed to read state at '{}' module, with error: {}", self.metadata.name, err);
+                None
+            }
+        }
+    }
+
+    // Pass a blob from a previous `save_state` call to the optional `load_state` export
+    fn load_state(&mut self, state: &[u8]) {
+        if let Some(component) = &self.component {
+            let result = self
+                .memory()
+                .cloned()
+                .and_then(|memory| component.load_state(&mut self.store, &memory, state));
+            if let Err(err) = result {
+                error!(target: "WASM", "Failed to restore state of '{}' module: {}", self.metadata.name, err);
+            }
+            return;
+        }
+
+        let Some(load_state) = self.load_state.clone() else {
+            return;
+        };
+
+        let result = self.alloc_guest_buffer(state.len()).and_then(|buffer| {
+            let Some(buffer) = buffer else {
+                warn!(target: "WASM", "'{}' module exports 'load_state' but no allocator, state won't be restored", self.metadata.name);
+                return Ok(());
+            };
+            self.write_guest_memory(buffer.ptr(), state)?;
+            load_state
+                .call(&mut self.store, buffer.ptr(), state.len() as i32)
+                .map_err(|err| call_failed("load_state", err))?;
+            self.free_guest_buffer(buffer);
+            Ok(())
+        });
+
+        if let Err(err) = result {
+            error!(target: "WASM", "Failed to restore state of '{}' module: {}", self.metadata.name, err);
+        }
     }
 }
 
@@ -142,29 +697,305 @@ pub struct Metadata {
     pub height: usize,
     pub width: usize,
     pub name: String,
+    // Plugins built before ABI versioning was introduced target version 1
+    #[serde(default = "default_abi_version")]
+    pub abi_version: u32,
+    pub version: Option<String>,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    // Minimum interval between `draw` calls
+    pub refresh_ms: Option<u64>,
+    // Missing for modules built before capabilities were introduced, see `LEGACY_CAPABILITIES`
+    pub permissions: Option<Vec<Capability>>,
+    pub settings_schema: Option<SettingsSchema>,
 }
 
-fn create_imports(store: &mut Store) -> Imports {
-    let get_global_cpu_usage = || SYSTEM_STAT_MONITOR.lock().unwrap().get_global_cpu_usage();
-    let get_memory_usage = || SYSTEM_STAT_MONITOR.lock().unwrap().get_memory_usage();
+fn default_abi_version() -> u32 {
+    1
+}
 
-    imports! {
+fn create_imports(
+    store: &mut Store,
+    env: &FunctionEnv<PluginEnv>,
+    granted: &HashSet<Capability>,
+) -> Imports {
+    let mut imports = imports! {
         "env" => {
-            "abort" => Function::new_typed(store, abort_polyfill),
-            "get_battery_state_of_charge" => Function::new_typed(store, SystemStatMonitor::get_battery_state_of_charge),
-            "get_global_cpu_usage" => Function::new_typed(store, get_global_cpu_usage),
-            "get_memory_usage" => Function::new_typed(store, get_memory_usage),
-            "get_epoch_time" => Function::new_typed(store, SystemStatMonitor::get_epoch_time),
+            "abort" => Function::new_typed_with_env(store, env, plugin_log::abort),
+            "log" => Function::new_typed_with_env(store, env, plugin_log::log_utf8),
+            "log_utf16" => Function::new_typed_with_env(store, env, plugin_log::log_utf16),
+            "get_setting_i64" => Function::new_typed_with_env(store, env, get_setting_i64),
+            "get_setting_f64" => Function::new_typed_with_env(store, env, get_setting_f64),
+            "get_setting_string" => Function::new_typed_with_env(store, env, get_setting_string),
+            "get_asset" => Function::new_typed_with_env(store, env, bundle::get_asset),
+            "kv_get_i64" => Function::new_typed_with_env(store, env, kv_bus::kv_get_i64),
+            "kv_get_f64" => Function::new_typed_with_env(store, env, kv_bus::kv_get_f64),
+            "kv_get_string" => Function::new_typed_with_env(store, env, kv_bus::kv_get_string),
+            "kv_set_i64" => Function::new_typed_with_env(store, env, kv_bus::kv_set_i64),
+            "kv_set_f64" => Function::new_typed_with_env(store, env, kv_bus::kv_set_f64),
+            "kv_set_string" => Function::new_typed_with_env(store, env, kv_bus::kv_set_string),
+        }
+    };
+
+    let stat_functions = [
+        ("get_battery_state_of_charge", Function::new_typed_with_env(store, env, get_battery_state_of_charge)),
+        ("get_global_cpu_usage", Function::new_typed_with_env(store, env, get_global_cpu_usage)),
+        ("get_memory_usage", Function::new_typed_with_env(store, env, get_memory_usage)),
+        ("get_epoch_time", Function::new_typed_with_env(store, env, get_epoch_time)),
+    ];
+    // Data sources are only linked when the plugin was granted the matching capability
+    for (name, function) in stat_functions {
+        if required_capability("env", name).is_some_and(|capability| granted.contains(&capability)) {
+            imports.define("env", name, function);
         }
     }
+    imports
 }
 
-fn abort_polyfill(msg: i32, file: i32, line: i32, col: i32) {
-    warn!(
-        "AssemblyScript abort called at {}:{} (msg_ptr={}, file_ptr={})",
-        line, col, msg, file
+// Types of every import the host can provide to core modules and components, as if all capabilities were granted
+// and WASI enabled
+pub(crate) fn host_import_types() -> HashMap<(String, String), ExternType> {
+    let mut store = Store::new(ENGINE.clone());
+    let env = FunctionEnv::new(
+        &mut store,
+        PluginEnv {
+            name: String::new(),
+            memory: None,
+            wasi: None,
+            settings: toml::Table::new(),
+            assets: Default::default(),
+            stats: crate::stat_provider::system_stats(),
+            realloc: None,
+        },
     );
+    let all_capabilities = HashSet::from([
+        Capability::Battery,
+        Capability::Cpu,
+        Capability::Memory,
+        Capability::Time,
+        Capability::Fs,
+    ]);
+
+    let mut imports = create_imports(&mut store, &env, &all_capabilities);
+    wasi::register_imports(&mut imports, &mut store, &env);
+    for ((module, name), function) in &component::create_imports(&mut store, &env, &all_capabilities) {
+        imports.define(&module, &name, function);
+    }
+    imports
+        .iter()
+        .map(|(module, name, export)| ((module.to_string(), name.to_string()), export.ty(&store)))
+        .collect()
 }
 
-static SYSTEM_STAT_MONITOR: LazyLock<Mutex<SystemStatMonitor>> =
-    LazyLock::new(|| Mutex::new(SystemStatMonitor::new()));
+pub(crate) fn get_battery_state_of_charge(env: FunctionEnvMut<PluginEnv>) -> f32 {
+    env.data().stats.battery_state_of_charge()
+}
+
+pub(crate) fn get_global_cpu_usage(env: FunctionEnvMut<PluginEnv>) -> f32 {
+    env.data().stats.global_cpu_usage()
+}
+
+pub(crate) fn get_memory_usage(env: FunctionEnvMut<PluginEnv>) -> f32 {
+    env.data().stats.memory_usage()
+}
+
+pub(crate) fn get_epoch_time(env: FunctionEnvMut<PluginEnv>) -> u64 {
+    env.data().stats.epoch_time()
+}
+
+#[cfg(test)]
+mod wasm_module_tests {
+    use std::collections::HashSet;
+
+    use wasmer::{imports, FunctionEnv, Module, Store};
+
+    use crate::config::PluginConf;
+    use crate::permissions::Capability;
+    use crate::picture::Picture;
+    use crate::stat_provider::system_stats;
+    use crate::wasm_module::{
+        check_abi_version, check_imports, compile, create_imports, Metadata, PluginEnv,
+        WASMError, WasmModule,
+    };
+
+    // Lights the first pixel with the first byte of the settings JSON passed to `init`,
+    // and the second one with the number of `tick` calls
+    const LIFECYCLE_PLUGIN: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $ticks (mut i32) (i32.const 0))
+        (@custom "metadata" "{\"name\":\"lifecycle\",\"width\":2,\"height\":1}")
+        (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+        (func (export "init") (param $ptr i32) (param $len i32)
+            (i32.store8 (i32.const 0) (i32.load8_u (local.get $ptr))))
+        (func (export "tick") (param $dt_ms i32)
+            (global.set $ticks (i32.add (global.get $ticks) (i32.const 1))))
+        (func (export "draw") (result i32)
+            (i32.store8 (i32.const 1) (global.get $ticks))
+            (i32.const 0)))"#;
+
+    const DRAW_ONLY_PLUGIN: &str = r#"(module
+        (memory (export "memory") 1)
+        (@custom "metadata" "{\"name\":\"draw-only\",\"width\":1,\"height\":1}")
+        (data (i32.const 0) "\07")
+        (func (export "draw") (result i32) (i32.const 0)))"#;
+
+    const TALL_PLUGIN: &str = r#"(module
+        (memory (export "memory") 1)
+        (@custom "metadata" "{\"name\":\"tall\",\"width\":1,\"height\":40}")
+        (func (export "draw") (result i32) (i32.const 0)))"#;
+
+    // Keeps a single byte counter at address 0, incremented on every draw
+    const STATEFUL_PLUGIN: &str = r#"(module
+        (memory (export "memory") 1)
+        (@custom "metadata" "{\"name\":\"stateful\",\"width\":1,\"height\":1}")
+        (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+        (func (export "save_state") (result i64) (i64.const 1))
+        (func (export "load_state") (param $ptr i32) (param $len i32)
+            (i32.store8 (i32.const 0) (i32.load8_u (local.get $ptr))))
+        (func (export "draw") (result i32)
+            (i32.store8 (i32.const 0) (i32.add (i32.load8_u (i32.const 0)) (i32.const 1)))
+            (i32.const 0)))"#;
+
+    fn plugin_conf(name: &str) -> PluginConf {
+        toml::from_str(&format!(
+            "name = '{}'\npos_x = 0\npos_y = 0\n[settings]\nlit = 255",
+            name
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn calls_init_with_settings_and_tick_before_draw() {
+        let mut module = WasmModule::new(
+            LIFECYCLE_PLUGIN.as_bytes().to_vec(),
+            &plugin_conf("lifecycle"),
+        );
+
+        let first_frame = module.draw();
+        let second_frame = module.draw();
+
+        assert_eq!(first_frame.get_el(0, 0), b'{');
+        assert_eq!(first_frame.get_el(0, 1), 1);
+        assert_eq!(second_frame.get_el(0, 1), 2);
+    }
+
+    #[test]
+    fn loads_draw_only_plugin() {
+        let mut module = WasmModule::new(
+            DRAW_ONLY_PLUGIN.as_bytes().to_vec(),
+            &plugin_conf("draw-only"),
+        );
+
+        assert_eq!(module.draw().get_el(0, 0), 7);
+        module.shutdown();
+    }
+
+    #[test]
+    fn rejects_incompatible_abi_version() {
+        let legacy: Metadata =
+            serde_json::from_str(r#"{"name":"a","width":1,"height":1}"#).unwrap();
+        let future: Metadata =
+            serde_json::from_str(r#"{"name":"a","width":1,"height":1,"abi_version":99}"#).unwrap();
+
+        assert_eq!(legacy.abi_version, 1);
+        assert_eq!(check_abi_version(&legacy), Ok(()));
+        assert_eq!(
+            check_abi_version(&future),
+            Err(WASMError::IncompatibleAbiVersion(99))
+        );
+    }
+
+    #[test]
+    fn rejects_plugin_larger_than_matrix() {
+        let compiled = compile(TALL_PLUGIN.as_bytes()).unwrap();
+
+        let err = WasmModule::instantiate(&compiled, &plugin_conf("tall"), system_stats()).err();
+
+        assert_eq!(err, Some(WASMError::InvalidSize { width: 1, height: 40 }));
+    }
+
+    #[test]
+    fn reports_missing_host_import() {
+        let store = Store::default();
+        let module = Module::new(
+            &store,
+            r#"(module (import "env" "get_gpu_usage" (func (result f32))))"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            check_imports(&module, &imports! {}),
+            Err(WASMError::MissingImport {
+                module: "env".to_string(),
+                name: "get_gpu_usage".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn links_only_granted_imports() {
+        let mut store = Store::default();
+        let module = Module::new(
+            &store,
+            r#"(module (import "env" "get_epoch_time" (func (result i64))))"#,
+        )
+        .unwrap();
+        let env = FunctionEnv::new(
+            &mut store,
+            PluginEnv {
+                name: "clock".to_string(),
+                memory: None,
+                wasi: None,
+                settings: toml::Table::new(),
+                assets: Default::default(),
+                stats: system_stats(),
+                realloc: None,
+            },
+        );
+
+        let denied = create_imports(&mut store, &env, &HashSet::new());
+        let granted = create_imports(&mut store, &env, &HashSet::from([Capability::Time]));
+
+        let err = check_imports(&module, &denied).unwrap_err();
+        assert!(err.to_string().contains("'time' permission"));
+        assert_eq!(check_imports(&module, &granted), Ok(()));
+    }
+
+    #[test]
+    fn restores_saved_state() {
+        let mut previous = WasmModule::new(
+            STATEFUL_PLUGIN.as_bytes().to_vec(),
+            &plugin_conf("stateful"),
+        );
+        previous.draw();
+        previous.draw();
+        let state = previous.save_state().unwrap();
+
+        let mut reloaded = WasmModule::new(
+            STATEFUL_PLUGIN.as_bytes().to_vec(),
+            &plugin_conf("stateful"),
+        );
+        reloaded.load_state(&state);
+
+        assert_eq!(state, vec![2]);
+        assert_eq!(reloaded.draw().get_el(0, 0), 3);
+    }
+
+    #[test]
+    fn instances_of_shared_module_keep_separate_state() {
+        let module = compile(STATEFUL_PLUGIN.as_bytes()).unwrap();
+        let mut first_conf = plugin_conf("stateful");
+        first_conf.id = Some("first".to_string());
+        let second_conf = plugin_conf("stateful");
+
+        let mut first = WasmModule::instantiate(&module, &first_conf, system_stats()).unwrap();
+        let mut second = WasmModule::instantiate(&module, &second_conf, system_stats()).unwrap();
+        first.draw();
+        first.draw();
+
+        assert_eq!(first_conf.id(), "first");
+        assert_eq!(second_conf.id(), "stateful");
+        assert_eq!(first.draw().get_el(0, 0), 3);
+        assert_eq!(second.draw().get_el(0, 0), 1);
+    }
+}